@@ -0,0 +1,5 @@
+INSERT INTO api_token
+    (user_id, name, token_hash, scopes, expire_time)
+VALUES
+    ($1, $2, $3, $4, NOW() + MAKE_INTERVAL(secs => $5::BIGINT))
+RETURNING id, user_id, name, scopes, create_time, expire_time, last_used;
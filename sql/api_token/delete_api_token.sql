@@ -0,0 +1 @@
+DELETE FROM api_token WHERE user_id = $1 AND id = $2 RETURNING id, user_id, name, scopes, create_time, expire_time, last_used;
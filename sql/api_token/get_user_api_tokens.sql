@@ -0,0 +1 @@
+SELECT id, user_id, name, scopes, create_time, expire_time, last_used FROM api_token WHERE user_id = $1 ORDER BY create_time DESC;
@@ -0,0 +1,5 @@
+UPDATE api_token
+    SET last_used = NOW()
+    WHERE token_hash = $1
+    AND (expire_time IS NULL OR expire_time > NOW())
+RETURNING id, user_id, name, scopes, create_time, expire_time, last_used;
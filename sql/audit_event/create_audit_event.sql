@@ -0,0 +1,5 @@
+INSERT INTO audit_event
+    (actor_id, impersonator_id, action, target_type, target_id, poll_id, before_state, after_state, ip_address)
+VALUES
+    ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+RETURNING *;
@@ -0,0 +1 @@
+SELECT * FROM audit_event ORDER BY event_time DESC, id DESC LIMIT $1 OFFSET $2;
@@ -0,0 +1 @@
+SELECT * FROM audit_event WHERE poll_id = $1 ORDER BY event_time DESC, id DESC LIMIT $2 OFFSET $3;
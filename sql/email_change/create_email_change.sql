@@ -0,0 +1,5 @@
+INSERT INTO email_change
+    (id, user_id, new_email)
+VALUES
+    ($1, $2, $3)
+RETURNING id, user_id, new_email;
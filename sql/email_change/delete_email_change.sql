@@ -0,0 +1 @@
+DELETE FROM email_change WHERE id = $1;
@@ -0,0 +1 @@
+DELETE FROM email_change WHERE user_id = $1;
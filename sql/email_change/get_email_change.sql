@@ -0,0 +1 @@
+SELECT id, user_id, new_email FROM email_change WHERE id = $1 AND EXTRACT(EPOCH FROM NOW() - create_time) < $2::BIGINT;
@@ -0,0 +1 @@
+DELETE FROM email_change WHERE EXTRACT(EPOCH FROM NOW() - create_time) >= $1::BIGINT;
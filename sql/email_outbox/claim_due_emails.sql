@@ -0,0 +1,9 @@
+UPDATE email_outbox SET next_attempt_time = NOW() + MAKE_INTERVAL(secs => $2::BIGINT)
+    WHERE id IN (
+        SELECT id FROM email_outbox
+            WHERE status = 'pending' AND next_attempt_time <= NOW()
+            ORDER BY next_attempt_time
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+    )
+RETURNING id, to_address, subject, html, text, status, attempts, last_error, create_time, next_attempt_time, last_attempt_time;
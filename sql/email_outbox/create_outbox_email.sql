@@ -0,0 +1,5 @@
+INSERT INTO email_outbox
+    (to_address, subject, html, text)
+VALUES
+    ($1, $2, $3, $4)
+RETURNING id, to_address, subject, html, text, status, attempts, last_error, create_time, next_attempt_time, last_attempt_time;
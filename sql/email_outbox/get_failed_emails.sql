@@ -0,0 +1 @@
+SELECT id, to_address, subject, html, text, status, attempts, last_error, create_time, next_attempt_time, last_attempt_time FROM email_outbox WHERE status = 'failed' ORDER BY last_attempt_time DESC LIMIT $1 OFFSET $2;
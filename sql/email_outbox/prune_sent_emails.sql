@@ -0,0 +1,3 @@
+DELETE FROM email_outbox
+    WHERE (status = 'sent' AND EXTRACT(EPOCH FROM NOW() - sent_time) >= 604800)
+        OR (status = 'failed' AND EXTRACT(EPOCH FROM NOW() - last_attempt_time) >= 604800);
@@ -0,0 +1,4 @@
+UPDATE email_outbox
+    SET status = $2, attempts = attempts + 1, last_error = $3, last_attempt_time = NOW(), next_attempt_time = NOW() + MAKE_INTERVAL(secs => $4::BIGINT),
+        html = CASE WHEN $2::VARCHAR = 'failed' THEN '' ELSE html END, text = CASE WHEN $2::VARCHAR = 'failed' THEN '' ELSE text END
+    WHERE id = $1;
@@ -0,0 +1,3 @@
+UPDATE email_outbox
+    SET status = 'sent', html = '', text = '', attempts = attempts + 1, last_error = NULL, last_attempt_time = NOW(), sent_time = NOW()
+    WHERE id = $1;
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS api_token (
+    id          SERIAL       NOT NULL,
+    user_id     SERIAL       NOT NULL,
+    name        VARCHAR(63)  NOT NULL,
+    token_hash  CHAR(64)     NOT NULL,
+    scopes      TEXT[]       NOT NULL,
+    create_time TIMESTAMP    NOT NULL DEFAULT NOW(),
+    expire_time TIMESTAMP,
+    last_used   TIMESTAMP,
+
+    PRIMARY KEY (id),
+
+    UNIQUE (token_hash),
+
+    CONSTRAINT fk_api_token_user
+        FOREIGN KEY (user_id)
+            REFERENCES app_user(id)
+                ON DELETE CASCADE
+);
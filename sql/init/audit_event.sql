@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS audit_event (
+    id           SERIAL       NOT NULL,
+    actor_id     INTEGER,
+    action       VARCHAR(63)  NOT NULL,
+    target_type  VARCHAR(63)  NOT NULL,
+    target_id    INTEGER      NOT NULL,
+    poll_id      INTEGER,
+    before_state JSONB,
+    after_state  JSONB,
+    ip_address   VARCHAR(45),
+    event_time   TIMESTAMP    NOT NULL DEFAULT NOW(),
+    impersonator_id INTEGER,
+
+    PRIMARY KEY (id),
+
+    CONSTRAINT fk_audit_event_user
+        FOREIGN KEY (actor_id)
+            REFERENCES app_user(id)
+                ON DELETE SET NULL,
+
+    CONSTRAINT fk_audit_event_impersonator
+        FOREIGN KEY (impersonator_id)
+            REFERENCES app_user(id)
+                ON DELETE SET NULL
+);
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_audit_event_poll ON audit_event (poll_id, event_time);
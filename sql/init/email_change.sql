@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS email_change (
+    id          CHAR(64)    NOT NULL,
+    user_id     SERIAL      NOT NULL,
+    new_email   VARCHAR(63) NOT NULL,
+    create_time TIMESTAMP   NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id),
+
+    CONSTRAINT fk_email_change_user
+        FOREIGN KEY (user_id)
+            REFERENCES app_user(id)
+                ON DELETE CASCADE
+);
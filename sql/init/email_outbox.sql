@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS email_outbox (
+    id                SERIAL       NOT NULL,
+    to_address        VARCHAR(255) NOT NULL,
+    subject           VARCHAR(255) NOT NULL,
+    html              TEXT         NOT NULL,
+    text              TEXT         NOT NULL,
+    status            VARCHAR(15)  NOT NULL DEFAULT 'pending',
+    attempts          INTEGER      NOT NULL DEFAULT 0,
+    last_error        TEXT,
+    create_time       TIMESTAMP    NOT NULL DEFAULT NOW(),
+    next_attempt_time TIMESTAMP    NOT NULL DEFAULT NOW(),
+    last_attempt_time TIMESTAMP,
+    sent_time         TIMESTAMP,
+
+    PRIMARY KEY (id)
+);
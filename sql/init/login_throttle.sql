@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS login_throttle (
+    scope         VARCHAR(7)   NOT NULL,
+    subject       TEXT         NOT NULL,
+    failures      INTEGER      NOT NULL DEFAULT 1,
+    last_failure  TIMESTAMP    NOT NULL DEFAULT NOW(),
+    blocked_until TIMESTAMP,
+
+    PRIMARY KEY (scope, subject)
+);
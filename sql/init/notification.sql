@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS notification (
+    id          SERIAL       NOT NULL,
+    user_id     SERIAL       NOT NULL,
+    kind        VARCHAR(31)  NOT NULL,
+    poll_id     INTEGER,
+    data        JSONB        NOT NULL,
+    dedupe_key  VARCHAR(127),
+    in_app      BOOLEAN      NOT NULL DEFAULT TRUE,
+    read        BOOLEAN      NOT NULL DEFAULT FALSE,
+    digested    BOOLEAN      NOT NULL DEFAULT FALSE,
+    create_time TIMESTAMP    NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id),
+
+    UNIQUE (user_id, dedupe_key),
+
+    CONSTRAINT fk_notification_user
+        FOREIGN KEY (user_id)
+            REFERENCES app_user(id)
+                ON DELETE CASCADE,
+
+    CONSTRAINT fk_notification_poll
+        FOREIGN KEY (poll_id)
+            REFERENCES poll(id)
+                ON DELETE CASCADE
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS notification_preference (
+    user_id          SERIAL    NOT NULL,
+    in_app           BOOLEAN   NOT NULL DEFAULT TRUE,
+    email            BOOLEAN   NOT NULL DEFAULT FALSE,
+    digest           BOOLEAN   NOT NULL DEFAULT FALSE,
+    last_digest_time TIMESTAMP,
+
+    PRIMARY KEY (user_id),
+
+    CONSTRAINT fk_notification_preference_user
+        FOREIGN KEY (user_id)
+            REFERENCES app_user(id)
+                ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_notification_user_create_time ON notification (user_id, create_time);
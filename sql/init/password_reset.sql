@@ -1,5 +1,5 @@
 CREATE TABLE IF NOT EXISTS password_reset (
-    id          CHAR(16)    NOT NULL DEFAULT SUBSTR(MD5(RANDOM()::TEXT), 0, 16),
+    id          CHAR(64)    NOT NULL,
     email       VARCHAR(63) NOT NULL,
     create_time TIMESTAMP   NOT NULL DEFAULT NOW(),
 
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS pending_login (
+    id          CHAR(64)  NOT NULL,
+    user_id     SERIAL    NOT NULL,
+    attempts    INTEGER   NOT NULL DEFAULT 0,
+    create_time TIMESTAMP NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id),
+
+    CONSTRAINT fk_pending_login_user
+        FOREIGN KEY (user_id)
+            REFERENCES app_user(id)
+                ON DELETE CASCADE
+);
@@ -1,9 +1,13 @@
 CREATE TABLE IF NOT EXISTS poll (
-    id          SERIAL        NOT NULL,
-    user_id     SERIAL        NOT NULL,
-    title       VARCHAR(255)  NOT NULL,
-    description VARCHAR(1023) NOT NULL,
-    create_time TIMESTAMP     NOT NULL DEFAULT NOW(),
+    id               SERIAL        NOT NULL,
+    user_id          SERIAL        NOT NULL,
+    title            VARCHAR(255)  NOT NULL,
+    description      VARCHAR(1023) NOT NULL,
+    create_time      TIMESTAMP     NOT NULL DEFAULT NOW(),
+    closed           BOOLEAN       NOT NULL DEFAULT FALSE,
+    hidden           BOOLEAN       NOT NULL DEFAULT FALSE,
+    max_vote_changes INTEGER,
+    close_time       TIMESTAMP,
 
     PRIMARY KEY (id),
 
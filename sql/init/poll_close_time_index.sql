@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_poll_close_time ON poll (close_time) WHERE closed = FALSE AND close_time IS NOT NULL;
@@ -7,6 +7,8 @@ CREATE TABLE IF NOT EXISTS poll_vote (
 
     PRIMARY KEY (id),
 
+    UNIQUE (user_id, poll_id),
+
     CONSTRAINT fk_poll_vote_user
         FOREIGN KEY (user_id)
             REFERENCES app_user(id)
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS poll_vote_history (
+    id             SERIAL    NOT NULL,
+    user_id        INTEGER   NOT NULL,
+    poll_id        INTEGER   NOT NULL,
+    poll_option_id INTEGER,
+    change_time    TIMESTAMP NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id),
+
+    CONSTRAINT fk_poll_vote_history_user
+        FOREIGN KEY (user_id)
+            REFERENCES app_user(id)
+                ON DELETE CASCADE,
+
+    CONSTRAINT fk_poll_vote_history_poll
+        FOREIGN KEY (poll_id)
+            REFERENCES poll(id)
+                ON DELETE CASCADE,
+
+    CONSTRAINT fk_poll_vote_history_poll_option
+        FOREIGN KEY (poll_option_id)
+            REFERENCES poll_option(id)
+                ON DELETE SET NULL
+);
@@ -0,0 +1,8 @@
+INSERT INTO poll_vote_history
+    (user_id, poll_id, poll_option_id, change_time)
+SELECT user_id, poll_id, poll_option_id, vote_time FROM poll_vote
+    WHERE NOT EXISTS (
+        SELECT 1 FROM poll_vote_history
+            WHERE poll_vote_history.user_id = poll_vote.user_id
+            AND poll_vote_history.poll_id = poll_vote.poll_id
+    );
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_poll_vote_history_poll ON poll_vote_history (poll_id, change_time);
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_poll_vote_history_user ON poll_vote_history (user_id, poll_id);
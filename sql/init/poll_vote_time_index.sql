@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_poll_vote_poll_time ON poll_vote (poll_id, vote_time);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS rate_limit_bucket (
+    key         VARCHAR(127)     NOT NULL,
+    tokens      DOUBLE PRECISION NOT NULL,
+    allowed     BOOLEAN          NOT NULL,
+    update_time TIMESTAMP        NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (key)
+);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS recovery_code (
+    id          SERIAL    NOT NULL,
+    user_id     SERIAL    NOT NULL,
+    code_hash   CHAR(64)  NOT NULL,
+    create_time TIMESTAMP NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id),
+
+    CONSTRAINT fk_recovery_code_user
+        FOREIGN KEY (user_id)
+            REFERENCES app_user(id)
+                ON DELETE CASCADE
+);
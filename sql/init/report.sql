@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS report (
+    id          SERIAL      NOT NULL,
+    reporter_id INTEGER     NOT NULL,
+    target_type VARCHAR(63) NOT NULL,
+    target_id   INTEGER     NOT NULL,
+    reason      VARCHAR(63) NOT NULL,
+    status      VARCHAR(15) NOT NULL DEFAULT 'open',
+    create_time TIMESTAMP   NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id),
+
+    UNIQUE (reporter_id, target_type, target_id),
+
+    CONSTRAINT fk_report_user
+        FOREIGN KEY (reporter_id)
+            REFERENCES app_user(id)
+                ON DELETE CASCADE
+);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS scheduled_job (
+    name              VARCHAR(63) NOT NULL,
+    last_run_time     TIMESTAMP   NOT NULL DEFAULT NOW(),
+    last_success_time TIMESTAMP,
+    last_error        TEXT,
+    runs              INTEGER     NOT NULL DEFAULT 0,
+    failures          INTEGER     NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (name)
+);
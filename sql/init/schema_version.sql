@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS schema_version (
+    version    INTEGER   NOT NULL,
+    apply_time TIMESTAMP NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (version)
+);
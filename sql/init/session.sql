@@ -1,12 +1,24 @@
 CREATE TABLE IF NOT EXISTS session (
-    id          CHAR(16)  NOT NULL DEFAULT SUBSTR(MD5(RANDOM()::TEXT), 0, 16),
-    user_id     SERIAL    NOT NULL,
-    create_time TIMESTAMP NOT NULL DEFAULT NOW(),
+    id              CHAR(64)     NOT NULL,
+    user_id         SERIAL       NOT NULL,
+    create_time     TIMESTAMP    NOT NULL DEFAULT NOW(),
+    handle          CHAR(64)     NOT NULL,
+    user_agent      VARCHAR(255),
+    ip_address      VARCHAR(45),
+    last_seen       TIMESTAMP    NOT NULL DEFAULT NOW(),
+    impersonator_id INTEGER,
 
     PRIMARY KEY (id),
 
+    UNIQUE (handle),
+
     CONSTRAINT fk_session_user
         FOREIGN KEY (user_id)
+            REFERENCES app_user(id)
+                ON DELETE CASCADE,
+
+    CONSTRAINT fk_session_impersonator
+        FOREIGN KEY (impersonator_id)
             REFERENCES app_user(id)
                 ON DELETE CASCADE
 );
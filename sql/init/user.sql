@@ -1,10 +1,17 @@
 CREATE TABLE IF NOT EXISTS app_user (
-    id        SERIAL       NOT NULL,
-    username  VARCHAR(63)  NOT NULL,
-    email     VARCHAR(63)  NOT NULL,
-    password  VARCHAR(255) NOT NULL,
-    verified  BOOLEAN      NOT NULL DEFAULT FALSE,
-    join_time TIMESTAMP    NOT NULL DEFAULT NOW(),
+    id             SERIAL       NOT NULL,
+    username       VARCHAR(63)  NOT NULL,
+    email          VARCHAR(63)  NOT NULL,
+    password       VARCHAR(255) NOT NULL,
+    verified       BOOLEAN      NOT NULL DEFAULT FALSE,
+    join_time      TIMESTAMP    NOT NULL DEFAULT NOW(),
+    is_admin       BOOLEAN      NOT NULL DEFAULT FALSE,
+    suspended      BOOLEAN      NOT NULL DEFAULT FALSE,
+    totp_secret    CHAR(40),
+    totp_enabled   BOOLEAN      NOT NULL DEFAULT FALSE,
+    totp_last_step BIGINT,
+    delete_time    TIMESTAMP,
+    locale         VARCHAR(15),
 
     PRIMARY KEY (id)
 );
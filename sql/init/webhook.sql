@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS webhook (
+    id          SERIAL        NOT NULL,
+    poll_id     SERIAL        NOT NULL,
+    url         VARCHAR(2047) NOT NULL,
+    secret      CHAR(64)      NOT NULL,
+    events      TEXT[]        NOT NULL,
+    create_time TIMESTAMP     NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id),
+
+    CONSTRAINT fk_webhook_poll
+        FOREIGN KEY (poll_id)
+            REFERENCES poll(id)
+                ON DELETE CASCADE
+);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS webhook_delivery (
+    id                SERIAL      NOT NULL,
+    webhook_id        SERIAL      NOT NULL,
+    event             VARCHAR(31) NOT NULL,
+    payload           TEXT        NOT NULL,
+    status            VARCHAR(15) NOT NULL DEFAULT 'pending',
+    attempts          INTEGER     NOT NULL DEFAULT 0,
+    response_status   INTEGER,
+    last_error        TEXT,
+    create_time       TIMESTAMP   NOT NULL DEFAULT NOW(),
+    next_attempt_time TIMESTAMP   NOT NULL DEFAULT NOW(),
+    last_attempt_time TIMESTAMP,
+    delivered_time    TIMESTAMP,
+
+    PRIMARY KEY (id),
+
+    CONSTRAINT fk_webhook_delivery_webhook
+        FOREIGN KEY (webhook_id)
+            REFERENCES webhook(id)
+                ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_webhook_delivery_status_next_attempt ON webhook_delivery (status, next_attempt_time);
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_webhook_delivery_webhook_create_time ON webhook_delivery (webhook_id, create_time);
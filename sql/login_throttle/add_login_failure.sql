@@ -0,0 +1,11 @@
+INSERT INTO login_throttle
+    (scope, subject)
+VALUES
+    ($1, $2)
+ON CONFLICT (scope, subject) DO UPDATE SET
+    failures = CASE
+        WHEN EXTRACT(EPOCH FROM NOW() - login_throttle.last_failure) >= $3::BIGINT THEN 1
+        ELSE login_throttle.failures + 1
+    END,
+    last_failure = NOW()
+RETURNING failures;
@@ -0,0 +1 @@
+DELETE FROM login_throttle WHERE scope = $1 AND subject = $2;
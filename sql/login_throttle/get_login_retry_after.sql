@@ -0,0 +1,4 @@
+SELECT CEIL(EXTRACT(EPOCH FROM MAX(blocked_until) - NOW()))::BIGINT AS "retry_after"
+    FROM login_throttle
+    WHERE ((scope = 'account' AND subject = $1) OR (scope = 'ip' AND subject = $2))
+        AND blocked_until > NOW();
@@ -0,0 +1,3 @@
+DELETE FROM login_throttle
+    WHERE EXTRACT(EPOCH FROM NOW() - last_failure) >= $1::BIGINT
+        AND (blocked_until IS NULL OR blocked_until <= NOW());
@@ -0,0 +1 @@
+UPDATE login_throttle SET blocked_until = NOW() + MAKE_INTERVAL(secs => $3::BIGINT) WHERE scope = $1 AND subject = $2;
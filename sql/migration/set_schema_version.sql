@@ -0,0 +1,5 @@
+INSERT INTO schema_version
+    (version)
+VALUES
+    ($1)
+ON CONFLICT (version) DO NOTHING;
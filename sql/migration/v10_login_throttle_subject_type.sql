@@ -0,0 +1,2 @@
+ALTER TABLE login_throttle
+    ALTER COLUMN subject TYPE TEXT;
@@ -0,0 +1 @@
+DELETE FROM password_reset WHERE id !~ '^[0-9a-f]{64}$';
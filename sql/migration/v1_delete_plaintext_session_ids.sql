@@ -0,0 +1 @@
+DELETE FROM session WHERE id !~ '^[0-9a-f]{64}$';
@@ -0,0 +1 @@
+DELETE FROM verify WHERE id !~ '^[0-9a-f]{64}$';
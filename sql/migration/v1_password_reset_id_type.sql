@@ -0,0 +1,3 @@
+ALTER TABLE password_reset
+    ALTER COLUMN id DROP DEFAULT,
+    ALTER COLUMN id TYPE CHAR(64);
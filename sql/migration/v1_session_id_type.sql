@@ -0,0 +1,3 @@
+ALTER TABLE session
+    ALTER COLUMN id DROP DEFAULT,
+    ALTER COLUMN id TYPE CHAR(64);
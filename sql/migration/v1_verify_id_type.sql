@@ -0,0 +1,3 @@
+ALTER TABLE verify
+    ALTER COLUMN id DROP DEFAULT,
+    ALTER COLUMN id TYPE CHAR(64);
@@ -0,0 +1,7 @@
+DELETE FROM poll_vote WHERE id IN (
+    SELECT id FROM (
+        SELECT id, ROW_NUMBER() OVER (PARTITION BY user_id, poll_id ORDER BY vote_time DESC, id DESC) AS vote_rank
+            FROM poll_vote
+    ) AS ranked_vote
+        WHERE vote_rank > 1
+);
@@ -0,0 +1,2 @@
+ALTER TABLE poll
+    ADD COLUMN IF NOT EXISTS max_vote_changes INTEGER;
@@ -0,0 +1 @@
+CREATE UNIQUE INDEX IF NOT EXISTS poll_vote_user_id_poll_id_key ON poll_vote (user_id, poll_id);
@@ -0,0 +1,5 @@
+ALTER TABLE session
+    ADD COLUMN IF NOT EXISTS handle     CHAR(64),
+    ADD COLUMN IF NOT EXISTS user_agent VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS ip_address VARCHAR(45),
+    ADD COLUMN IF NOT EXISTS last_seen  TIMESTAMP NOT NULL DEFAULT NOW();
@@ -0,0 +1,3 @@
+UPDATE session SET
+    handle = REPLACE(GEN_RANDOM_UUID()::TEXT || GEN_RANDOM_UUID()::TEXT, '-', '')
+WHERE handle IS NULL;
@@ -0,0 +1,2 @@
+ALTER TABLE session
+    ALTER COLUMN handle SET NOT NULL;
@@ -0,0 +1 @@
+CREATE UNIQUE INDEX IF NOT EXISTS session_handle_key ON session (handle);
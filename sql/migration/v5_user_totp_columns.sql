@@ -0,0 +1,4 @@
+ALTER TABLE app_user
+    ADD COLUMN IF NOT EXISTS totp_secret    CHAR(40),
+    ADD COLUMN IF NOT EXISTS totp_enabled   BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS totp_last_step BIGINT;
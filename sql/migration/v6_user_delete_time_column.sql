@@ -0,0 +1,2 @@
+ALTER TABLE app_user
+    ADD COLUMN IF NOT EXISTS delete_time TIMESTAMP;
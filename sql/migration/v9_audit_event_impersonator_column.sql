@@ -0,0 +1,2 @@
+ALTER TABLE audit_event
+    ADD COLUMN IF NOT EXISTS impersonator_id INTEGER REFERENCES app_user(id) ON DELETE SET NULL;
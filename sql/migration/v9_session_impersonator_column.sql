@@ -0,0 +1,2 @@
+ALTER TABLE session
+    ADD COLUMN IF NOT EXISTS impersonator_id INTEGER REFERENCES app_user(id) ON DELETE CASCADE;
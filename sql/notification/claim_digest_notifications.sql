@@ -0,0 +1,3 @@
+UPDATE notification SET digested = TRUE
+    WHERE user_id = $1 AND digested = FALSE AND read = FALSE
+RETURNING id, user_id, kind, poll_id, data, read, create_time;
@@ -0,0 +1,7 @@
+UPDATE notification_preference SET last_digest_time = NOW()
+    WHERE user_id IN (
+        SELECT user_id FROM notification_preference
+            WHERE digest = TRUE AND (last_digest_time IS NULL OR EXTRACT(EPOCH FROM NOW() - last_digest_time) >= $1::BIGINT)
+            FOR UPDATE SKIP LOCKED
+    )
+RETURNING user_id;
@@ -0,0 +1,6 @@
+INSERT INTO notification
+    (user_id, kind, poll_id, data, dedupe_key, in_app, digested)
+VALUES
+    ($1, $2, $3, $4, $5, $6, $7)
+ON CONFLICT (user_id, dedupe_key) DO NOTHING
+RETURNING id, user_id, kind, poll_id, data, read, create_time;
@@ -0,0 +1 @@
+SELECT user_id, in_app, email, digest FROM notification_preference WHERE user_id = $1;
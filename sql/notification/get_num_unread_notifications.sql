@@ -0,0 +1 @@
+SELECT COUNT(*) AS "count!" FROM notification WHERE user_id = $1 AND in_app = TRUE AND read = FALSE;
@@ -0,0 +1,5 @@
+SELECT id, user_id, kind, poll_id, data, read, create_time FROM notification
+    WHERE user_id = $1 AND in_app = TRUE AND (read = FALSE OR $2::BOOLEAN = FALSE)
+    ORDER BY create_time DESC, id DESC
+    LIMIT $3
+    OFFSET $4;
@@ -0,0 +1 @@
+UPDATE notification SET read = TRUE WHERE user_id = $1 AND read = FALSE;
@@ -0,0 +1 @@
+UPDATE notification SET read = TRUE WHERE id = $1 AND user_id = $2 AND in_app = TRUE RETURNING id, user_id, kind, poll_id, data, read, create_time;
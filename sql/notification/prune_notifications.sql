@@ -0,0 +1 @@
+DELETE FROM notification WHERE read = TRUE AND EXTRACT(EPOCH FROM NOW() - create_time) >= 7776000;
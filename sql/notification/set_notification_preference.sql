@@ -0,0 +1,7 @@
+INSERT INTO notification_preference
+    (user_id, in_app, email, digest)
+VALUES
+    ($1, $2, $3, $4)
+ON CONFLICT (user_id) DO UPDATE
+    SET in_app = EXCLUDED.in_app, email = EXCLUDED.email, digest = EXCLUDED.digest
+RETURNING user_id, in_app, email, digest;
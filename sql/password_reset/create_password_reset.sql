@@ -1,5 +1,5 @@
 INSERT INTO password_reset
-    (email)
+    (id, email)
 VALUES
-    ($1)
+    ($1, $2)
 RETURNING *;
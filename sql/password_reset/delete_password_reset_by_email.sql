@@ -0,0 +1 @@
+DELETE FROM password_reset WHERE email = $1;
@@ -1 +1 @@
-SELECT * FROM password_reset WHERE id = $1;
+SELECT * FROM password_reset WHERE id = $1 AND EXTRACT(EPOCH FROM NOW() - create_time) < $2::BIGINT;
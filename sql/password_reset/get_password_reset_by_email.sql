@@ -1 +0,0 @@
-SELECT * FROM password_reset WHERE email = $1;
@@ -1,3 +1,3 @@
 SELECT * FROM app_user WHERE email = (
-    SELECT email FROM password_reset WHERE id = $1
+    SELECT email FROM password_reset WHERE id = $1 AND EXTRACT(EPOCH FROM NOW() - create_time) < $2::BIGINT
 );
@@ -1 +1 @@
-DELETE FROM password_reset WHERE EXTRACT(EPOCH FROM NOW() - create_time) >= 3600;
+DELETE FROM password_reset WHERE EXTRACT(EPOCH FROM NOW() - create_time) >= $1::BIGINT;
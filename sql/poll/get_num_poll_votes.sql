@@ -0,0 +1 @@
+SELECT COUNT(*) AS "count!" FROM poll_vote WHERE poll_id = $1;
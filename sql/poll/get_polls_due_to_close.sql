@@ -0,0 +1 @@
+SELECT * FROM poll WHERE closed = FALSE AND close_time <= NOW() ORDER BY close_time;
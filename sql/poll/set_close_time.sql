@@ -0,0 +1 @@
+UPDATE poll SET close_time = TO_TIMESTAMP($1::BIGINT)::TIMESTAMP WHERE id = $2;
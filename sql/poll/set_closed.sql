@@ -0,0 +1 @@
+UPDATE poll SET closed = $1 WHERE id = $2;
@@ -0,0 +1 @@
+UPDATE poll SET hidden = $1 WHERE id = $2;
@@ -0,0 +1 @@
+UPDATE poll SET max_vote_changes = $1 WHERE id = $2;
@@ -0,0 +1,4 @@
+UPDATE poll_vote
+    SET poll_option_id = $3, vote_time = NOW()
+    WHERE user_id = $1 AND poll_id = $2
+RETURNING *;
@@ -0,0 +1,5 @@
+INSERT INTO poll_vote_history
+    (user_id, poll_id, poll_option_id)
+VALUES
+    ($1, $2, $3)
+RETURNING *;
@@ -0,0 +1 @@
+SELECT COUNT(*) AS "count!" FROM poll_vote_history WHERE user_id = $1 AND poll_id = $2;
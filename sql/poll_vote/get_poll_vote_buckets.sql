@@ -0,0 +1,15 @@
+WITH buckets AS (
+    SELECT
+        DATE_TRUNC($2, vote_time AT TIME ZONE 'UTC' AT TIME ZONE $3) AS bucket_time,
+        poll_option_id
+    FROM poll_vote
+    WHERE poll_id = $1
+)
+SELECT
+    bucket_time AT TIME ZONE $3 AT TIME ZONE 'UTC' AS "bucket_time!",
+    poll_option_id AS "poll_option_id!",
+    COUNT(*) AS "votes!",
+    SUM(COUNT(*)) OVER (PARTITION BY poll_option_id ORDER BY bucket_time)::BIGINT AS "cumulative_votes!"
+FROM buckets
+GROUP BY bucket_time, poll_option_id
+ORDER BY bucket_time, poll_option_id;
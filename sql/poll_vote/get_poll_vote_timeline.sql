@@ -0,0 +1,21 @@
+WITH changes AS (
+    SELECT
+        id,
+        change_time,
+        poll_option_id,
+        LAG(poll_option_id) OVER (PARTITION BY user_id ORDER BY change_time, id) AS previous_poll_option_id
+    FROM poll_vote_history
+    WHERE poll_id = $1
+), deltas AS (
+    SELECT id, change_time, poll_option_id, 1 AS delta FROM changes
+        WHERE poll_option_id IS NOT NULL
+    UNION ALL
+    SELECT id, change_time, previous_poll_option_id AS poll_option_id, -1 AS delta FROM changes
+        WHERE previous_poll_option_id IS NOT NULL
+)
+SELECT
+    change_time AS "change_time!",
+    poll_option_id AS "poll_option_id!",
+    SUM(delta) OVER (PARTITION BY poll_option_id ORDER BY change_time, id, delta) AS "votes!"
+FROM deltas
+ORDER BY change_time, id, delta;
@@ -0,0 +1 @@
+SELECT * FROM poll_vote WHERE user_id = $1 ORDER BY vote_time;
@@ -0,0 +1 @@
+SELECT * FROM poll_vote_history WHERE user_id = $1 AND poll_id = $2 ORDER BY change_time, id;
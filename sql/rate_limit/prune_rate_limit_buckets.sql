@@ -0,0 +1 @@
+DELETE FROM rate_limit_bucket WHERE EXTRACT(EPOCH FROM NOW() - update_time) >= $1::BIGINT;
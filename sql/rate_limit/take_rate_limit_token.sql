@@ -0,0 +1,13 @@
+INSERT INTO rate_limit_bucket AS bucket
+    (key, tokens, allowed)
+VALUES
+    ($1, $2::DOUBLE PRECISION - 1, TRUE)
+ON CONFLICT (key) DO UPDATE SET
+    tokens = CASE
+        WHEN LEAST($2::DOUBLE PRECISION, bucket.tokens + EXTRACT(EPOCH FROM NOW() - bucket.update_time)::DOUBLE PRECISION * $3::DOUBLE PRECISION) >= 1
+            THEN LEAST($2::DOUBLE PRECISION, bucket.tokens + EXTRACT(EPOCH FROM NOW() - bucket.update_time)::DOUBLE PRECISION * $3::DOUBLE PRECISION) - 1
+        ELSE LEAST($2::DOUBLE PRECISION, bucket.tokens + EXTRACT(EPOCH FROM NOW() - bucket.update_time)::DOUBLE PRECISION * $3::DOUBLE PRECISION)
+    END,
+    allowed = LEAST($2::DOUBLE PRECISION, bucket.tokens + EXTRACT(EPOCH FROM NOW() - bucket.update_time)::DOUBLE PRECISION * $3::DOUBLE PRECISION) >= 1,
+    update_time = NOW()
+RETURNING tokens, allowed;
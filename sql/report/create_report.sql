@@ -0,0 +1,5 @@
+INSERT INTO report
+    (reporter_id, target_type, target_id, reason)
+VALUES
+    ($1, $2, $3, $4)
+RETURNING *;
@@ -0,0 +1,8 @@
+SELECT COUNT(DISTINCT reporter_id) AS "count!" FROM report
+    WHERE status = $2
+    AND (
+        (target_type = 'poll' AND target_id = $1)
+        OR (target_type = 'poll_option' AND target_id IN (
+            SELECT id FROM poll_option WHERE poll_id = $1
+        ))
+    );
@@ -0,0 +1 @@
+SELECT * FROM report WHERE id = $1;
@@ -0,0 +1 @@
+SELECT * FROM report WHERE reporter_id = $1 AND target_type = $2 AND target_id = $3;
@@ -0,0 +1 @@
+SELECT * FROM report WHERE status = $1 ORDER BY create_time, id LIMIT $2 OFFSET $3;
@@ -0,0 +1 @@
+UPDATE report SET status = $1 WHERE id = $2;
@@ -0,0 +1,8 @@
+INSERT INTO scheduled_job
+    (name)
+VALUES
+    ($1)
+ON CONFLICT (name) DO UPDATE SET
+    last_run_time = NOW()
+WHERE EXTRACT(EPOCH FROM NOW() - scheduled_job.last_run_time) >= $2::BIGINT
+RETURNING *;
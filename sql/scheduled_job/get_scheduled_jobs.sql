@@ -0,0 +1 @@
+SELECT * FROM scheduled_job ORDER BY name;
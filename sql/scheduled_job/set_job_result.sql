@@ -0,0 +1,6 @@
+UPDATE scheduled_job SET
+    last_success_time = CASE WHEN $2 THEN NOW() ELSE last_success_time END,
+    last_error = $3,
+    runs = runs + 1,
+    failures = failures + CASE WHEN $2 THEN 0 ELSE 1 END
+WHERE name = $1;
@@ -0,0 +1,5 @@
+INSERT INTO session
+    (id, handle, user_id, user_agent, ip_address, impersonator_id)
+VALUES
+    ($1, $2, $3, $4, $5, $6)
+RETURNING *;
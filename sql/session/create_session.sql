@@ -1,5 +1,5 @@
 INSERT INTO session
-    (user_id)
+    (id, handle, user_id, user_agent, ip_address)
 VALUES
-    ($1)
+    ($1, $2, $3, $4, $5)
 RETURNING *;
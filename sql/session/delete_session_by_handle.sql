@@ -0,0 +1 @@
+DELETE FROM session WHERE user_id = $1 AND handle = $2 RETURNING *;
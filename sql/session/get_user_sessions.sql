@@ -1 +1,5 @@
-SELECT * FROM session WHERE user_id = $1;
+SELECT * FROM session
+    WHERE user_id = $1
+    AND EXTRACT(EPOCH FROM NOW() - create_time) < $2::BIGINT
+    AND EXTRACT(EPOCH FROM NOW() - last_seen) < $3::BIGINT
+    ORDER BY last_seen DESC;
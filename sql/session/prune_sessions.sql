@@ -0,0 +1,3 @@
+DELETE FROM session
+    WHERE EXTRACT(EPOCH FROM NOW() - create_time) >= $1::BIGINT
+    OR EXTRACT(EPOCH FROM NOW() - last_seen) >= $2::BIGINT;
@@ -0,0 +1,6 @@
+UPDATE session
+    SET last_seen = NOW()
+    WHERE id = $1
+    AND EXTRACT(EPOCH FROM NOW() - create_time) < $2::BIGINT
+    AND EXTRACT(EPOCH FROM NOW() - last_seen) < $3::BIGINT
+RETURNING *;
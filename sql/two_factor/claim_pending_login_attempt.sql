@@ -0,0 +1 @@
+UPDATE pending_login SET attempts = attempts + 1 WHERE id = $1 AND EXTRACT(EPOCH FROM NOW() - create_time) < 300 AND attempts < 5 RETURNING user_id;
@@ -0,0 +1,4 @@
+INSERT INTO pending_login
+    (id, user_id)
+VALUES
+    ($1, $2);
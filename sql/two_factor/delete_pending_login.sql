@@ -0,0 +1 @@
+DELETE FROM pending_login WHERE id = $1;
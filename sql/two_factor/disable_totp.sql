@@ -0,0 +1 @@
+UPDATE app_user SET totp_secret = NULL, totp_enabled = FALSE, totp_last_step = NULL WHERE id = $1;
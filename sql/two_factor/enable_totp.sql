@@ -0,0 +1 @@
+UPDATE app_user SET totp_enabled = TRUE, totp_last_step = $1 WHERE id = $2;
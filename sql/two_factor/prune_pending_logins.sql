@@ -0,0 +1 @@
+DELETE FROM pending_login WHERE EXTRACT(EPOCH FROM NOW() - create_time) >= 300 OR attempts >= 5;
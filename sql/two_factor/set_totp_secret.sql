@@ -0,0 +1 @@
+UPDATE app_user SET totp_secret = $1, totp_enabled = FALSE, totp_last_step = NULL WHERE id = $2;
@@ -0,0 +1 @@
+UPDATE app_user SET delete_time = NULL WHERE id = $1;
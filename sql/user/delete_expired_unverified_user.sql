@@ -0,0 +1 @@
+DELETE FROM app_user WHERE (username = $1 OR email = $2) AND verified = FALSE AND EXTRACT(EPOCH FROM NOW() - join_time) >= $3::BIGINT;
@@ -0,0 +1 @@
+SELECT * FROM app_user WHERE email = $1 AND (verified = TRUE OR EXTRACT(EPOCH FROM NOW() - join_time) < $2::BIGINT);
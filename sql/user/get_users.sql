@@ -0,0 +1 @@
+SELECT * FROM app_user ORDER BY id LIMIT $1 OFFSET $2;
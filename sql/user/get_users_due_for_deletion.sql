@@ -0,0 +1 @@
+SELECT * FROM app_user WHERE delete_time <= NOW();
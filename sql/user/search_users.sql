@@ -0,0 +1,5 @@
+SELECT * FROM app_user
+    WHERE username ILIKE '%' || $1 || '%'
+    OR email ILIKE '%' || $1 || '%'
+    ORDER BY id
+    LIMIT $2 OFFSET $3;
@@ -0,0 +1 @@
+UPDATE app_user SET delete_time = NOW() + MAKE_INTERVAL(secs => $1::BIGINT) WHERE id = $2;
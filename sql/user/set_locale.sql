@@ -0,0 +1 @@
+UPDATE app_user SET locale = $1 WHERE id = $2;
@@ -0,0 +1 @@
+UPDATE app_user SET suspended = $1 WHERE id = $2;
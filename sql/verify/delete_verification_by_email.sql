@@ -0,0 +1 @@
+DELETE FROM verify WHERE email = $1;
@@ -1 +0,0 @@
-SELECT * FROM verify WHERE email = $1;
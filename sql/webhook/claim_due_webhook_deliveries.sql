@@ -0,0 +1,9 @@
+UPDATE webhook_delivery SET next_attempt_time = NOW() + MAKE_INTERVAL(secs => $2::BIGINT)
+    WHERE id IN (
+        SELECT id FROM webhook_delivery
+            WHERE status = 'pending' AND next_attempt_time <= NOW()
+            ORDER BY next_attempt_time
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+    )
+RETURNING *;
@@ -0,0 +1,5 @@
+INSERT INTO webhook
+    (poll_id, url, secret, events)
+VALUES
+    ($1, $2, $3, $4)
+RETURNING *;
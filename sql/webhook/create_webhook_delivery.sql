@@ -0,0 +1,5 @@
+INSERT INTO webhook_delivery
+    (webhook_id, event, payload)
+VALUES
+    ($1, $2, $3)
+RETURNING *;
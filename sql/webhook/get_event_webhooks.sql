@@ -0,0 +1 @@
+SELECT * FROM webhook WHERE poll_id = $1 AND $2 = ANY(events);
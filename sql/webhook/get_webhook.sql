@@ -0,0 +1 @@
+SELECT * FROM webhook WHERE id = $1;
@@ -0,0 +1,5 @@
+SELECT * FROM webhook_delivery
+    WHERE webhook_id = $1
+    ORDER BY create_time DESC, id DESC
+    LIMIT $2
+    OFFSET $3;
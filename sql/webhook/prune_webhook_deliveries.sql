@@ -0,0 +1 @@
+DELETE FROM webhook_delivery WHERE status <> 'pending' AND EXTRACT(EPOCH FROM NOW() - create_time) >= 2592000;
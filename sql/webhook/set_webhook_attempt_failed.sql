@@ -0,0 +1,5 @@
+UPDATE webhook_delivery
+    SET status = $2, attempts = attempts + 1, response_status = $3, last_error = $4, last_attempt_time = NOW(),
+        next_attempt_time = NOW() + MAKE_INTERVAL(secs => $5::BIGINT)
+    WHERE id = $1
+RETURNING *;
@@ -0,0 +1,4 @@
+UPDATE webhook_delivery
+    SET status = 'delivered', attempts = attempts + 1, response_status = $2, last_error = NULL, last_attempt_time = NOW(), delivered_time = NOW()
+    WHERE id = $1
+RETURNING *;
@@ -0,0 +1,302 @@
+use actix_web::{FromRequest, HttpRequest, HttpResponse, HttpMessage, Error, web};
+use actix_web::dev::Payload;
+use actix_web::http::{header, StatusCode};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::pin::Pin;
+use crate::services;
+use crate::i18n;
+use crate::services::User;
+use crate::util::{AppData, ActiveSession, ErrorJSON};
+
+/// A permission that an API token must have been granted for a route to accept it
+pub trait Scope {
+    /// The name of the scope, or `None` if the route only accepts session cookies
+    const NAME: Option<&'static str>;
+}
+
+/// Marks a route as only accepting session cookies
+pub struct SessionOnly;
+
+/// Marks a route as accepting API tokens with the `read_polls` scope
+pub struct ReadPolls;
+
+/// Marks a route as accepting API tokens with the `create_polls` scope
+pub struct CreatePolls;
+
+/// Marks a route as accepting API tokens with the `vote` scope
+pub struct Vote;
+
+impl Scope for SessionOnly {
+    const NAME: Option<&'static str> = None;
+}
+
+impl Scope for ReadPolls {
+    const NAME: Option<&'static str> = Some("read_polls");
+}
+
+impl Scope for CreatePolls {
+    const NAME: Option<&'static str> = Some("create_polls");
+}
+
+impl Scope for Vote {
+    const NAME: Option<&'static str> = Some("vote");
+}
+
+/// How a request was authenticated
+#[derive(Clone)]
+enum Credentials {
+    Session,
+    ApiToken(Vec<String>),
+}
+
+/// The user a request was authenticated as, and how
+#[derive(Clone)]
+struct Authenticated {
+    user: User,
+    credentials: Credentials,
+}
+
+impl Authenticated {
+    /// Returns whether or not the credentials grant a scope
+    fn has_scope<S: Scope>(&self) -> bool {
+        match &self.credentials {
+            Credentials::Session => true,
+            Credentials::ApiToken(scopes) => match S::NAME {
+                Some(name) => scopes.iter().any(|scope| scope == name),
+                None => false,
+            },
+        }
+    }
+}
+
+/// Why a request could not be authenticated
+#[derive(Clone)]
+struct AuthError {
+    status: StatusCode,
+    message: String,
+}
+
+impl AuthError {
+    /// An error for a request with missing or invalid credentials
+    fn unauthorized(message: &str) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            message: message.to_string(),
+        }
+    }
+
+    /// An error for a request with valid credentials that are not permitted to perform the action
+    fn forbidden(message: &str) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<AuthError> for Error {
+    fn from(err: AuthError) -> Self {
+        HttpResponse::build(err.status).json(ErrorJSON {
+            error: i18n::localize(&err.message)
+        }).into()
+    }
+}
+
+/// The result of authenticating a request, cached in the request's extensions
+type AuthResult = Result<Option<Authenticated>, AuthError>;
+
+/// Returns the API token from the request's `Authorization: Bearer` header
+/// 
+/// # Arguments
+/// 
+/// * `req` - The HTTP request object
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|authorization| authorization.to_str().ok())
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+/// Resolves the user a request is authenticated as, preferring an API token over a session cookie
+/// 
+/// The result is cached, so a request is only looked up once however many extractors ask for it.
+/// 
+/// # Arguments
+/// 
+/// * `req` - The HTTP request object
+async fn authenticate(req: &HttpRequest) -> AuthResult {
+    if let Some(res) = req.extensions().get::<AuthResult>() {
+        return res.clone();
+    }
+
+    let app_data = req.app_data::<web::Data<AppData>>().unwrap().clone();
+
+    let res = if let Some(token) = bearer_token(req) {
+        match services::api_token_service::use_api_token(&app_data.pool, token).await {
+            Ok((_, user)) if user.suspended => Err(AuthError::forbidden("account_suspended")),
+            Ok((api_token, user)) => Ok(Some(Authenticated {
+                user,
+                credentials: Credentials::ApiToken(api_token.scopes),
+            })),
+            Err(e) => Err(AuthError::unauthorized(&format!("{}", e))),
+        }
+    } else if let Some(session_cookie) = req.cookie("session_id") {
+        match services::session_service::get_user_by_session_id(&app_data.pool, &app_data.config, session_cookie.value().to_string()).await {
+            Ok((user, _)) if user.suspended => Err(AuthError::forbidden("account_suspended")),
+            Ok((user, session)) => {
+                req.extensions_mut().insert(ActiveSession {
+                    session_id: session_cookie.value().to_string(),
+                    impersonator_id: session.impersonator_id,
+                });
+
+                Ok(Some(Authenticated {
+                    user,
+                    credentials: Credentials::Session,
+                }))
+            },
+            Err(e) => Err(AuthError::unauthorized(&format!("{}", e))),
+        }
+    } else {
+        Ok(None)
+    };
+
+    req.extensions_mut().insert(res.clone());
+
+    res
+}
+
+/// Returns the ID of the user a request is authenticated as, if it has valid credentials of any kind
+/// 
+/// # Arguments
+/// 
+/// * `req` - The HTTP request object
+pub async fn authenticated_user_id(req: &HttpRequest) -> Option<i32> {
+    match authenticate(req).await {
+        Ok(Some(auth)) => Some(auth.user.id),
+        _ => None,
+    }
+}
+
+/// Returns the preferred locale of the user a request is authenticated as, if it has valid credentials and the user has chosen one
+/// 
+/// # Arguments
+/// 
+/// * `req` - The HTTP request object
+pub async fn authenticated_user_locale(req: &HttpRequest) -> Option<String> {
+    match authenticate(req).await {
+        Ok(Some(auth)) => auth.user.locale,
+        _ => None,
+    }
+}
+
+/// Extracts the user a request is authenticated as, rejecting the request with a 401 if it is not authenticated
+/// 
+/// API tokens are only accepted if they have been granted the scope `S`. By default only session cookies are accepted.
+pub struct AuthUser<S: Scope = SessionOnly> {
+    pub user: User,
+    scope: PhantomData<S>,
+}
+
+impl<S: Scope> Deref for AuthUser<S> {
+    type Target = User;
+
+    fn deref(&self) -> &User {
+        &self.user
+    }
+}
+
+impl<S: Scope + 'static> FromRequest for AuthUser<S> {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            match authenticate(&req).await? {
+                Some(auth) if auth.has_scope::<S>() => Ok(AuthUser {
+                    user: auth.user,
+                    scope: PhantomData,
+                }),
+                Some(_) => Err(AuthError::forbidden("api_token_forbidden").into()),
+                None => Err(AuthError::unauthorized("not_logged_in").into()),
+            }
+        })
+    }
+}
+
+/// Extracts the user a request is authenticated as, if any
+/// 
+/// Requests with no credentials, an expired session cookie or an API token without the scope `S` are treated as anonymous.
+pub struct OptionalAuthUser<S: Scope = SessionOnly> {
+    pub user: Option<User>,
+    scope: PhantomData<S>,
+}
+
+impl<S: Scope + 'static> FromRequest for OptionalAuthUser<S> {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let user = match authenticate(&req).await {
+                Ok(Some(auth)) if auth.has_scope::<S>() => Some(auth.user),
+                Ok(_) => None,
+                Err(e) if e.status == StatusCode::UNAUTHORIZED && bearer_token(&req).is_none() => None,
+                Err(e) => return Err(e.into()),
+            };
+
+            Ok(OptionalAuthUser {
+                user,
+                scope: PhantomData,
+            })
+        })
+    }
+}
+
+/// Extracts the user a request is authenticated as, provided they are an administrator
+/// 
+/// Only session cookies are accepted.
+pub struct AdminUser {
+    pub user: User,
+}
+
+impl Deref for AdminUser {
+    type Target = User;
+
+    fn deref(&self) -> &User {
+        &self.user
+    }
+}
+
+impl FromRequest for AdminUser {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let auth_user = AuthUser::<SessionOnly>::from_request(req, payload);
+        let req = req.clone();
+
+        Box::pin(async move {
+            let auth_user = auth_user.await?;
+            // A session impersonating a user never has administrator rights, even if the user is made an administrator meanwhile
+            let impersonating = req.extensions().get::<ActiveSession>().is_some_and(|active_session| active_session.impersonator_id.is_some());
+
+            if auth_user.is_admin && !impersonating {
+                Ok(AdminUser {
+                    user: auth_user.user,
+                })
+            } else {
+                Err(AuthError::forbidden("forbidden").into())
+            }
+        })
+    }
+}
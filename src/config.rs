@@ -0,0 +1,672 @@
+use actix_web::http::Method;
+use serde::Deserialize;
+use std::io::{Error, ErrorKind, Result};
+use std::net::IpAddr;
+use std::str::FromStr;
+use url::Url;
+use ipnet::IpNet;
+use crate::emailer::SmtpTlsMode;
+
+/// The config file read when the `CONFIG_FILE` environment variable is not set, if it exists
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// The application's configuration, loaded from a TOML file with environment variables layered on top
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub cors: CorsConfig,
+    pub database: DatabaseConfig,
+    pub sessions: SessionConfig,
+    pub expiry: ExpiryConfig,
+    pub accounts: AccountConfig,
+    pub login: LoginConfig,
+    pub rate_limit: RateLimitConfig,
+    pub email: EmailConfig,
+    pub smtp: SmtpConfig,
+    pub notifications: NotificationConfig,
+    pub moderation: ModerationConfig,
+    pub webhooks: WebhookConfig,
+    pub scheduler: SchedulerConfig,
+}
+
+/// Configuration for the HTTP server
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The port to listen on
+    pub port: u16,
+    /// The URL of the frontend, which links in emails point to
+    pub frontend_url: String,
+    /// The addresses or CIDR ranges of reverse proxies whose `X-Forwarded-For` headers are believed
+    pub trusted_proxies: Vec<String>,
+}
+
+/// Configuration for cross-origin requests
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// The origins allowed to make requests
+    pub allowed_origins: Vec<String>,
+    /// The HTTP methods those origins may use
+    pub allowed_methods: Vec<String>,
+}
+
+/// Configuration for the database
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// The URL of the database, which must be set here or by the `DATABASE_URL` environment variable
+    pub url: Option<String>,
+    /// The maximum number of connections in the pool
+    pub max_connections: u32,
+}
+
+/// Configuration for user sessions
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// The maximum number of sessions a user can have at once
+    pub max_per_user: i64,
+    /// The number of seconds after creation that a session expires
+    pub max_age: i64,
+    /// The number of seconds a session can go unused before it expires
+    pub idle_timeout: i64,
+}
+
+/// The number of seconds emailed links stay valid for
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExpiryConfig {
+    /// How long an account verification link stays valid, after which the unverified account is deleted
+    pub verification: i64,
+    /// How long a password reset link stays valid
+    pub password_reset: i64,
+    /// How long an email change confirmation link stays valid
+    pub email_change: i64,
+}
+
+/// Configuration for user accounts
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountConfig {
+    /// The number of seconds an account is kept after its deletion is requested
+    pub deletion_grace_period: i64,
+}
+
+/// Configuration for login throttling
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginConfig {
+    /// The number of failed logins to an account after which it is locked
+    pub lockout_threshold: i32,
+    /// The number of failed logins from an IP address after which it is locked out
+    pub ip_lockout_threshold: i32,
+    /// The number of seconds a lockout lasts, which is also how long failed logins are remembered
+    pub lockout_duration: i64,
+}
+
+/// Where rate limit buckets are kept
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    /// In this process, so each instance limits clients separately
+    #[default]
+    Memory,
+    /// In the database, shared between every instance
+    Postgres,
+}
+
+/// Configuration for rate limiting
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Where rate limit buckets are kept
+    pub store: RateLimitBackend,
+    /// The number of requests a client can burst before being limited
+    pub burst: f64,
+    /// The number of requests a client can sustain each minute
+    pub per_minute: f64,
+}
+
+/// How emails are delivered
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    /// Through an SMTP server
+    #[default]
+    Smtp,
+    /// Written to a maildir, for development
+    File,
+    /// Kept in memory, for tests
+    Memory,
+}
+
+/// Configuration for outbound emails
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailConfig {
+    /// How emails are delivered
+    pub transport: EmailTransportKind,
+    /// The address emails are sent from, which must be set to deliver through SMTP
+    pub from_address: Option<String>,
+    /// The maildir the file transport writes emails to
+    pub dir: String,
+    /// A directory of templates replacing the embedded templates of the same name
+    pub template_dir: Option<String>,
+    /// The number of delivery attempts after which an email is given up on
+    pub max_attempts: i32,
+    /// The number of seconds between checks for queued emails that are due to be delivered
+    pub poll_interval: u64,
+}
+
+/// Configuration for the SMTP server emails are delivered through
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpConfig {
+    /// The SMTP server
+    pub host: String,
+    /// The port of the SMTP server, defaulting to the one usually used with the TLS mode
+    pub port: Option<u16>,
+    /// How the connection is secured
+    pub tls: SmtpTlsMode,
+    /// The username to log in with, defaulting to the address emails are sent from
+    pub username: Option<String>,
+    /// The password to log in with, if the server requires one
+    pub password: Option<String>,
+}
+
+/// Configuration for notifications
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    /// The number of seconds between digest emails
+    pub digest_interval: i64,
+}
+
+/// Configuration for moderation
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationConfig {
+    /// The number of distinct users who must report a poll before it is hidden pending review
+    pub report_threshold: i64,
+}
+
+/// Configuration for outgoing webhooks
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// The number of seconds to wait for a webhook endpoint to connect and respond
+    pub timeout: u64,
+    /// The number of delivery attempts after which a webhook delivery is given up on
+    pub max_attempts: i32,
+    /// The number of seconds between checks for webhook deliveries that are due
+    pub poll_interval: u64,
+    /// Whether webhooks may be sent to loopback and private network addresses, which should only be allowed in development
+    pub allow_private_networks: bool,
+}
+
+/// Configuration for the background job scheduler, with the number of seconds between runs of each job
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    /// The fraction of its interval by which each run of a job is randomly brought forward or put back, so instances started together spread out
+    pub jitter: f64,
+    pub expire_sessions: u64,
+    pub prune_verifications: u64,
+    pub prune_password_resets: u64,
+    pub prune_email_changes: u64,
+    pub prune_pending_logins: u64,
+    pub prune_login_throttles: u64,
+    pub prune_deleted_users: u64,
+    pub prune_sent_emails: u64,
+    pub prune_notifications: u64,
+    pub prune_webhook_deliveries: u64,
+    pub close_polls: u64,
+    pub send_digests: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: 3000,
+            frontend_url: "https://greenpoll.herokuapp.com".to_string(),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["https://greenpoll.herokuapp.com".to_string(), "http://localhost:3000".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            max_connections: 20,
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            max_per_user: 4,
+            max_age: 60 * 60 * 24 * 30,
+            idle_timeout: 60 * 60 * 24 * 7,
+        }
+    }
+}
+
+impl Default for ExpiryConfig {
+    fn default() -> Self {
+        Self {
+            verification: 60 * 60,
+            password_reset: 60 * 60,
+            email_change: 60 * 60,
+        }
+    }
+}
+
+impl Default for AccountConfig {
+    fn default() -> Self {
+        Self {
+            deletion_grace_period: 60 * 60 * 24 * 30,
+        }
+    }
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        Self {
+            lockout_threshold: 10,
+            ip_lockout_threshold: 50,
+            lockout_duration: 60 * 15,
+        }
+    }
+}
+
+impl FromStr for RateLimitBackend {
+    type Err = ();
+
+    fn from_str(name: &str) -> std::result::Result<Self, ()> {
+        match name {
+            "memory" => Ok(RateLimitBackend::Memory),
+            "postgres" => Ok(RateLimitBackend::Postgres),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            store: RateLimitBackend::default(),
+            burst: 120.0,
+            per_minute: 120.0,
+        }
+    }
+}
+
+impl FromStr for EmailTransportKind {
+    type Err = ();
+
+    fn from_str(name: &str) -> std::result::Result<Self, ()> {
+        match name {
+            "smtp" => Ok(EmailTransportKind::Smtp),
+            "file" => Ok(EmailTransportKind::File),
+            "memory" => Ok(EmailTransportKind::Memory),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            transport: EmailTransportKind::default(),
+            from_address: None,
+            dir: "sent_emails".to_string(),
+            template_dir: None,
+            max_attempts: 8,
+            poll_interval: 5,
+        }
+    }
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            host: "smtp.gmail.com".to_string(),
+            port: None,
+            tls: SmtpTlsMode::Wrapper,
+            username: None,
+            password: None,
+        }
+    }
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            digest_interval: 60 * 60 * 24,
+        }
+    }
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            report_threshold: 5,
+        }
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 10,
+            max_attempts: 8,
+            poll_interval: 5,
+            allow_private_networks: false,
+        }
+    }
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            jitter: 0.1,
+            expire_sessions: 60 * 15,
+            prune_verifications: 60 * 5,
+            prune_password_resets: 60 * 5,
+            prune_email_changes: 60 * 5,
+            prune_pending_logins: 60 * 5,
+            prune_login_throttles: 60 * 10,
+            prune_deleted_users: 60 * 60,
+            prune_sent_emails: 60 * 60,
+            prune_notifications: 60 * 60,
+            prune_webhook_deliveries: 60 * 60,
+            close_polls: 60,
+            send_digests: 60 * 60,
+        }
+    }
+}
+
+/// Replaces a config value with the value of an environment variable, if it is set
+/// 
+/// # Arguments
+/// 
+/// * `name` - The name of the environment variable
+/// * `value` - The config value to replace
+fn env_override<T: FromStr>(name: &str, value: &mut T) -> Result<()> {
+    if let Ok(env_value) = std::env::var(name) {
+        *value = match env_value.trim().parse() {
+            Ok(val) => Ok(val),
+            Err(_) => Err(Error::new(ErrorKind::InvalidInput, format!("Invalid value {:?} for environment variable {}", env_value, name)))
+        }?;
+    }
+
+    Ok(())
+}
+
+/// Sets an optional config value to the value of an environment variable, if it is set
+/// 
+/// # Arguments
+/// 
+/// * `name` - The name of the environment variable
+/// * `value` - The config value to set
+fn env_optional_override<T: FromStr>(name: &str, value: &mut Option<T>) -> Result<()> {
+    if let Ok(env_value) = std::env::var(name) {
+        *value = match env_value.trim().parse() {
+            Ok(val) => Ok(Some(val)),
+            Err(_) => Err(Error::new(ErrorKind::InvalidInput, format!("Invalid value {:?} for environment variable {}", env_value, name)))
+        }?;
+    }
+
+    Ok(())
+}
+
+/// Replaces a config list with the comma-separated values of an environment variable, if it is set
+/// 
+/// # Arguments
+/// 
+/// * `name` - The name of the environment variable
+/// * `value` - The config list to replace
+fn env_list_override(name: &str, value: &mut Vec<String>) {
+    if let Ok(env_value) = std::env::var(name) {
+        *value = env_value.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect();
+    }
+}
+
+/// Returns whether a URL is an absolute `http` or `https` URL with a host
+/// 
+/// # Arguments
+/// 
+/// * `url` - The URL
+fn is_web_url(url: &str) -> bool {
+    match Url::parse(url) {
+        Ok(url) => (url.scheme() == "http" || url.scheme() == "https") && url.host_str().is_some(),
+        Err(_) => false,
+    }
+}
+
+/// Returns whether a string is an origin as browsers send it, like `https://example.com:8080`, with no path or trailing slash
+/// 
+/// # Arguments
+/// 
+/// * `origin` - The origin
+fn is_origin(origin: &str) -> bool {
+    is_web_url(origin) && Url::parse(origin).map(|url| url.origin().ascii_serialization() == origin).unwrap_or(false)
+}
+
+/// Parses an IP address or CIDR range, treating a bare address as a range containing only itself
+/// 
+/// # Arguments
+/// 
+/// * `range` - The address or range
+fn parse_ip_range(range: &str) -> Option<IpNet> {
+    match range.parse::<IpNet>() {
+        Ok(range) => Some(range),
+        Err(_) => range.parse::<IpAddr>().ok().map(IpNet::from),
+    }
+}
+
+impl ServerConfig {
+    /// Returns whether an address belongs to a trusted reverse proxy
+    /// 
+    /// # Arguments
+    /// 
+    /// * `ip` - The address
+    pub fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter()
+            .filter_map(|proxy| parse_ip_range(proxy))
+            .any(|proxy| proxy.contains(&ip))
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the file named by the `CONFIG_FILE` environment variable, or `config.toml` if it exists,
+    /// applies environment variable overrides, and validates the result
+    pub fn load() -> Result<Self> {
+        let (path, required) = match std::env::var("CONFIG_FILE") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_FILE.to_string(), false),
+        };
+
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(source) => match toml::from_str(&source) {
+                Ok(val) => Ok(val),
+                Err(e) => Err(Error::new(ErrorKind::InvalidData, format!("Invalid config file {}: {}", path, e)))
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => Ok(Config::default()),
+            Err(e) => Err(Error::new(e.kind(), format!("Failed to read config file {}: {}", path, e))),
+        }?;
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Overrides config values with those set in environment variables
+    fn apply_env(&mut self) -> Result<()> {
+        env_override("PORT", &mut self.server.port)?;
+        env_override("FRONTEND_URL", &mut self.server.frontend_url)?;
+        env_list_override("TRUSTED_PROXIES", &mut self.server.trusted_proxies);
+        env_list_override("CORS_ALLOWED_ORIGINS", &mut self.cors.allowed_origins);
+        env_list_override("CORS_ALLOWED_METHODS", &mut self.cors.allowed_methods);
+        env_override("DATABASE_MAX_CONNECTIONS", &mut self.database.max_connections)?;
+        env_override("NUM_USER_SESSIONS", &mut self.sessions.max_per_user)?;
+        env_override("SESSION_MAX_AGE", &mut self.sessions.max_age)?;
+        env_override("SESSION_IDLE_TIMEOUT", &mut self.sessions.idle_timeout)?;
+        env_override("VERIFICATION_EXPIRY", &mut self.expiry.verification)?;
+        env_override("PASSWORD_RESET_EXPIRY", &mut self.expiry.password_reset)?;
+        env_override("EMAIL_CHANGE_EXPIRY", &mut self.expiry.email_change)?;
+        env_override("ACCOUNT_DELETION_GRACE_PERIOD", &mut self.accounts.deletion_grace_period)?;
+        env_override("LOGIN_LOCKOUT_THRESHOLD", &mut self.login.lockout_threshold)?;
+        env_override("LOGIN_IP_LOCKOUT_THRESHOLD", &mut self.login.ip_lockout_threshold)?;
+        env_override("LOGIN_LOCKOUT_DURATION", &mut self.login.lockout_duration)?;
+        env_override("RATE_LIMIT_STORE", &mut self.rate_limit.store)?;
+        env_override("RATE_LIMIT_BURST", &mut self.rate_limit.burst)?;
+        env_override("RATE_LIMIT_PER_MINUTE", &mut self.rate_limit.per_minute)?;
+        env_override("EMAIL_TRANSPORT", &mut self.email.transport)?;
+        env_optional_override("EMAIL_ADDRESS", &mut self.email.from_address)?;
+        env_override("EMAIL_DIR", &mut self.email.dir)?;
+        env_optional_override("EMAIL_TEMPLATE_DIR", &mut self.email.template_dir)?;
+        env_override("EMAIL_MAX_ATTEMPTS", &mut self.email.max_attempts)?;
+        env_override("EMAIL_OUTBOX_POLL_INTERVAL", &mut self.email.poll_interval)?;
+        env_override("SMTP_HOST", &mut self.smtp.host)?;
+        env_optional_override("SMTP_PORT", &mut self.smtp.port)?;
+        env_override("SMTP_TLS", &mut self.smtp.tls)?;
+        env_optional_override("SMTP_USERNAME", &mut self.smtp.username)?;
+        env_optional_override("EMAIL_APP_PASSWORD", &mut self.smtp.password)?;
+        env_optional_override("SMTP_PASSWORD", &mut self.smtp.password)?;
+        env_override("DIGEST_INTERVAL", &mut self.notifications.digest_interval)?;
+        env_override("POLL_REPORT_THRESHOLD", &mut self.moderation.report_threshold)?;
+        env_override("WEBHOOK_TIMEOUT", &mut self.webhooks.timeout)?;
+        env_override("WEBHOOK_MAX_ATTEMPTS", &mut self.webhooks.max_attempts)?;
+        env_override("WEBHOOK_POLL_INTERVAL", &mut self.webhooks.poll_interval)?;
+        env_override("WEBHOOK_ALLOW_PRIVATE_NETWORKS", &mut self.webhooks.allow_private_networks)?;
+        env_override("SCHEDULER_JITTER", &mut self.scheduler.jitter)?;
+        env_override("SESSION_SWEEP_INTERVAL", &mut self.scheduler.expire_sessions)?;
+        env_override("CLOSE_POLLS_INTERVAL", &mut self.scheduler.close_polls)?;
+        env_override("DIGEST_SWEEP_INTERVAL", &mut self.scheduler.send_digests)?;
+
+        env_optional_override("DATABASE_URL", &mut self.database.url)?;
+
+        Ok(())
+    }
+
+    /// Checks that every config value is usable, normalizing those that can be, and reports every problem found at once
+    fn validate(&mut self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        self.server.frontend_url = self.server.frontend_url.trim_end_matches('/').to_string();
+
+        if !is_web_url(&self.server.frontend_url) {
+            problems.push(format!("server.frontend_url must be an http or https URL, not {:?}", self.server.frontend_url));
+        }
+
+        for proxy in self.server.trusted_proxies.iter().filter(|proxy| parse_ip_range(proxy).is_none()) {
+            problems.push(format!("server.trusted_proxies entry {:?} must be an IP address or CIDR range like 10.0.0.0/8", proxy));
+        }
+
+        if self.cors.allowed_origins.is_empty() {
+            problems.push("cors.allowed_origins must list at least one origin".to_string());
+        }
+
+        for origin in self.cors.allowed_origins.iter().filter(|origin| !is_origin(origin)) {
+            problems.push(format!("cors.allowed_origins entry {:?} must be an origin like https://example.com", origin));
+        }
+
+        self.cors.allowed_methods = self.cors.allowed_methods.iter().map(|method| method.to_uppercase()).collect();
+
+        if self.cors.allowed_methods.is_empty() {
+            problems.push("cors.allowed_methods must list at least one method".to_string());
+        }
+
+        for method in self.cors.allowed_methods.iter().filter(|method| Method::from_str(method).is_err()) {
+            problems.push(format!("cors.allowed_methods entry {:?} is not an HTTP method", method));
+        }
+
+        match &self.database.url {
+            Some(url) if !url.trim().is_empty() => (),
+            _ => problems.push("database.url must be set, either in the config file or by the DATABASE_URL environment variable".to_string()),
+        }
+
+        let positive = [
+            ("database.max_connections", self.database.max_connections as i64),
+            ("sessions.max_per_user", self.sessions.max_per_user),
+            ("sessions.max_age", self.sessions.max_age),
+            ("sessions.idle_timeout", self.sessions.idle_timeout),
+            ("expiry.verification", self.expiry.verification),
+            ("expiry.password_reset", self.expiry.password_reset),
+            ("expiry.email_change", self.expiry.email_change),
+            ("accounts.deletion_grace_period", self.accounts.deletion_grace_period),
+            ("login.lockout_threshold", self.login.lockout_threshold as i64),
+            ("login.ip_lockout_threshold", self.login.ip_lockout_threshold as i64),
+            ("login.lockout_duration", self.login.lockout_duration),
+            ("email.max_attempts", self.email.max_attempts as i64),
+            ("email.poll_interval", self.email.poll_interval as i64),
+            ("notifications.digest_interval", self.notifications.digest_interval),
+            ("moderation.report_threshold", self.moderation.report_threshold),
+            ("webhooks.timeout", self.webhooks.timeout as i64),
+            ("webhooks.max_attempts", self.webhooks.max_attempts as i64),
+            ("webhooks.poll_interval", self.webhooks.poll_interval as i64),
+            ("scheduler.expire_sessions", self.scheduler.expire_sessions as i64),
+            ("scheduler.prune_verifications", self.scheduler.prune_verifications as i64),
+            ("scheduler.prune_password_resets", self.scheduler.prune_password_resets as i64),
+            ("scheduler.prune_email_changes", self.scheduler.prune_email_changes as i64),
+            ("scheduler.prune_pending_logins", self.scheduler.prune_pending_logins as i64),
+            ("scheduler.prune_login_throttles", self.scheduler.prune_login_throttles as i64),
+            ("scheduler.prune_deleted_users", self.scheduler.prune_deleted_users as i64),
+            ("scheduler.prune_sent_emails", self.scheduler.prune_sent_emails as i64),
+            ("scheduler.prune_notifications", self.scheduler.prune_notifications as i64),
+            ("scheduler.prune_webhook_deliveries", self.scheduler.prune_webhook_deliveries as i64),
+            ("scheduler.close_polls", self.scheduler.close_polls as i64),
+            ("scheduler.send_digests", self.scheduler.send_digests as i64),
+        ];
+
+        for (name, value) in positive.iter().filter(|(_, value)| *value <= 0) {
+            problems.push(format!("{} must be greater than zero, not {}", name, value));
+        }
+
+        if !(1.0..).contains(&self.rate_limit.burst) {
+            problems.push(format!("rate_limit.burst must be at least 1, not {}", self.rate_limit.burst));
+        }
+
+        if self.rate_limit.per_minute.is_nan() || self.rate_limit.per_minute <= 0.0 {
+            problems.push(format!("rate_limit.per_minute must be greater than zero, not {}", self.rate_limit.per_minute));
+        }
+
+        if self.email.transport == EmailTransportKind::Smtp && self.email.from_address.as_deref().filter(|from| !from.trim().is_empty()).is_none() {
+            problems.push("email.from_address must be set, either in the config file or by the EMAIL_ADDRESS environment variable, to deliver emails through SMTP".to_string());
+        }
+
+        if self.smtp.port == Some(0) {
+            problems.push("smtp.port must be greater than zero".to_string());
+        }
+
+        if !(0.0..1.0).contains(&self.scheduler.jitter) {
+            problems.push(format!("scheduler.jitter must be at least 0 and less than 1, not {}", self.scheduler.jitter));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::InvalidInput, problems.join("\n  ")))
+        }
+    }
+
+    /// Returns the database URL, which validation ensures is set
+    pub fn database_url(&self) -> &str {
+        self.database.url.as_deref().unwrap_or("")
+    }
+}
@@ -1,18 +1,191 @@
+use sqlx::{Postgres, Transaction};
 use crate::util::DBPool;
 
-/// Initialize the database tables
+/// The schema version this version of the API expects, which is the version of the latest migration
+pub const SCHEMA_VERSION: i32 = 10;
+
+/// Applies a migration, bringing tables created by an earlier version of the API up to date
+/// 
+/// Tables created by this version already have the current schema, so every migration must also be harmless to run on them.
+/// 
+/// # Arguments
+/// 
+/// * `tx` - The transaction the migrations are applied in
+/// * `version` - The version of the migration
+async fn apply_migration(tx: &mut Transaction<'_, Postgres>, version: i32) -> Result<(), sqlx::Error> {
+    match version {
+        // Session, verification and password reset IDs are stored as hashes, and IDs stored in plaintext can no longer be used
+        1 => {
+            sqlx::query_file!("sql/migration/v1_session_id_type.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v1_delete_plaintext_session_ids.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v1_verify_id_type.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v1_delete_plaintext_verify_ids.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v1_password_reset_id_type.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v1_delete_plaintext_password_reset_ids.sql").execute(&mut *tx).await?;
+        },
+        // Users can be made admins and suspended, and polls closed and hidden
+        2 => {
+            sqlx::query_file!("sql/migration/v2_user_moderation_columns.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v2_poll_moderation_columns.sql").execute(&mut *tx).await?;
+        },
+        // Polls can limit vote changes, and a user has at most one vote on a poll, so any extra votes are kept only in the vote history
+        3 => {
+            sqlx::query_file!("sql/migration/v3_poll_max_vote_changes_column.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/init/poll_vote_history_backfill.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v3_delete_duplicate_votes.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v3_poll_vote_unique.sql").execute(&mut *tx).await?;
+        },
+        // Sessions record the device they were created on and when they were last used, and have a handle to revoke them by
+        4 => {
+            sqlx::query_file!("sql/migration/v4_session_columns.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v4_session_handle_backfill.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v4_session_handle_not_null.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v4_session_handle_unique.sql").execute(&mut *tx).await?;
+        },
+        // Users can enable two-factor authentication
+        5 => {
+            sqlx::query_file!("sql/migration/v5_user_totp_columns.sql").execute(&mut *tx).await?;
+        },
+        // Users can schedule their account for deletion
+        6 => {
+            sqlx::query_file!("sql/migration/v6_user_delete_time_column.sql").execute(&mut *tx).await?;
+        },
+        // Users can choose their locale
+        7 => {
+            sqlx::query_file!("sql/migration/v7_user_locale_column.sql").execute(&mut *tx).await?;
+        },
+        // Polls can close automatically at a set time
+        8 => {
+            sqlx::query_file!("sql/migration/v8_poll_close_time_column.sql").execute(&mut *tx).await?;
+        },
+        // Administrators can impersonate users, and sessions and audit events record the administrator doing so
+        9 => {
+            sqlx::query_file!("sql/migration/v9_session_impersonator_column.sql").execute(&mut *tx).await?;
+            sqlx::query_file!("sql/migration/v9_audit_event_impersonator_column.sql").execute(&mut *tx).await?;
+        },
+        // Login throttles are kept for any email address a login is attempted with, however long
+        10 => {
+            sqlx::query_file!("sql/migration/v10_login_throttle_subject_type.sql").execute(&mut *tx).await?;
+        },
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Applies every migration newer than the database's schema version, in order, recording each one as it is applied
+/// 
+/// The migrations are applied in a single transaction, holding a lock so instances starting at once do not apply them twice.
+/// 
+/// # Arguments
+/// 
+/// * `pool` - The database pool
+async fn migrate(pool: &DBPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query_file!("sql/migration/lock_migrations.sql").fetch_one(&mut tx).await?;
+    let version = sqlx::query_file!("sql/migration/get_schema_version.sql").fetch_one(&mut tx).await?.version;
+
+    for next_version in (version + 1)..=SCHEMA_VERSION {
+        apply_migration(&mut tx, next_version).await?;
+        sqlx::query_file!("sql/migration/set_schema_version.sql", next_version).execute(&mut tx).await?;
+    }
+
+    tx.commit().await
+}
+
+/// Initialize the database tables, migrating any created by an earlier version of the API
 /// 
 /// # Arguments
 /// 
 /// * `pool` - The database pool
 pub async fn init_db(pool: &DBPool) -> Result<(), sqlx::Error> {
+    // Tables
+    sqlx::query_file!("sql/init/schema_version.sql").fetch_all(pool).await?;
     sqlx::query_file!("sql/init/user.sql").fetch_all(pool).await?;
     sqlx::query_file!("sql/init/poll.sql").fetch_all(pool).await?;
     sqlx::query_file!("sql/init/poll_option.sql").fetch_all(pool).await?;
     sqlx::query_file!("sql/init/poll_vote.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/poll_vote_history.sql").fetch_all(pool).await?;
     sqlx::query_file!("sql/init/session.sql").fetch_all(pool).await?;
     sqlx::query_file!("sql/init/verify.sql").fetch_all(pool).await?;
     sqlx::query_file!("sql/init/password_reset.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/email_change.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/email_outbox.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/api_token.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/recovery_code.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/pending_login.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/login_throttle.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/rate_limit_bucket.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/audit_event.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/report.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/notification.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/notification_preference.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/webhook.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/webhook_delivery.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/scheduled_job.sql").fetch_all(pool).await?;
+
+    // Migrations, which must run before anything that depends on the columns they add
+    migrate(pool).await?;
+
+    // Indexes and backfills
+    sqlx::query_file!("sql/init/poll_close_time_index.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/poll_vote_time_index.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/poll_vote_history_index.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/poll_vote_history_user_index.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/poll_vote_history_backfill.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/email_outbox_due_index.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/audit_event_poll_index.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/notification_user_index.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/webhook_delivery_due_index.sql").fetch_all(pool).await?;
+    sqlx::query_file!("sql/init/webhook_delivery_webhook_index.sql").fetch_all(pool).await?;
 
     Ok(())
 }
+
+/// Connects to the database named by `DATABASE_URL` in a new schema of its own, with every table initialized, returning the pool and the schema's name
+/// 
+/// Tests that run the delivery workers use this, since the workers act on every due row and would otherwise pick up rows from other tests or a running API.
+/// 
+/// # Arguments
+/// 
+/// * `name` - A name for the schema, which is made unique
+#[cfg(test)]
+pub async fn test_pool(name: &str) -> (DBPool, String) {
+    use sqlx::Executor;
+    use sqlx::postgres::PgPoolOptions;
+
+    let schema = format!("test_{}_{}", name, &crate::util::generate_token()[..16]);
+    let search_path = format!("SET search_path TO {}", schema);
+
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .after_connect(move |conn| {
+            let search_path = search_path.clone();
+            Box::pin(async move {
+                conn.execute(search_path.as_str()).await?;
+                Ok(())
+            })
+        })
+        .connect(&std::env::var("DATABASE_URL").expect("DATABASE_URL must be set to run database tests"))
+        .await
+        .unwrap();
+
+    pool.execute(format!("CREATE SCHEMA {}", schema).as_str()).await.unwrap();
+    init_db(&pool).await.unwrap();
+
+    (pool, schema)
+}
+
+/// Drops a schema created by `test_pool`, along with everything in it
+/// 
+/// # Arguments
+/// 
+/// * `pool` - The pool returned by `test_pool`
+/// * `schema` - The name of the schema
+#[cfg(test)]
+pub async fn drop_test_schema(pool: &DBPool, schema: &str) {
+    use sqlx::Executor;
+
+    pool.execute(format!("DROP SCHEMA {} CASCADE", schema).as_str()).await.unwrap();
+}
@@ -1,87 +1,332 @@
 use lettre::smtp::authentication::Credentials;
-use lettre::{SmtpClient, Transport};
+use lettre::smtp::ConnectionReuseParameters;
+use lettre::{SmtpClient, SmtpTransport, ClientSecurity, ClientTlsParameters, SendableEmail, Transport};
 use lettre_email::EmailBuilder;
-use std::io::{Result, Error, ErrorKind};
-use std::collections::HashMap;
+use native_tls::TlsConnector;
+use serde::Deserialize;
+use std::io::{Result, Error};
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, Arc, PoisonError};
+use crate::config::{Config, EmailTransportKind};
+use crate::util::generate_token;
 
-/// Sends an email using the app email account
+/// The name emails are sent from
+const EMAIL_FROM_NAME: &str = "GreenPoll";
+
+/// The address the file transport writes emails from if none is configured
+const DEFAULT_EMAIL_FROM: &str = "noreply@greenpoll.local";
+
+/// An email ready to be sent
+#[derive(Clone)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// A way of delivering emails
+pub trait EmailTransport: Send + Sync {
+    /// Delivers an email
+    /// 
+    /// # Arguments
+    /// 
+    /// * `message` - The email to deliver
+    fn send(&self, message: &EmailMessage) -> Result<()>;
+
+    /// Checks the transport is configured well enough to deliver emails, without delivering one
+    fn check(&self) -> Result<()>;
+}
+
+/// Builds an email into its sendable form
 /// 
 /// # Arguments
 /// 
-/// * `email_to` - The address to send the email to
-/// * `email_subject` - The subject line of the email
-/// * `email_html` - The HTML content to send in the email body
-/// * `email_text` - The text content to send in the email body
-pub fn send_email(
-    email_to: String,
-    email_subject: String,
-    email_html: String,
-    email_text: String
-) -> Result<lettre::smtp::response::Response> {
-    let email_address = std::env::var("EMAIL_ADDRESS")
-        .expect("EMAIL_ADDRESS must exist");    
-    let email_password = std::env::var("EMAIL_APP_PASSWORD")
-        .expect("EMAIL_APP_PASSWORD must exist");
-
-    let email = match EmailBuilder::new()
-        .from((email_address.clone(), "GreenPoll"))
-        .to(email_to)
-        .subject(email_subject)
-        .alternative(email_html, email_text)
+/// * `from` - The address to send the email from
+/// * `message` - The email
+fn build_email(from: &str, message: &EmailMessage) -> Result<SendableEmail> {
+    match EmailBuilder::new()
+        .from((from.to_string(), EMAIL_FROM_NAME))
+        .to(message.to.clone())
+        .subject(message.subject.clone())
+        .alternative(message.html.clone(), message.text.clone())
         .build() {
+            Ok(val) => Ok(val.into()),
+            Err(e) => Err(Error::other(format!("Failed to build email: {}", e)))
+        }
+}
+
+/// How an SMTP connection is secured
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTlsMode {
+    /// The connection is wrapped in TLS from the start, usually on port 465
+    Wrapper,
+    /// The connection is upgraded with `STARTTLS`, usually on port 587
+    StartTls,
+    /// The connection is upgraded with `STARTTLS` if the server supports it
+    Opportunistic,
+    /// The connection is not encrypted, which is only suitable for a local relay
+    None,
+}
+
+impl FromStr for SmtpTlsMode {
+    type Err = ();
+
+    fn from_str(name: &str) -> std::result::Result<Self, ()> {
+        match name {
+            "wrapper" => Ok(SmtpTlsMode::Wrapper),
+            "starttls" => Ok(SmtpTlsMode::StartTls),
+            "opportunistic" => Ok(SmtpTlsMode::Opportunistic),
+            "none" => Ok(SmtpTlsMode::None),
+            _ => Err(()),
+        }
+    }
+}
+
+impl SmtpTlsMode {
+    /// Returns the port usually used with the TLS mode
+    fn default_port(self) -> u16 {
+        match self {
+            SmtpTlsMode::Wrapper => lettre::smtp::SUBMISSIONS_PORT,
+            SmtpTlsMode::StartTls | SmtpTlsMode::Opportunistic => lettre::smtp::SUBMISSION_PORT,
+            SmtpTlsMode::None => lettre::smtp::SMTP_PORT,
+        }
+    }
+}
+
+/// Sends emails through an SMTP server, connecting on the first message and reusing the connection for later ones
+pub struct SmtpEmailTransport {
+    from: String,
+    host: String,
+    port: u16,
+    security: ClientSecurity,
+    credentials: Option<Credentials>,
+    transport: Mutex<Option<SmtpTransport>>,
+}
+
+impl SmtpEmailTransport {
+    /// Creates an SMTP transport
+    /// 
+    /// # Arguments
+    /// 
+    /// * `from` - The address to send emails from
+    /// * `host` - The SMTP server
+    /// * `port` - The port of the SMTP server
+    /// * `tls_mode` - How the connection is secured
+    /// * `credentials` - The username and password to log in to the server with, if it requires them
+    pub fn new(from: String, host: String, port: u16, tls_mode: SmtpTlsMode, credentials: Option<(String, String)>) -> Result<Self> {
+        let security = if tls_mode == SmtpTlsMode::None {
+            ClientSecurity::None
+        } else {
+            let connector = match TlsConnector::new() {
+                Ok(val) => Ok(val),
+                Err(e) => Err(Error::other(format!("Failed to create TLS connector: {}", e)))
+            }?;
+            let tls_parameters = ClientTlsParameters::new(host.clone(), connector);
+
+            match tls_mode {
+                SmtpTlsMode::Wrapper => ClientSecurity::Wrapper(tls_parameters),
+                SmtpTlsMode::StartTls => ClientSecurity::Required(tls_parameters),
+                _ => ClientSecurity::Opportunistic(tls_parameters),
+            }
+        };
+
+        Ok(Self {
+            from,
+            host,
+            port,
+            security,
+            credentials: credentials.map(|(username, password)| Credentials::new(username, password)),
+            transport: Mutex::new(None),
+        })
+    }
+
+    /// Creates the underlying SMTP transport
+    fn connect(&self) -> Result<SmtpTransport> {
+        let mut client = match SmtpClient::new((&self.host[..], self.port), self.security.clone()) {
             Ok(val) => Ok(val),
-            Err(e) => Err(Error::new(ErrorKind::Other, format!("Failed to build email: {}", e)))
-        }?;
+            Err(e) => Err(Error::other(format!("Failed to create email client: {}", e)))
+        }?
+            .connection_reuse(ConnectionReuseParameters::ReuseUnlimited);
 
-    let creds = Credentials::new(email_address.clone(), email_password.clone());
+        if let Some(credentials) = &self.credentials {
+            client = client.credentials(credentials.clone());
+        }
 
-    let mut mailer = match SmtpClient::new_simple("smtp.gmail.com") {
-        Ok(val) => Ok(val),
-        Err(e) => Err(Error::new(ErrorKind::Other, format!("Failed to create email client: {}", e)))
-    }?
-        .credentials(creds)
-        .transport();
+        Ok(client.transport())
+    }
+}
 
-    let result = match mailer.send(email.into()) {
-        Ok(val) => Ok(val),
-        Err(e) => Err(Error::new(ErrorKind::Other, format!("Failed to send email: {}", e)))
-    }?;
+impl EmailTransport for SmtpEmailTransport {
+    fn send(&self, message: &EmailMessage) -> Result<()> {
+        let email = build_email(&self.from, message)?;
+        let mut transport = match self.transport.lock() {
+            Ok(transport) => transport,
+            Err(poisoned) => {
+                // A send panicked part way through, so the connection may be mid-conversation and must not be reused
+                let mut transport = poisoned.into_inner();
+                *transport = None;
+                transport
+            },
+        };
 
-    Ok(result)
+        if transport.is_none() {
+            *transport = Some(self.connect()?);
+        }
+
+        match transport.as_mut().unwrap().send(email) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // Drop the connection so the next message starts afresh
+                *transport = None;
+                Err(Error::other(format!("Failed to send email: {}", e)))
+            }
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        match (&self.host[..], self.port).to_socket_addrs() {
+            Ok(mut addrs) => match addrs.next() {
+                Some(_) => Ok(()),
+                None => Err(Error::other(format!("SMTP server {} has no addresses", self.host))),
+            },
+            Err(e) => Err(Error::other(format!("Failed to resolve SMTP server {}: {}", self.host, e))),
+        }
+    }
 }
 
-/// Sends an email in the `/emails` directory, replacing placeholder values in the HTML and text representations of the email
+/// Writes emails to a maildir instead of sending them, for development
+pub struct FileEmailTransport {
+    from: String,
+    dir: PathBuf,
+}
+
+impl FileEmailTransport {
+    /// Creates a file transport, creating the maildir if it does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `from` - The address the emails are from
+    /// * `dir` - The maildir to write emails to
+    pub fn new(from: String, dir: PathBuf) -> Result<Self> {
+        for subdir in ["tmp", "new", "cur"].iter() {
+            std::fs::create_dir_all(dir.join(subdir))?;
+        }
+
+        Ok(Self { from, dir })
+    }
+}
+
+impl EmailTransport for FileEmailTransport {
+    fn send(&self, message: &EmailMessage) -> Result<()> {
+        let email = build_email(&self.from, message)?.message_to_string()?;
+        let file_name = format!("{}.{}.greenpoll", time::OffsetDateTime::now_utc().unix_timestamp(), &generate_token()[..16]);
+
+        // Write to tmp then move into new, so mail readers never see a partial message
+        let tmp_path = self.dir.join("tmp").join(&file_name);
+        std::fs::write(&tmp_path, email)?;
+        std::fs::rename(&tmp_path, self.dir.join("new").join(&file_name))?;
+
+        Ok(())
+    }
+
+    fn check(&self) -> Result<()> {
+        if ["tmp", "new"].iter().all(|subdir| self.dir.join(subdir).is_dir()) {
+            Ok(())
+        } else {
+            Err(Error::other(format!("Maildir {} is missing", self.dir.display())))
+        }
+    }
+}
+
+/// Keeps emails in memory instead of sending them, so tests can check what would have been sent
+#[derive(Default)]
+pub struct MemoryEmailTransport {
+    sent: Mutex<Vec<EmailMessage>>,
+}
+
+impl MemoryEmailTransport {
+    /// Returns every email sent so far, oldest first
+    #[cfg(test)]
+    pub fn sent(&self) -> Vec<EmailMessage> {
+        self.sent.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+impl EmailTransport for MemoryEmailTransport {
+    fn send(&self, message: &EmailMessage) -> Result<()> {
+        self.sent.lock().unwrap_or_else(PoisonError::into_inner).push(message.clone());
+
+        Ok(())
+    }
+
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates the email transport chosen by the config
 /// 
 /// # Arguments
 /// 
-/// * `email_to` - The address to send the email to
-/// * `email_subject` - The subject line of the email
-/// * `email_name` - The name of the email files in the emails directory
-/// * `options` - Key-value pairs of the placeholders and their values
-/// 
-/// # Placeholder example
-/// 
-/// In the HTML and text email files, the following could be used to insert placeholder values:
-/// 
-/// `Hello, {firstname} {lastname}. You have received the message: {message}.`
-/// 
-/// The `options` parameter could then contain values for `firstname`, `lastname`, and `message`.
-pub fn send_formatted_email(
-    email_to: String,
-    email_subject: String,
-    email_name: String,
-    options: HashMap<&str, &str>
-) -> Result<lettre::smtp::response::Response> {
-    let mut email_html = std::fs::read_to_string(format!("emails/{}.html", email_name))?;
-    let mut email_text = std::fs::read_to_string(format!("emails/{}.txt", email_name))?;
+/// * `config` - The configuration
+pub fn transport_from_config(config: &Config) -> Result<Arc<dyn EmailTransport>> {
+    match config.email.transport {
+        EmailTransportKind::Smtp => {
+            let from = config.email.from_address.clone().unwrap_or_default();
+            let port = config.smtp.port.unwrap_or_else(|| config.smtp.tls.default_port());
+            let username = config.smtp.username.clone().unwrap_or_else(|| from.clone());
+            let credentials = config.smtp.password.clone().map(|password| (username, password));
+
+            let transport = SmtpEmailTransport::new(from, config.smtp.host.clone(), port, config.smtp.tls, credentials)?;
+            Ok(Arc::new(transport))
+        },
+        EmailTransportKind::File => {
+            let from = config.email.from_address.clone().unwrap_or_else(|| DEFAULT_EMAIL_FROM.to_string());
+            Ok(Arc::new(FileEmailTransport::new(from, PathBuf::from(&config.email.dir))?))
+        },
+        EmailTransportKind::Memory => Ok(Arc::new(MemoryEmailTransport::default())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_message(to: &str) -> EmailMessage {
+        EmailMessage {
+            to: to.to_string(),
+            subject: "Subject".to_string(),
+            html: "<p>Body</p>".to_string(),
+            text: "Body".to_string(),
+        }
+    }
+
+    #[test]
+    fn memory_transport_keeps_sent_emails_in_order() {
+        let transport = MemoryEmailTransport::default();
+        transport.send(&test_message("first@example.com")).unwrap();
+        transport.send(&test_message("second@example.com")).unwrap();
 
-    for (key, value) in options.iter() {
-        let key_search = format!("{{{}}}", key);
-        email_html = str::replace(&email_html[..], &key_search[..], value);
-        email_text = str::replace(&email_text[..], &key_search[..], value);
+        let recipients: Vec<String> = transport.sent().into_iter().map(|message| message.to).collect();
+        assert_eq!(recipients, vec!["first@example.com", "second@example.com"]);
     }
 
-    let result = send_email(email_to, email_subject, email_html, email_text)?;
+    #[test]
+    fn memory_transport_survives_a_poisoned_lock() {
+        let transport = Arc::new(MemoryEmailTransport::default());
+        transport.send(&test_message("first@example.com")).unwrap();
 
-    Ok(result)
+        let poisoner = transport.clone();
+        let _ = std::thread::spawn(move || {
+            let _sent = poisoner.sent.lock().unwrap();
+            panic!("poison the lock");
+        }).join();
+
+        transport.send(&test_message("second@example.com")).unwrap();
+        assert_eq!(transport.sent().len(), 2);
+    }
 }
@@ -0,0 +1,257 @@
+use actix_web::{HttpRequest, Error};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::{Future, Ready, ready};
+use std::io;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use crate::auth::authenticated_user_locale;
+
+/// The locale used when no other is requested, which every message must have a translation for
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// The message catalogues built into the binary, as pairs of locale and JSON object mapping message IDs to translations
+const CATALOGUES: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.json")),
+    ("es", include_str!("../locales/es.json")),
+];
+
+/// The parsed message catalogues, set once at startup
+static MESSAGES: OnceLock<HashMap<&'static str, HashMap<String, String>>> = OnceLock::new();
+
+tokio::task_local! {
+    /// The locale of the request being handled
+    static REQUEST_LOCALE: &'static str;
+}
+
+/// Parses the message catalogues, failing if any is malformed or has messages missing from the default locale
+pub fn load_catalogues() -> io::Result<()> {
+    let mut messages = HashMap::new();
+
+    for (locale, source) in CATALOGUES.iter() {
+        let catalogue: HashMap<String, String> = match serde_json::from_str(source) {
+            Ok(val) => Ok(val),
+            Err(e) => Err(io::Error::other(format!("Invalid message catalogue for locale {}: {}", locale, e)))
+        }?;
+
+        messages.insert(*locale, catalogue);
+    }
+
+    let default_messages = &messages[DEFAULT_LOCALE];
+
+    for (locale, catalogue) in messages.iter() {
+        if let Some(id) = catalogue.keys().find(|id| !default_messages.contains_key(*id)) {
+            return Err(io::Error::other(format!("Message {} in locale {} does not exist in locale {}", id, locale, DEFAULT_LOCALE)));
+        }
+    }
+
+    match MESSAGES.set(messages) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(io::Error::other("Message catalogues have already been loaded")),
+    }
+}
+
+/// Returns every supported locale
+pub fn supported_locales() -> impl Iterator<Item = &'static str> {
+    CATALOGUES.iter().map(|(locale, _)| *locale)
+}
+
+/// Returns the supported locale matching a language tag, ignoring any region, so `es-MX` matches `es`
+/// 
+/// # Arguments
+/// 
+/// * `tag` - The language tag
+pub fn supported_locale(tag: &str) -> Option<&'static str> {
+    let language = tag.trim().split(['-', '_']).next().unwrap_or("").to_lowercase();
+
+    supported_locales().find(|locale| *locale == language)
+}
+
+/// Returns the supported locale most preferred by an `Accept-Language` header
+/// 
+/// # Arguments
+/// 
+/// * `accept_language` - The value of the header, such as `es-MX,es;q=0.9,en;q=0.8`
+pub fn negotiate_locale(accept_language: &str) -> Option<&'static str> {
+    let mut preferences: Vec<(&str, f32)> = accept_language.split(',')
+        .map(|preference| {
+            let mut parts = preference.split(';');
+            let tag = parts.next().unwrap_or("").trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|quality| quality.parse().ok())
+                .unwrap_or(1.0);
+
+            (tag, quality)
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+
+    // Stable, so equally preferred languages keep the order they were listed in
+    preferences.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    preferences.into_iter().find_map(|(tag, _)| supported_locale(tag))
+}
+
+/// Builds an error message carrying values for the placeholders in its translations
+/// 
+/// # Arguments
+/// 
+/// * `id` - The ID of the message
+/// * `args` - Key-value pairs of the placeholders and their values
+pub fn message_with_args(id: &str, args: &[(&str, &str)]) -> String {
+    let args: Vec<String> = args.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+
+    format!("{}?{}", id, args.join("&"))
+}
+
+/// Translates a message into a locale, falling back to the default locale, and returns messages that are not in the catalogue unchanged
+/// 
+/// # Arguments
+/// 
+/// * `locale` - The locale to translate into
+/// * `message` - The ID of the message, optionally followed by placeholder values as built by `message_with_args`
+pub fn translate(locale: &str, message: &str) -> String {
+    let (id, args) = match message.split_once('?') {
+        Some((id, args)) => (id, args),
+        None => (message, ""),
+    };
+    let args: Vec<(&str, &str)> = args.split('&').filter_map(|arg| arg.split_once('=')).collect();
+
+    translate_with_args(locale, id, &args).unwrap_or_else(|| message.to_string())
+}
+
+/// Translates a message with placeholder values into a locale, falling back to the default locale, or returns nothing if the message is not in the catalogue
+/// 
+/// Unlike `message_with_args`, the values may contain any characters, so this suits user-provided text such as poll titles.
+/// 
+/// # Arguments
+/// 
+/// * `locale` - The locale to translate into
+/// * `id` - The ID of the message
+/// * `args` - Key-value pairs of the placeholders and their values
+pub fn translate_with_args(locale: &str, id: &str, args: &[(&str, &str)]) -> Option<String> {
+    let messages = MESSAGES.get()?;
+
+    let translation = messages.get(locale)
+        .and_then(|catalogue| catalogue.get(id))
+        .or_else(|| messages[DEFAULT_LOCALE].get(id))?;
+
+    Some(args.iter().fold(translation.clone(), |translation, (key, value)| translation.replace(&format!("{{{}}}", key), value)))
+}
+
+/// Returns the locale of the request being handled, or the default locale outside of a request
+pub fn current_locale() -> &'static str {
+    REQUEST_LOCALE.try_with(|locale| *locale).unwrap_or(DEFAULT_LOCALE)
+}
+
+/// Returns a user's chosen locale if it is supported, otherwise the locale of the request being handled
+/// 
+/// # Arguments
+/// 
+/// * `preference` - The locale the user has chosen, if any
+pub fn preferred_locale(preference: Option<&str>) -> &'static str {
+    preference.and_then(supported_locale).unwrap_or_else(current_locale)
+}
+
+/// Translates a message into the locale of the request being handled
+/// 
+/// # Arguments
+/// 
+/// * `message` - The ID of the message, optionally followed by placeholder values
+pub fn localize(message: &str) -> String {
+    translate(current_locale(), message)
+}
+
+/// Resolves the locale of a request from its `Accept-Language` header alone, for responses sent before the request is authenticated
+/// 
+/// # Arguments
+/// 
+/// * `req` - The HTTP request object
+pub fn accept_language_locale(req: &HttpRequest) -> &'static str {
+    req.headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|accept_language| accept_language.to_str().ok())
+        .and_then(negotiate_locale)
+        .unwrap_or(DEFAULT_LOCALE)
+}
+
+/// Resolves the locale of a request from the authenticated user's preference, then the `Accept-Language` header
+/// 
+/// # Arguments
+/// 
+/// * `req` - The HTTP request object
+async fn request_locale(req: &HttpRequest) -> &'static str {
+    if let Some(locale) = authenticated_user_locale(req).await.and_then(|locale| supported_locale(&locale)) {
+        return locale;
+    }
+
+    accept_language_locale(req)
+}
+
+/// Middleware that resolves the locale of each request, so messages can be translated while it is handled
+pub struct Localizer;
+
+impl<S, B> Transform<S> for Localizer
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = LocalizerMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LocalizerMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        }))
+    }
+}
+
+/// The localization middleware wrapped around a service
+pub struct LocalizerMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for LocalizerMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let (http_req, payload) = req.into_parts();
+            let locale = request_locale(&http_req).await;
+
+            let req = match ServiceRequest::from_parts(http_req, payload) {
+                Ok(req) => req,
+                Err((http_req, _)) => return Ok(ServiceResponse::from_err(ErrorInternalServerError("Failed to resume request"), http_req)),
+            };
+
+            let res = service.borrow_mut().call(req);
+
+            REQUEST_LOCALE.scope(locale, res).await
+        })
+    }
+}
@@ -1,16 +1,30 @@
 use actix_web::{App, HttpServer, HttpResponse, Result, web, get};
+use actix_web::dev::Service;
 use actix_web::http::header;
 use actix_cors::Cors;
 use sqlx::postgres::PgPoolOptions;
-use std::sync::{Mutex, Arc};
+use std::sync::Arc;
 
+mod config;
 mod util;
+mod auth;
+mod rate_limit;
+mod metrics;
 mod dbinit;
 mod emailer;
+mod webhooks;
+mod scheduler;
+mod i18n;
+mod templates;
 mod routes;
 mod services;
 
-use util::{AppData, FRONTEND_URL};
+use emailer::EmailTransport;
+use config::Config;
+use util::{AppData, DBPool, renew_session_cookie};
+use i18n::Localizer;
+use rate_limit::{RateLimiter, RateLimitStore, rate_limit_bucket_max_age};
+use metrics::RequestMetrics;
 
 /// Index route
 #[get("/")]
@@ -23,23 +37,78 @@ async fn not_found() -> Result<HttpResponse> {
     Ok(HttpResponse::NotFound().json("404 not found"))
 }
 
+/// Periodically forgets rate limit buckets that have refilled
+/// 
+/// # Arguments
+/// 
+/// * `store` - Where rate limit buckets are kept
+/// * `max_age` - The number of seconds after which an unused bucket has refilled
+async fn sweep_rate_limit_buckets(store: RateLimitStore, max_age: i64) {
+    let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(max_age as u64));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = store.prune(max_age).await {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Periodically delivers queued emails that are due, at the configured poll interval
+/// 
+/// # Arguments
+/// 
+/// * `pool` - The database pool
+/// * `config` - The configuration
+/// * `email_transport` - The transport to deliver emails with
+async fn deliver_emails(pool: DBPool, config: Arc<Config>, email_transport: Arc<dyn EmailTransport>) {
+    let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(config.email.poll_interval));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = services::email_outbox_service::deliver_due_emails(&pool, &config, &email_transport).await {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Periodically delivers webhook events that are due, at the configured poll interval
+/// 
+/// # Arguments
+/// 
+/// * `pool` - The database pool
+/// * `config` - The configuration
+async fn deliver_webhooks(pool: DBPool, config: Arc<Config>) {
+    let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(config.webhooks.poll_interval));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = services::webhook_service::deliver_due_webhooks(&pool, &config).await {
+            eprintln!("{}", e);
+        }
+    }
+}
+
 /// Main function
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Get port
-    let port = std::env::var("PORT")
-        .unwrap_or_else(|_| "3000".to_string())
-        .parse()
-        .expect("PORT must be a number");
-
-    // Get database URL
-    let db_url = std::env::var("DATABASE_URL")
-        .expect("DATABASE_URL must exist");
+    // Load the configuration, refusing to start if any of it is invalid
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("Invalid configuration:\n  {}", e);
+            std::process::exit(1);
+        }
+    };
+    let port = config.server.port;
 
     // Create database pool
     let pool = PgPoolOptions::new()
-        .max_connections(20)
-        .connect(&db_url[..])
+        .max_connections(config.database.max_connections)
+        .connect(config.database_url())
         .await
         .expect("Failed to create database pool");
 
@@ -48,26 +117,66 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to initialize database");
 
-    // Application data
-    let app_data = Arc::new(Mutex::new(AppData { pool }));
+    // Load the message catalogues
+    i18n::load_catalogues()
+        .expect("Failed to load message catalogues");
+
+    // Compile the email templates, making sure they all render
+    templates::load_templates(config.email.template_dir.as_deref())
+        .expect("Failed to load email templates");
+
+    // Choose how emails are delivered, and deliver queued emails in the background
+    let email_transport = emailer::transport_from_config(&config)
+        .expect("Failed to configure email transport");
+    actix_web::rt::spawn(deliver_emails(pool.clone(), config.clone(), email_transport.clone()));
+
+    // Run pruning and maintenance jobs in the background, such as expiring sessions, closing polls and sending digests
+    scheduler::start(&pool, &config);
+
+    // Deliver poll events to webhooks in the background
+    actix_web::rt::spawn(deliver_webhooks(pool.clone(), config.clone()));
+
+    // Choose where rate limit buckets are kept, and forget them once they refill
+    let rate_limit_store = RateLimitStore::from_config(&pool, &config.rate_limit);
+    actix_web::rt::spawn(sweep_rate_limit_buckets(rate_limit_store.clone(), rate_limit_bucket_max_age(&config.rate_limit)));
+
+    // Application data, shared between workers without locking
+    let app_data = web::Data::new(AppData { pool, config: config.clone(), email_transport });
 
     // Create HTTP server
     let server = HttpServer::new(move || {
-            let cors = Cors::default()
-                .allowed_origin(FRONTEND_URL)
-                .allowed_origin("http://localhost:3000")
-                .allowed_methods(vec!["GET", "POST"])
+            let cors = config.cors.allowed_origins.iter()
+                .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+                .allowed_methods(config.cors.allowed_methods.iter().map(|method| method.as_str()))
                 .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT, header::CONTENT_TYPE, header::ACCESS_CONTROL_ALLOW_CREDENTIALS])
                 .supports_credentials();
 
             App::new()
+                .wrap_fn(|req, srv| {
+                    let res = srv.call(req);
+
+                    async move {
+                        let mut res = res.await?;
+                        renew_session_cookie(&mut res);
+
+                        Ok(res)
+                    }
+                })
+                .wrap(Localizer)
+                // Rate limit before anything authenticates the request, but inside CORS and metrics so limited responses are readable and counted
+                .wrap(RateLimiter::new(rate_limit_store.clone(), &config.rate_limit))
                 .wrap(cors)
-                .data(app_data.clone())
+                .wrap(RequestMetrics)
+                .app_data(app_data.clone())
                 .service(index)
+                .service(routes::health_routes::healthz)
+                .service(routes::health_routes::readyz)
+                .service(routes::health_routes::metrics)
                 .service(routes::user_routes::get_user_info)
                 .service(routes::user_routes::get_specific_user_info)
                 .service(routes::user_routes::set_username)
                 .service(routes::user_routes::set_password)
+                .service(routes::user_routes::set_locale)
                 .service(routes::user_routes::get_user_polls)
                 .service(routes::poll_routes::create_poll)
                 .service(routes::poll_routes::get_poll_info)
@@ -77,6 +186,12 @@ async fn main() -> std::io::Result<()> {
                 .service(routes::poll_routes::set_poll_title)
                 .service(routes::poll_routes::set_poll_description)
                 .service(routes::poll_routes::delete_poll)
+                .service(routes::poll_routes::invite_to_poll)
+                .service(routes::poll_routes::get_poll_history)
+                .service(routes::poll_routes::set_poll_max_vote_changes)
+                .service(routes::poll_routes::set_poll_close_time)
+                .service(routes::poll_routes::get_poll_vote_timeline)
+                .service(routes::poll_routes::get_poll_vote_analytics)
                 .service(routes::poll_option_routes::create_poll_option)
                 .service(routes::poll_option_routes::get_poll_option_info)
                 .service(routes::poll_option_routes::set_poll_option_value)
@@ -86,14 +201,58 @@ async fn main() -> std::io::Result<()> {
                 .service(routes::poll_vote_routes::poll_unvote)
                 .service(routes::poll_vote_routes::get_poll_vote_poll)
                 .service(routes::poll_vote_routes::get_user_vote)
+                .service(routes::poll_vote_routes::get_user_vote_history)
                 .service(routes::login_register_routes::register)
                 .service(routes::login_register_routes::login)
                 .service(routes::login_register_routes::logout)
                 .service(routes::login_register_routes::logout_everywhere)
+                .service(routes::two_factor_routes::login_two_factor)
+                .service(routes::two_factor_routes::enable_two_factor)
+                .service(routes::two_factor_routes::confirm_two_factor)
+                .service(routes::two_factor_routes::disable_two_factor)
+                .service(routes::two_factor_routes::regenerate_recovery_codes)
+                .service(routes::session_routes::get_sessions)
+                .service(routes::session_routes::revoke_session)
+                .service(routes::api_token_routes::create_api_token)
+                .service(routes::api_token_routes::get_api_tokens)
+                .service(routes::api_token_routes::revoke_api_token)
                 .service(routes::verify_routes::verify_account)
                 .service(routes::password_reset_routes::request_password_reset)
                 .service(routes::password_reset_routes::password_reset_exists)
                 .service(routes::password_reset_routes::reset_password)
+                .service(routes::email_change_routes::change_email)
+                .service(routes::email_change_routes::confirm_email_change)
+                .service(routes::account_routes::delete_account)
+                .service(routes::account_routes::cancel_account_deletion)
+                .service(routes::account_routes::export_user_data)
+                .service(routes::admin_routes::get_all_users)
+                .service(routes::admin_routes::search_users)
+                .service(routes::admin_routes::suspend_user)
+                .service(routes::admin_routes::unsuspend_user)
+                .service(routes::admin_routes::admin_delete_poll)
+                .service(routes::admin_routes::force_close_poll)
+                .service(routes::admin_routes::impersonate_user)
+                .service(routes::admin_routes::end_impersonation)
+                .service(routes::admin_routes::get_audit_events)
+                .service(routes::admin_routes::get_reports)
+                .service(routes::admin_routes::action_report)
+                .service(routes::admin_routes::dismiss_report)
+                .service(routes::admin_routes::get_failed_emails)
+                .service(routes::admin_routes::get_scheduled_jobs)
+                .service(routes::report_routes::report_poll)
+                .service(routes::report_routes::report_poll_option)
+                .service(routes::report_routes::report_user)
+                .service(routes::notification_routes::get_notifications)
+                .service(routes::notification_routes::get_unread_notification_count)
+                .service(routes::notification_routes::mark_notification_read)
+                .service(routes::notification_routes::mark_all_notifications_read)
+                .service(routes::notification_routes::get_notification_preferences)
+                .service(routes::notification_routes::set_notification_preferences)
+                .service(routes::webhook_routes::create_webhook)
+                .service(routes::webhook_routes::get_webhooks)
+                .service(routes::webhook_routes::delete_webhook)
+                .service(routes::webhook_routes::get_webhook_deliveries)
+                .service(routes::webhook_routes::test_webhook)
                 .default_service(web::route().to(not_found))
         })
         .bind(("0.0.0.0", port))?
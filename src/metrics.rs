@@ -0,0 +1,253 @@
+use actix_web::Error;
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::{Future, Ready, ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use crate::util::DBPool;
+
+/// The metrics collected since the process started
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// The upper bounds, in seconds, of the request latency histogram buckets
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The route label given to requests that did not match a route, so unknown paths cannot create new series
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// A Prometheus histogram with the request latency buckets
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    /// Records an observation
+    /// 
+    /// # Arguments
+    /// 
+    /// * `value` - The observed value
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// The requests served by a single route
+#[derive(Default)]
+struct RouteMetrics {
+    responses: BTreeMap<u16, u64>,
+    latency: Histogram,
+}
+
+/// Every metric collected by the process, other than those read from the database pool when scraped
+#[derive(Default)]
+struct Metrics {
+    routes: Mutex<BTreeMap<String, RouteMetrics>>,
+    votes_cast: AtomicU64,
+    emails_sent: AtomicU64,
+    emails_failed: AtomicU64,
+    job_runs: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+}
+
+/// Returns the metrics collected by the process
+fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::default)
+}
+
+/// Records a request that was served
+/// 
+/// # Arguments
+/// 
+/// * `route` - The pattern of the route that served the request
+/// * `status` - The status code of the response
+/// * `seconds` - How long the request took to serve
+fn record_request(route: &str, status: u16, seconds: f64) {
+    let mut routes = metrics().routes.lock().unwrap();
+    let route = routes.entry(route.to_string()).or_default();
+
+    *route.responses.entry(status).or_insert(0) += 1;
+    route.latency.observe(seconds);
+}
+
+/// Records a vote being cast, including a change to an existing vote
+pub fn record_vote() {
+    metrics().votes_cast.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records an email being delivered
+pub fn record_email_sent() {
+    metrics().emails_sent.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records an attempt to deliver an email failing, whether or not it will be retried
+pub fn record_email_failed() {
+    metrics().emails_failed.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records the outcome of a turn of a background job
+/// 
+/// # Arguments
+/// 
+/// * `job` - The name of the job
+/// * `outcome` - Either `success`, `failure` or `skipped`
+pub fn record_job_run(job: &'static str, outcome: &'static str) {
+    *metrics().job_runs.lock().unwrap().entry((job, outcome)).or_insert(0) += 1;
+}
+
+/// Escapes a value for use as a Prometheus label
+/// 
+/// # Arguments
+/// 
+/// * `value` - The label value
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Writes the header describing a metric
+/// 
+/// # Arguments
+/// 
+/// * `out` - The text being written
+/// * `name` - The name of the metric
+/// * `kind` - The type of the metric
+/// * `help` - A description of the metric
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+/// Renders every metric in the Prometheus text exposition format
+/// 
+/// # Arguments
+/// 
+/// * `pool` - The database pool, whose utilisation is reported
+/// * `max_connections` - The most connections the pool may open
+pub fn render(pool: &DBPool, max_connections: u32) -> String {
+    let metrics = metrics();
+    let mut out = String::new();
+
+    {
+        let routes = metrics.routes.lock().unwrap();
+
+        write_header(&mut out, "greenpoll_http_requests_total", "counter", "HTTP requests served, by route and status code.");
+        for (route, route_metrics) in routes.iter() {
+            for (status, count) in route_metrics.responses.iter() {
+                writeln!(out, "greenpoll_http_requests_total{{route=\"{}\",status=\"{}\"}} {}", escape_label(route), status, count).unwrap();
+            }
+        }
+
+        write_header(&mut out, "greenpoll_http_request_duration_seconds", "histogram", "Time taken to serve HTTP requests, by route.");
+        for (route, route_metrics) in routes.iter() {
+            let route = escape_label(route);
+            let latency = &route_metrics.latency;
+
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(latency.buckets.iter()) {
+                writeln!(out, "greenpoll_http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}", route, bound, count).unwrap();
+            }
+            writeln!(out, "greenpoll_http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}", route, latency.count).unwrap();
+            writeln!(out, "greenpoll_http_request_duration_seconds_sum{{route=\"{}\"}} {}", route, latency.sum).unwrap();
+            writeln!(out, "greenpoll_http_request_duration_seconds_count{{route=\"{}\"}} {}", route, latency.count).unwrap();
+        }
+    }
+
+    write_header(&mut out, "greenpoll_db_pool_connections", "gauge", "Connections open in the database pool.");
+    writeln!(out, "greenpoll_db_pool_connections {}", pool.size()).unwrap();
+    write_header(&mut out, "greenpoll_db_pool_idle_connections", "gauge", "Open connections in the database pool that are not in use.");
+    writeln!(out, "greenpoll_db_pool_idle_connections {}", pool.num_idle()).unwrap();
+    write_header(&mut out, "greenpoll_db_pool_max_connections", "gauge", "The most connections the database pool may open.");
+    writeln!(out, "greenpoll_db_pool_max_connections {}", max_connections).unwrap();
+
+    write_header(&mut out, "greenpoll_votes_cast_total", "counter", "Votes cast, including changes to existing votes.");
+    writeln!(out, "greenpoll_votes_cast_total {}", metrics.votes_cast.load(Ordering::Relaxed)).unwrap();
+
+    write_header(&mut out, "greenpoll_emails_sent_total", "counter", "Emails delivered.");
+    writeln!(out, "greenpoll_emails_sent_total {}", metrics.emails_sent.load(Ordering::Relaxed)).unwrap();
+    write_header(&mut out, "greenpoll_emails_failed_total", "counter", "Failed attempts to deliver emails, including ones that will be retried.");
+    writeln!(out, "greenpoll_emails_failed_total {}", metrics.emails_failed.load(Ordering::Relaxed)).unwrap();
+
+    write_header(&mut out, "greenpoll_job_runs_total", "counter", "Turns of background jobs on this instance, by job and outcome.");
+    for ((job, outcome), count) in metrics.job_runs.lock().unwrap().iter() {
+        writeln!(out, "greenpoll_job_runs_total{{job=\"{}\",outcome=\"{}\"}} {}", job, outcome, count).unwrap();
+    }
+
+    out
+}
+
+/// Middleware that counts requests and measures how long they take, by the pattern of the route that served them
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S> for RequestMetrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestMetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        }))
+    }
+}
+
+/// The request metrics middleware wrapped around a service
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for RequestMetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let res = self.service.borrow_mut().call(req);
+
+        Box::pin(async move {
+            let res = res.await;
+            let seconds = start.elapsed().as_secs_f64();
+
+            match &res {
+                Ok(res) => {
+                    let route = res.request().match_pattern();
+                    record_request(route.as_deref().unwrap_or(UNMATCHED_ROUTE), res.status().as_u16(), seconds);
+                },
+                Err(e) => record_request(UNMATCHED_ROUTE, e.as_response_error().status_code().as_u16(), seconds),
+            }
+
+            res
+        })
+    }
+}
@@ -0,0 +1,348 @@
+use actix_web::{HttpResponse, Error};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::dev::{Service, Transform, ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderName, HeaderValue};
+use std::cell::RefCell;
+use std::future::{Future, Ready, ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use dashmap::DashMap;
+use crate::services;
+use crate::i18n;
+use crate::auth::authenticated_user_id;
+use crate::config::{RateLimitConfig, RateLimitBackend};
+use crate::util::{DBPool, ErrorJSON, request_ip};
+
+/// The budgets of routes that are expensive or attractive to abuse, limited on top of the global budget
+const ROUTE_BUDGETS: [RouteBudget; 4] = [
+    RouteBudget { path: "/register", budget: Budget { name: "register", capacity: 5.0, refill_rate: 5.0 / 3600.0 } },
+    RouteBudget { path: "/request_password_reset", budget: Budget { name: "request_password_reset", capacity: 5.0, refill_rate: 5.0 / 3600.0 } },
+    RouteBudget { path: "/poll_vote", budget: Budget { name: "poll_vote", capacity: 30.0, refill_rate: 30.0 / 60.0 } },
+    RouteBudget { path: "/create_poll", budget: Budget { name: "create_poll", capacity: 10.0, refill_rate: 20.0 / 3600.0 } },
+];
+
+/// A token bucket budget
+#[derive(Clone, Copy)]
+struct Budget {
+    name: &'static str,
+    capacity: f64,
+    refill_rate: f64,
+}
+
+impl Budget {
+    /// Returns the number of seconds an empty bucket takes to refill
+    fn refill_time(&self) -> f64 {
+        self.capacity / self.refill_rate
+    }
+}
+
+/// A budget applied to a single route
+struct RouteBudget {
+    path: &'static str,
+    budget: Budget,
+}
+
+/// Returns the budget shared by every route
+/// 
+/// # Arguments
+/// 
+/// * `config` - The rate limit config
+fn global_budget(config: &RateLimitConfig) -> Budget {
+    Budget {
+        name: "global",
+        capacity: config.burst,
+        refill_rate: config.per_minute / 60.0,
+    }
+}
+
+/// Returns the number of seconds after which any unused bucket has refilled and can be forgotten
+/// 
+/// # Arguments
+/// 
+/// * `config` - The rate limit config
+pub fn rate_limit_bucket_max_age(config: &RateLimitConfig) -> i64 {
+    ROUTE_BUDGETS.iter()
+        .map(|route| route.budget.refill_time())
+        .fold(global_budget(config).refill_time(), f64::max)
+        .ceil() as i64
+}
+
+/// A token bucket held in memory
+pub struct MemoryBucket {
+    tokens: f64,
+    update_time: Instant,
+}
+
+/// The state of a bucket after a request has tried to take a token from it
+struct BucketState {
+    budget: Budget,
+    tokens: f64,
+    allowed: bool,
+}
+
+impl BucketState {
+    /// Returns the number of seconds until the bucket has a token to take
+    fn retry_after(&self) -> u64 {
+        ((1.0 - self.tokens).max(0.0) / self.budget.refill_rate).ceil() as u64
+    }
+
+    /// Returns the number of seconds until the bucket is full again
+    fn reset_after(&self) -> u64 {
+        ((self.budget.capacity - self.tokens).max(0.0) / self.budget.refill_rate).ceil() as u64
+    }
+}
+
+/// Where token buckets are kept
+#[derive(Clone)]
+pub enum RateLimitStore {
+    /// Buckets are kept in this process, shared between its workers in a sharded map, so updating a bucket only locks its own shard
+    Memory(Arc<DashMap<String, MemoryBucket>>),
+    /// Buckets are kept in the database, shared between every instance of the API
+    Postgres(DBPool),
+}
+
+impl RateLimitStore {
+    /// Creates the configured store
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The rate limit config
+    pub fn from_config(pool: &DBPool, config: &RateLimitConfig) -> Self {
+        match config.store {
+            RateLimitBackend::Postgres => RateLimitStore::Postgres(pool.clone()),
+            RateLimitBackend::Memory => RateLimitStore::Memory(Arc::new(DashMap::new())),
+        }
+    }
+
+    /// Takes a token from a bucket, returning the bucket's new state
+    /// 
+    /// # Arguments
+    /// 
+    /// * `key` - The client the bucket belongs to
+    /// * `budget` - The budget of the bucket
+    async fn take(&self, key: &str, budget: Budget) -> std::io::Result<BucketState> {
+        let key = format!("{}:{}", key, budget.name);
+
+        match self {
+            RateLimitStore::Memory(buckets) => {
+                let now = Instant::now();
+                let mut bucket = buckets.entry(key).or_insert(MemoryBucket {
+                    tokens: budget.capacity,
+                    update_time: now,
+                });
+
+                let elapsed = now.duration_since(bucket.update_time).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * budget.refill_rate).min(budget.capacity);
+                bucket.update_time = now;
+
+                let allowed = bucket.tokens >= 1.0;
+
+                if allowed {
+                    bucket.tokens -= 1.0;
+                }
+
+                Ok(BucketState {
+                    budget,
+                    tokens: bucket.tokens,
+                    allowed,
+                })
+            },
+            RateLimitStore::Postgres(pool) => {
+                let bucket = services::rate_limit_service::take_rate_limit_token(pool, &key, budget.capacity, budget.refill_rate).await?;
+
+                Ok(BucketState {
+                    budget,
+                    tokens: bucket.tokens,
+                    allowed: bucket.allowed,
+                })
+            },
+        }
+    }
+
+    /// Forgets every bucket that has not been used for long enough to have refilled
+    /// 
+    /// # Arguments
+    /// 
+    /// * `max_age` - The number of seconds after which an unused bucket has refilled
+    pub async fn prune(&self, max_age: i64) -> std::io::Result<()> {
+        match self {
+            RateLimitStore::Memory(buckets) => {
+                let now = Instant::now();
+                buckets.retain(|_, bucket| (now.duration_since(bucket.update_time).as_secs() as i64) < max_age);
+
+                Ok(())
+            },
+            RateLimitStore::Postgres(pool) => services::rate_limit_service::prune_rate_limit_buckets(pool, max_age).await,
+        }
+    }
+}
+
+/// Takes a token from each of a client's buckets, adding the buckets' new states to a list
+/// 
+/// A bucket whose store fails is left out, so the request fails open: if the store is unavailable, such as the database
+/// behind the Postgres store, requests go through unlimited rather than every request being refused. The error is logged
+/// so the outage does not go unnoticed.
+/// 
+/// # Arguments
+/// 
+/// * `store` - Where the buckets are kept
+/// * `key` - The client the buckets belong to
+/// * `budgets` - The budgets of the buckets
+/// * `states` - The list to add the buckets' states to
+async fn take_tokens(store: &RateLimitStore, key: &str, budgets: &[Budget], states: &mut Vec<BucketState>) {
+    for budget in budgets.iter() {
+        match store.take(key, *budget).await {
+            Ok(state) => states.push(state),
+            Err(e) => eprintln!("Rate limit store failed, letting the request through: {}", e),
+        }
+    }
+}
+
+/// Middleware that limits how often clients can make requests, keyed by IP address and by authenticated user
+/// 
+/// Each client has a bucket for the global budget, plus one for the route's budget if it has one.
+/// Responses carry `X-RateLimit-*` headers describing the most depleted bucket, and limited requests are rejected with a 429 and a `Retry-After` header.
+pub struct RateLimiter {
+    store: RateLimitStore,
+    global_budget: Budget,
+}
+
+impl RateLimiter {
+    /// Creates the middleware
+    /// 
+    /// # Arguments
+    /// 
+    /// * `store` - Where to keep token buckets
+    /// * `config` - The rate limit config
+    pub fn new(store: RateLimitStore, config: &RateLimitConfig) -> Self {
+        Self {
+            store,
+            global_budget: global_budget(config),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RateLimiter
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimiterMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            store: self.store.clone(),
+            global_budget: self.global_budget,
+        }))
+    }
+}
+
+/// The rate limiting middleware wrapped around a service
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    store: RateLimitStore,
+    global_budget: Budget,
+}
+
+/// Adds a numeric header to a response
+/// 
+/// # Arguments
+/// 
+/// * `res` - The response
+/// * `name` - The name of the header
+/// * `value` - The value of the header
+fn set_header<B>(res: &mut ServiceResponse<B>, name: &'static str, value: u64) {
+    res.headers_mut().insert(HeaderName::from_static(name), HeaderValue::from(value));
+}
+
+impl<S, B> Service for RateLimiterMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = self.store.clone();
+        let global_budget = self.global_budget;
+
+        Box::pin(async move {
+            let (http_req, payload) = req.into_parts();
+
+            let mut budgets = vec![global_budget];
+
+            if let Some(route) = ROUTE_BUDGETS.iter().find(|route| route.path == http_req.path()) {
+                budgets.push(route.budget);
+            }
+
+            let mut states = Vec::new();
+
+            // Clients are limited by IP address before the request is authenticated, so requests over the limit cost no credential lookups
+            if let Some(ip) = request_ip(&http_req) {
+                take_tokens(&store, &format!("ip:{}", ip), &budgets, &mut states).await;
+            }
+
+            if states.iter().all(|state| state.allowed) {
+                if let Some(user_id) = authenticated_user_id(&http_req).await {
+                    take_tokens(&store, &format!("user:{}", user_id), &budgets, &mut states).await;
+                }
+            }
+
+            let limited = states.iter()
+                .filter(|state| !state.allowed)
+                .max_by_key(|state| state.retry_after());
+
+            if let Some(limited) = limited {
+                let res = HttpResponse::TooManyRequests()
+                    .header("Retry-After", limited.retry_after())
+                    .header("X-RateLimit-Limit", limited.budget.capacity as u64)
+                    .header("X-RateLimit-Remaining", 0u64)
+                    .header("X-RateLimit-Reset", limited.reset_after())
+                    .json(ErrorJSON {
+                        error: i18n::translate(i18n::accept_language_locale(&http_req), "too_many_requests")
+                    });
+
+                return Ok(ServiceResponse::from_err(res, http_req));
+            }
+
+            let req = match ServiceRequest::from_parts(http_req, payload) {
+                Ok(req) => req,
+                Err((http_req, _)) => return Ok(ServiceResponse::from_err(ErrorInternalServerError("Failed to resume request"), http_req)),
+            };
+
+            let res = service.borrow_mut().call(req);
+            let mut res = res.await?;
+
+            let most_depleted = states.iter()
+                .min_by(|a, b| (a.tokens / a.budget.capacity).partial_cmp(&(b.tokens / b.budget.capacity)).unwrap());
+
+            if let Some(state) = most_depleted {
+                set_header(&mut res, "x-ratelimit-limit", state.budget.capacity as u64);
+                set_header(&mut res, "x-ratelimit-remaining", state.tokens.floor() as u64);
+                set_header(&mut res, "x-ratelimit-reset", state.reset_after());
+            }
+
+            Ok(res)
+        })
+    }
+}
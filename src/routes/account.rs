@@ -0,0 +1,172 @@
+use actix_web::{HttpRequest, HttpResponse, HttpMessage, Result, web, get};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header;
+use serde::{Serialize, Deserialize};
+use crate::{services, generic_http_err};
+use crate::auth::AuthUser;
+use crate::routes::{SessionJSON, PollJSON, PollOptionJSON, PollVoteJSON};
+use crate::util::{AppData, audit_context, SuccessJSON, ErrorJSON, success_json};
+
+/// Query parameters for deleting an account
+#[derive(Serialize, Deserialize)]
+pub struct DeleteAccountQuery {
+    password: String,
+}
+
+/// JSON representation of a user's own profile in a data export
+#[derive(Serialize, Deserialize)]
+pub struct ProfileExportJSON {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    pub verified: bool,
+    pub is_admin: bool,
+    pub suspended: bool,
+    pub two_factor_enabled: bool,
+    pub delete_time: Option<i64>,
+    pub locale: Option<String>,
+    pub join_time: i64,
+}
+
+/// JSON representation of everything stored about a user
+#[derive(Serialize, Deserialize)]
+pub struct UserDataExportJSON {
+    pub profile: ProfileExportJSON,
+    pub sessions: Vec<SessionJSON>,
+    pub polls: Vec<PollJSON>,
+    pub poll_options: Vec<PollOptionJSON>,
+    pub votes: Vec<PollVoteJSON>,
+    pub export_time: i64,
+}
+
+/// The account routes
+pub mod account_routes {
+    use super::*;
+
+    /// Schedules the current user's account for deletion after a grace period, logging out everywhere
+    #[get("/delete_account")]
+    pub async fn delete_account(
+        req: HttpRequest,
+        user: AuthUser,
+        query: web::Query<DeleteAccountQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::user_service::request_deletion(&app_data.pool, &app_data.config, &audit_context(&req, Some(user.id)), &user, query.password.clone())
+            .await);
+
+        Ok(HttpResponse::Ok()
+            .cookie(
+                Cookie::build("session_id", "")
+                    .path("/")
+                    .secure(true)
+                    .http_only(true)
+                    .same_site(SameSite::None)
+                    .finish()
+            ).json(SuccessJSON {
+                success: true
+            })
+        )
+    }
+
+    /// Cancels the scheduled deletion of the current user's account
+    #[get("/cancel_account_deletion")]
+    pub async fn cancel_account_deletion(
+        req: HttpRequest,
+        user: AuthUser,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::user_service::cancel_deletion(&app_data.pool, &audit_context(&req, Some(user.id)), &user)
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Returns a JSON archive of the current user's profile, sessions, polls, poll options and votes
+    #[get("/export_user_data")]
+    pub async fn export_user_data(
+        req: HttpRequest,
+        user: AuthUser,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let current_session = generic_http_err!(
+            services::session_service::get_session(&app_data.pool, req.cookie("session_id").unwrap().value().to_string())
+            .await);
+
+        let user_sessions = generic_http_err!(
+            services::session_service::get_user_sessions(&app_data.pool, &app_data.config, user.id)
+            .await);
+
+        let user_polls = generic_http_err!(
+            services::user_service::get_user_polls(&app_data.pool, user.id)
+            .await);
+
+        let mut poll_options = Vec::new();
+
+        for poll in user_polls.iter() {
+            let options = generic_http_err!(
+                services::poll_service::get_poll_options(&app_data.pool, poll.id)
+                .await);
+
+            poll_options.extend(options.into_iter().map(|option| PollOptionJSON {
+                id: option.id,
+                poll_id: option.poll_id,
+                value: option.value
+            }));
+        }
+
+        let user_votes = generic_http_err!(
+            services::poll_vote_service::get_user_poll_votes(&app_data.pool, user.id)
+            .await);
+
+        let user = user.user;
+
+        let export = UserDataExportJSON {
+            profile: ProfileExportJSON {
+                id: user.id,
+                username: user.username,
+                email: user.email,
+                verified: user.verified,
+                is_admin: user.is_admin,
+                suspended: user.suspended,
+                two_factor_enabled: user.totp_enabled,
+                delete_time: user.delete_time.map(|delete_time| delete_time.assume_utc().unix_timestamp()),
+                locale: user.locale,
+                join_time: user.join_time.assume_utc().unix_timestamp()
+            },
+            sessions: user_sessions.into_iter().map(|session| SessionJSON {
+                current: session.handle == current_session.handle,
+                handle: session.handle,
+                user_agent: session.user_agent,
+                ip_address: session.ip_address,
+                create_time: session.create_time.assume_utc().unix_timestamp(),
+                last_seen: session.last_seen.assume_utc().unix_timestamp()
+            }).collect(),
+            polls: user_polls.into_iter().map(|poll| PollJSON {
+                id: poll.id,
+                user_id: poll.user_id,
+                title: poll.title,
+                description: poll.description,
+                closed: poll.closed,
+                hidden: poll.hidden,
+                max_vote_changes: poll.max_vote_changes,
+                close_time: poll.close_time.map(|close_time| close_time.assume_utc().unix_timestamp()),
+                create_time: poll.create_time.assume_utc().unix_timestamp()
+            }).collect(),
+            poll_options,
+            votes: user_votes.into_iter().map(|vote| PollVoteJSON {
+                id: vote.id,
+                user_id: vote.user_id,
+                poll_id: vote.poll_id,
+                poll_option_id: vote.poll_option_id,
+                vote_time: vote.vote_time.assume_utc().unix_timestamp()
+            }).collect(),
+            export_time: time::OffsetDateTime::now_utc().unix_timestamp()
+        };
+
+        Ok(HttpResponse::Ok()
+            .header(header::CONTENT_DISPOSITION, "attachment; filename=\"greenpoll-data.json\"")
+            .json(export))
+    }
+}
@@ -0,0 +1,478 @@
+use actix_web::{HttpRequest, HttpResponse, HttpMessage, Result, web, get};
+use actix_web::cookie::{Cookie, SameSite};
+use serde::{Serialize, Deserialize};
+use serde_json::Value as JsonValue;
+use crate::{services, generic_http_err};
+use crate::services::{User, Report, OutboxEmail};
+use crate::routes::ReportJSON;
+use crate::auth::{AuthUser, AdminUser};
+use crate::util::{AppData, ActiveSession, audit_context, page_bounds, SuccessJSON, ErrorJSON, success_json, error_json, session_cookie, impersonator_session_cookie, request_ip, request_user_agent};
+
+/// Query parameters for paging through records
+#[derive(Serialize, Deserialize)]
+pub struct PageQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Query parameters for searching users
+#[derive(Serialize, Deserialize)]
+pub struct SearchUsersQuery {
+    search: String,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Query parameters for admin actions performed on a user
+#[derive(Serialize, Deserialize)]
+pub struct AdminUserQuery {
+    user_id: i32,
+}
+
+/// Query parameters for admin actions performed on a poll
+#[derive(Serialize, Deserialize)]
+pub struct AdminPollQuery {
+    poll_id: i32,
+}
+
+/// Query parameters for getting reports
+#[derive(Serialize, Deserialize)]
+pub struct GetReportsQuery {
+    status: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Query parameters for reviewing a report
+#[derive(Serialize, Deserialize)]
+pub struct ReviewReportQuery {
+    report_id: i32,
+}
+
+/// JSON representation of a user as seen by an administrator
+#[derive(Serialize, Deserialize)]
+pub struct AdminUserJSON {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    pub verified: bool,
+    pub is_admin: bool,
+    pub suspended: bool,
+    pub join_time: i64,
+}
+
+/// JSON representation of an audit event
+#[derive(Serialize, Deserialize)]
+pub struct AuditEventJSON {
+    pub id: i32,
+    pub actor_id: Option<i32>,
+    pub impersonator_id: Option<i32>,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: i32,
+    pub poll_id: Option<i32>,
+    pub before_state: Option<JsonValue>,
+    pub after_state: Option<JsonValue>,
+    pub ip_address: Option<String>,
+    pub event_time: i64,
+}
+
+/// JSON representation of a queued email, without its body
+#[derive(Serialize, Deserialize)]
+pub struct OutboxEmailJSON {
+    pub id: i32,
+    pub to_address: String,
+    pub subject: String,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub create_time: i64,
+    pub next_attempt_time: i64,
+    pub last_attempt_time: Option<i64>,
+}
+
+/// JSON representation of a background job and the outcome of its last run
+#[derive(Serialize, Deserialize)]
+pub struct ScheduledJobJSON {
+    pub name: String,
+    pub last_run_time: i64,
+    pub last_success_time: Option<i64>,
+    pub last_error: Option<String>,
+    pub runs: i32,
+    pub failures: i32,
+}
+
+/// Converts user records into their admin JSON representation
+/// 
+/// # Arguments
+/// 
+/// * `users` - The user records
+fn admin_users_json(users: Vec<User>) -> Vec<AdminUserJSON> {
+    users.into_iter().map(|user| AdminUserJSON {
+        id: user.id,
+        username: user.username,
+        email: user.email,
+        verified: user.verified,
+        is_admin: user.is_admin,
+        suspended: user.suspended,
+        join_time: user.join_time.assume_utc().unix_timestamp()
+    }).collect()
+}
+
+/// Converts a report record into its JSON representation
+/// 
+/// # Arguments
+/// 
+/// * `report` - The report record
+fn report_json(report: Report) -> ReportJSON {
+    ReportJSON {
+        id: report.id,
+        reporter_id: report.reporter_id,
+        target_type: report.target_type,
+        target_id: report.target_id,
+        reason: report.reason,
+        status: report.status,
+        create_time: report.create_time.assume_utc().unix_timestamp()
+    }
+}
+
+/// Converts a queued email record into its JSON representation
+/// 
+/// # Arguments
+/// 
+/// * `email` - The queued email record
+fn outbox_email_json(email: OutboxEmail) -> OutboxEmailJSON {
+    OutboxEmailJSON {
+        id: email.id,
+        to_address: email.to_address,
+        subject: email.subject,
+        status: email.status,
+        attempts: email.attempts,
+        last_error: email.last_error,
+        create_time: email.create_time.assume_utc().unix_timestamp(),
+        next_attempt_time: email.next_attempt_time.assume_utc().unix_timestamp(),
+        last_attempt_time: email.last_attempt_time.map(|last_attempt_time| last_attempt_time.assume_utc().unix_timestamp())
+    }
+}
+
+/// The admin routes
+pub mod admin_routes {
+    use super::*;
+
+    /// Returns a page of all users
+    #[get("/get_all_users")]
+    pub async fn get_all_users(
+        _admin: AdminUser,
+        query: web::Query<PageQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let (limit, offset) = page_bounds(query.limit, query.offset);
+
+        let users = generic_http_err!(
+            services::user_service::get_users(&app_data.pool, limit, offset)
+            .await);
+
+        Ok(HttpResponse::Ok().json(admin_users_json(users)))
+    }
+
+    /// Returns a page of users whose username or email address matches a search
+    #[get("/search_users")]
+    pub async fn search_users(
+        _admin: AdminUser,
+        query: web::Query<SearchUsersQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let (limit, offset) = page_bounds(query.limit, query.offset);
+
+        let users = generic_http_err!(
+            services::user_service::search_users(&app_data.pool, query.search.clone(), limit, offset)
+            .await);
+
+        Ok(HttpResponse::Ok().json(admin_users_json(users)))
+    }
+
+    /// Suspends a user's account
+    #[get("/suspend_user")]
+    pub async fn suspend_user(
+        req: HttpRequest,
+        admin: AdminUser,
+        query: web::Query<AdminUserQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        if admin.id == query.user_id {
+            Ok(error_json("cannot_suspend_self"))
+        } else {
+            generic_http_err!(
+                services::user_service::set_suspended(&app_data.pool, &audit_context(&req, Some(admin.id)), query.user_id, true)
+                .await);
+
+            Ok(success_json())
+        }
+    }
+
+    /// Lifts the suspension on a user's account
+    #[get("/unsuspend_user")]
+    pub async fn unsuspend_user(
+        req: HttpRequest,
+        admin: AdminUser,
+        query: web::Query<AdminUserQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::user_service::set_suspended(&app_data.pool, &audit_context(&req, Some(admin.id)), query.user_id, false)
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Deletes any user's poll
+    #[get("/admin_delete_poll")]
+    pub async fn admin_delete_poll(
+        req: HttpRequest,
+        admin: AdminUser,
+        query: web::Query<AdminPollQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::poll_service::delete_poll(&app_data.pool, &audit_context(&req, Some(admin.id)), query.poll_id)
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Closes any user's poll to further voting
+    #[get("/force_close_poll")]
+    pub async fn force_close_poll(
+        req: HttpRequest,
+        admin: AdminUser,
+        query: web::Query<AdminPollQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::poll_service::set_closed(&app_data.pool, &app_data.config, &audit_context(&req, Some(admin.id)), query.poll_id, true)
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Logs in as another user for support purposes, replacing the administrator's session cookie
+    /// 
+    /// The administrator's own session is kept, to be restored by `end_impersonation`. Everything done in the
+    /// impersonation session is recorded in the audit log as done by the administrator on the user's behalf.
+    #[get("/impersonate_user")]
+    pub async fn impersonate_user(
+        req: HttpRequest,
+        admin: AdminUser,
+        query: web::Query<AdminUserQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let user = generic_http_err!(
+            services::user_service::get_user(&app_data.pool, query.user_id)
+            .await);
+
+        if user.is_admin {
+            return Ok(error_json("cannot_impersonate_admin"));
+        }
+
+        let session = generic_http_err!(
+            services::session_service::create_impersonation_session(&app_data.pool, user.id, admin.id, request_user_agent(&req), request_ip(&req))
+            .await);
+
+        generic_http_err!(
+            services::audit_event_service::create_audit_event(&app_data.pool, &audit_context(&req, Some(admin.id)), "impersonate_user", "user", user.id, None, None, None)
+            .await);
+
+        let mut res = HttpResponse::Ok();
+        res.cookie(session_cookie(&app_data.config, session.id));
+
+        if let Some(admin_session) = req.cookie("session_id") {
+            res.cookie(impersonator_session_cookie(&app_data.config, admin_session.value().to_string()));
+        }
+
+        Ok(res.json(SuccessJSON {
+            success: true
+        }))
+    }
+
+    /// Ends an impersonation session, logging out of it and restoring the administrator's own session if it is still active
+    #[get("/end_impersonation")]
+    pub async fn end_impersonation(
+        req: HttpRequest,
+        user: AuthUser,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let active_session = req.extensions().get::<ActiveSession>().cloned();
+        let (session_id, impersonator_id) = match active_session {
+            Some(ActiveSession { session_id, impersonator_id: Some(impersonator_id) }) => (session_id, impersonator_id),
+            _ => return Ok(error_json("not_impersonating")),
+        };
+
+        generic_http_err!(
+            services::audit_event_service::create_audit_event(&app_data.pool, &audit_context(&req, Some(user.id)), "end_impersonation", "user", user.id, None, None, None)
+            .await);
+
+        generic_http_err!(
+            services::session_service::delete_session(&app_data.pool, session_id)
+            .await);
+
+        // Only restore the session if it still belongs to the administrator who started impersonating
+        let admin_session_id = match req.cookie("impersonator_session_id") {
+            Some(cookie) => match services::session_service::get_user_by_session_id(&app_data.pool, &app_data.config, cookie.value().to_string()).await {
+                Ok((admin, _)) if admin.id == impersonator_id => Some(cookie.value().to_string()),
+                _ => None,
+            },
+            None => None,
+        };
+
+        let session_cookie = match admin_session_id {
+            Some(admin_session_id) => session_cookie(&app_data.config, admin_session_id),
+            None => Cookie::build("session_id", "")
+                .path("/")
+                .secure(true)
+                .http_only(true)
+                .same_site(SameSite::None)
+                .finish(),
+        };
+
+        Ok(HttpResponse::Ok()
+            .cookie(session_cookie)
+            .cookie(
+                Cookie::build("impersonator_session_id", "")
+                    .path("/")
+                    .secure(true)
+                    .http_only(true)
+                    .same_site(SameSite::None)
+                    .finish()
+            ).json(SuccessJSON {
+                success: true
+            })
+        )
+    }
+
+    /// Returns a page of reports, open reports by default
+    #[get("/get_reports")]
+    pub async fn get_reports(
+        _admin: AdminUser,
+        query: web::Query<GetReportsQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let status = query.status.clone().unwrap_or_else(|| "open".to_string());
+        let (limit, offset) = page_bounds(query.limit, query.offset);
+
+        let reports = generic_http_err!(
+            services::report_service::get_reports(&app_data.pool, status, limit, offset)
+            .await);
+
+        let reports: Vec<ReportJSON> = reports.into_iter().map(report_json).collect();
+
+        Ok(HttpResponse::Ok().json(reports))
+    }
+
+    /// Marks a report as actioned, leaving any reported poll hidden
+    #[get("/action_report")]
+    pub async fn action_report(
+        req: HttpRequest,
+        admin: AdminUser,
+        query: web::Query<ReviewReportQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let report = generic_http_err!(
+            services::report_service::review_report(&app_data.pool, &audit_context(&req, Some(admin.id)), query.report_id, "actioned")
+            .await);
+
+        generic_http_err!(
+            services::audit_event_service::create_audit_event(&app_data.pool, &audit_context(&req, Some(admin.id)), "action_report", "report", report.id, None, None, None)
+            .await);
+
+        Ok(HttpResponse::Ok().json(report_json(report)))
+    }
+
+    /// Dismisses a report, showing the reported poll again if no other reports against it remain
+    #[get("/dismiss_report")]
+    pub async fn dismiss_report(
+        req: HttpRequest,
+        admin: AdminUser,
+        query: web::Query<ReviewReportQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let report = generic_http_err!(
+            services::report_service::review_report(&app_data.pool, &audit_context(&req, Some(admin.id)), query.report_id, "dismissed")
+            .await);
+
+        generic_http_err!(
+            services::audit_event_service::create_audit_event(&app_data.pool, &audit_context(&req, Some(admin.id)), "dismiss_report", "report", report.id, None, None, None)
+            .await);
+
+        Ok(HttpResponse::Ok().json(report_json(report)))
+    }
+
+    /// Returns a page of the audit log, most recent first
+    #[get("/get_audit_events")]
+    pub async fn get_audit_events(
+        _admin: AdminUser,
+        query: web::Query<PageQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let (limit, offset) = page_bounds(query.limit, query.offset);
+
+        let audit_events = generic_http_err!(
+            services::audit_event_service::get_audit_events(&app_data.pool, limit, offset)
+            .await);
+
+        let events: Vec<AuditEventJSON> = audit_events.into_iter().map(|event| AuditEventJSON {
+            id: event.id,
+            actor_id: event.actor_id,
+            impersonator_id: event.impersonator_id,
+            action: event.action,
+            target_type: event.target_type,
+            target_id: event.target_id,
+            poll_id: event.poll_id,
+            before_state: event.before_state,
+            after_state: event.after_state,
+            ip_address: event.ip_address,
+            event_time: event.event_time.assume_utc().unix_timestamp()
+        }).collect();
+
+        Ok(HttpResponse::Ok().json(events))
+    }
+
+    /// Returns a page of emails that could not be delivered, most recently attempted first
+    #[get("/get_failed_emails")]
+    pub async fn get_failed_emails(
+        _admin: AdminUser,
+        query: web::Query<PageQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let (limit, offset) = page_bounds(query.limit, query.offset);
+
+        let emails = generic_http_err!(
+            services::email_outbox_service::get_failed_emails(&app_data.pool, limit, offset)
+            .await);
+
+        let emails: Vec<OutboxEmailJSON> = emails.into_iter().map(outbox_email_json).collect();
+
+        Ok(HttpResponse::Ok().json(emails))
+    }
+
+    /// Returns every background job that has run, with when it last ran and whether that run succeeded
+    #[get("/get_scheduled_jobs")]
+    pub async fn get_scheduled_jobs(
+        _admin: AdminUser,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let jobs = generic_http_err!(
+            services::scheduled_job_service::get_scheduled_jobs(&app_data.pool)
+            .await);
+
+        let jobs: Vec<ScheduledJobJSON> = jobs.into_iter().map(|job| ScheduledJobJSON {
+            name: job.name,
+            last_run_time: job.last_run_time.assume_utc().unix_timestamp(),
+            last_success_time: job.last_success_time.map(|last_success_time| last_success_time.assume_utc().unix_timestamp()),
+            last_error: job.last_error,
+            runs: job.runs,
+            failures: job.failures
+        }).collect();
+
+        Ok(HttpResponse::Ok().json(jobs))
+    }
+}
@@ -0,0 +1,107 @@
+use actix_web::{HttpResponse, Result, web, get};
+use serde::{Serialize, Deserialize};
+use crate::{services, generic_http_err};
+use crate::services::ApiToken;
+use crate::auth::AuthUser;
+use crate::util::{AppData, ErrorJSON, success_json};
+
+/// Query parameters for creating an API token
+#[derive(Serialize, Deserialize)]
+pub struct CreateApiTokenQuery {
+    name: String,
+    scopes: String,
+    expires_in: Option<i64>,
+}
+
+/// Query parameters for revoking an API token
+#[derive(Serialize, Deserialize)]
+pub struct RevokeApiTokenQuery {
+    api_token_id: i32,
+}
+
+/// JSON representation of an API token
+#[derive(Serialize, Deserialize)]
+pub struct ApiTokenJSON {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub create_time: i64,
+    pub expire_time: Option<i64>,
+    pub last_used: Option<i64>,
+}
+
+/// JSON representation of a newly created API token, the only time the token itself is shown
+#[derive(Serialize, Deserialize)]
+pub struct NewApiTokenJSON {
+    pub token: String,
+    pub api_token: ApiTokenJSON,
+}
+
+/// Converts an API token record into its JSON representation
+/// 
+/// # Arguments
+/// 
+/// * `api_token` - The API token record
+fn api_token_json(api_token: ApiToken) -> ApiTokenJSON {
+    ApiTokenJSON {
+        id: api_token.id,
+        name: api_token.name,
+        scopes: api_token.scopes,
+        create_time: api_token.create_time.assume_utc().unix_timestamp(),
+        expire_time: api_token.expire_time.map(|expire_time| expire_time.assume_utc().unix_timestamp()),
+        last_used: api_token.last_used.map(|last_used| last_used.assume_utc().unix_timestamp())
+    }
+}
+
+/// The API token routes
+pub mod api_token_routes {
+    use super::*;
+
+    /// Creates an API token with a comma-separated list of scopes
+    #[get("/create_api_token")]
+    pub async fn create_api_token(
+        user: AuthUser,
+        query: web::Query<CreateApiTokenQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let scopes: Vec<String> = query.scopes.split(',').map(|scope| scope.trim().to_string()).filter(|scope| !scope.is_empty()).collect();
+
+        let (token, api_token) = generic_http_err!(
+            services::api_token_service::create_api_token(&app_data.pool, user.id, query.name.clone(), scopes, query.expires_in)
+            .await);
+
+        Ok(HttpResponse::Ok().json(NewApiTokenJSON {
+            token,
+            api_token: api_token_json(api_token)
+        }))
+    }
+
+    /// Returns the current user's API tokens
+    #[get("/get_api_tokens")]
+    pub async fn get_api_tokens(
+        user: AuthUser,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let api_tokens = generic_http_err!(
+            services::api_token_service::get_user_api_tokens(&app_data.pool, user.id)
+            .await);
+
+        let api_tokens: Vec<ApiTokenJSON> = api_tokens.into_iter().map(api_token_json).collect();
+
+        Ok(HttpResponse::Ok().json(api_tokens))
+    }
+
+    /// Revokes one of the current user's API tokens
+    #[get("/revoke_api_token")]
+    pub async fn revoke_api_token(
+        user: AuthUser,
+        query: web::Query<RevokeApiTokenQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::api_token_service::delete_api_token(&app_data.pool, user.id, query.api_token_id)
+            .await);
+
+        Ok(success_json())
+    }
+}
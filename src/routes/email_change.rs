@@ -0,0 +1,78 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web, get};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use std::io::Error;
+use crate::{services, generic_http_err};
+use crate::templates::EmailTemplate;
+use crate::i18n;
+use crate::auth::AuthUser;
+use crate::util::{AppData, audit_context, ErrorJSON, success_json, request_ip};
+
+/// Query parameters for changing an email address
+#[derive(Serialize, Deserialize)]
+pub struct ChangeEmailQuery {
+    password: String,
+    new_email: String,
+}
+
+/// Query parameters for confirming an email address change
+#[derive(Serialize, Deserialize)]
+pub struct ConfirmEmailChangeQuery {
+    change_id: String,
+}
+
+/// The email change routes
+pub mod email_change_routes {
+    use super::*;
+
+    /// Requests a change of email address, which takes effect once confirmed from the new address
+    #[get("/change_email")]
+    pub async fn change_email(
+        req: HttpRequest,
+        user: AuthUser,
+        query: web::Query<ChangeEmailQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let email_change = generic_http_err!(
+            services::email_change_service::create_email_change(&app_data.pool, &app_data.config, &user, query.password.clone(), query.new_email.clone(), request_ip(&req))
+            .await);
+
+        match services::email_outbox_service::queue_email(
+            &app_data.pool,
+            email_change.new_email.clone(),
+            i18n::current_locale(),
+            EmailTemplate::EmailChange,
+            json!({ "url": app_data.config.server.frontend_url, "change_id": email_change.id })
+        ).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::other("Failed to queue email change confirmation email"))
+        }?;
+
+        match services::email_outbox_service::queue_email(
+            &app_data.pool,
+            user.email.clone(),
+            i18n::current_locale(),
+            EmailTemplate::EmailChangeNotice,
+            json!({ "url": app_data.config.server.frontend_url, "new_email": email_change.new_email })
+        ).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::other("Failed to queue email change notice"))
+        }?;
+
+        Ok(success_json())
+    }
+
+    /// Confirms a change of email address
+    #[get("/confirm_email_change")]
+    pub async fn confirm_email_change(
+        req: HttpRequest,
+        query: web::Query<ConfirmEmailChangeQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::email_change_service::confirm_email_change(&app_data.pool, &app_data.config, &audit_context(&req, None), query.change_id.clone())
+            .await);
+
+        Ok(success_json())
+    }
+}
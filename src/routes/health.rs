@@ -0,0 +1,102 @@
+use actix_web::{HttpResponse, Result, web, get};
+use actix_web::error::BlockingError;
+use serde::{Serialize, Deserialize};
+use std::future::Future;
+use std::time::Duration;
+use crate::services;
+use crate::util::{AppData, success_json};
+
+/// The number of seconds a readiness check may take before it is considered failed
+const READINESS_CHECK_TIMEOUT: u64 = 5;
+
+/// JSON representation of the outcome of a readiness check, which only says whether it passed, as the endpoint is public
+#[derive(Serialize, Deserialize)]
+pub struct ReadinessCheckJSON {
+    pub name: String,
+    pub ok: bool,
+}
+
+/// JSON representation of whether the API is ready to serve requests
+#[derive(Serialize, Deserialize)]
+pub struct ReadinessJSON {
+    pub ready: bool,
+    pub checks: Vec<ReadinessCheckJSON>,
+}
+
+/// Runs a readiness check, failing it if it takes too long, and logs why it failed rather than responding with it
+/// 
+/// # Arguments
+/// 
+/// * `name` - The name of the check
+/// * `check` - The check
+async fn readiness_check(name: &str, check: impl Future<Output = std::io::Result<()>>) -> ReadinessCheckJSON {
+    let error = match actix_web::rt::time::timeout(Duration::from_secs(READINESS_CHECK_TIMEOUT), check).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some("Timed out".to_string()),
+    };
+
+    if let Some(error) = &error {
+        eprintln!("Readiness check {} failed: {}", name, error);
+    }
+
+    ReadinessCheckJSON {
+        name: name.to_string(),
+        ok: error.is_none(),
+    }
+}
+
+/// The health routes
+pub mod health_routes {
+    use super::*;
+
+    /// Reports that the process is up, without checking anything it depends on
+    #[get("/healthz")]
+    pub async fn healthz() -> Result<HttpResponse> {
+        Ok(success_json())
+    }
+
+    /// Reports whether the API is ready to serve requests, responding with a 503 if the database cannot be reached, its schema is out of date or emails cannot be delivered
+    #[get("/readyz")]
+    pub async fn readyz(app_data: web::Data<AppData>) -> Result<HttpResponse> {
+        let email_transport = app_data.email_transport.clone();
+
+        let database = readiness_check("database", services::health_service::check_database(&app_data.pool)).await;
+        let schema = if database.ok {
+            readiness_check("schema", services::health_service::check_schema(&app_data.pool)).await
+        } else {
+            ReadinessCheckJSON {
+                name: "schema".to_string(),
+                ok: false,
+            }
+        };
+
+        let checks = vec![
+            database,
+            schema,
+            readiness_check("email_transport", async move {
+                // Checking the transport may block, so check on the thread pool rather than the worker thread
+                match web::block(move || email_transport.check()).await {
+                    Ok(_) => Ok(()),
+                    Err(BlockingError::Error(e)) => Err(e),
+                    Err(BlockingError::Canceled) => Err(std::io::Error::other("Email transport check was canceled")),
+                }
+            }).await,
+        ];
+        let ready = checks.iter().all(|check| check.ok);
+
+        let mut res = if ready { HttpResponse::Ok() } else { HttpResponse::ServiceUnavailable() };
+
+        Ok(res.json(ReadinessJSON { ready, checks }))
+    }
+
+    /// Exports the API's metrics in the Prometheus text format
+    #[get("/metrics")]
+    pub async fn metrics(app_data: web::Data<AppData>) -> Result<HttpResponse> {
+        let body = crate::metrics::render(&app_data.pool, app_data.config.database.max_connections);
+
+        Ok(HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body))
+    }
+}
@@ -1,11 +1,14 @@
 use actix_web::{HttpRequest, HttpResponse, HttpMessage, Result, web, get};
 use actix_web::cookie::{Cookie, SameSite};
 use serde::{Serialize, Deserialize};
-use std::sync::{Mutex, Arc};
-use std::io::{Error, ErrorKind};
+use serde_json::json;
+use std::io::Error;
 use crate::{services, generic_http_err};
-use crate::util::{AppData, SuccessJSON, ErrorJSON, success_json, get_user_by_session, FRONTEND_URL};
-use crate::emailer;
+use crate::templates::EmailTemplate;
+use crate::i18n;
+use crate::services::LoginResult;
+use crate::auth::AuthUser;
+use crate::util::{AppData, audit_context, SuccessJSON, ErrorJSON, success_json, session_cookie, request_ip, request_user_agent};
 
 /// Query parameters for registration
 #[derive(Serialize, Deserialize)]
@@ -22,6 +25,13 @@ pub struct LoginQuery {
     password: String,
 }
 
+/// JSON response to a correct password for an account with two-factor authentication enabled
+#[derive(Serialize, Deserialize)]
+pub struct TwoFactorRequiredJSON {
+    pub two_factor_required: bool,
+    pub pending_login_token: String,
+}
+
 /// The login and registration routes
 pub mod login_register_routes {
     use super::*;
@@ -29,27 +39,27 @@ pub mod login_register_routes {
     /// Registers an account
     #[get("/register")]
     pub async fn register(
+        req: HttpRequest,
         query: web::Query<RegisterQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         let user = generic_http_err!(
-            services::user_service::create_user(&data.pool, query.username.clone(), query.email.clone(), query.password.clone())
+            services::user_service::create_user(&app_data.pool, &app_data.config, &audit_context(&req, None), query.username.clone(), query.email.clone(), query.password.clone())
             .await);
 
         let verification = generic_http_err!(
-            services::verify_service::create_verification(&data.pool, user.email.clone())
+            services::verify_service::create_verification(&app_data.pool, user.email.clone())
             .await);
 
-        match emailer::send_formatted_email(
+        match services::email_outbox_service::queue_email(
+            &app_data.pool,
             user.email.clone(),
-            "GreenPoll - Verify Account".to_string(),
-            "verify".to_string(),
-            [("url", FRONTEND_URL), ("verify_id", &verification.id[..])].iter().cloned().collect()
-        ) {
+            i18n::current_locale(),
+            EmailTemplate::Verify,
+            json!({ "url": app_data.config.server.frontend_url, "verify_id": verification.id })
+        ).await {
             Ok(_) => Ok(()),
-            Err(_) => Err(Error::new(ErrorKind::Other, "Failed to send verification email"))
+            Err(_) => Err(Error::other("Failed to queue verification email"))
         }?;
 
         Ok(success_json())
@@ -58,40 +68,37 @@ pub mod login_register_routes {
     /// Logs in using email and password
     #[get("/login")]
     pub async fn login(
+        req: HttpRequest,
         query: web::Query<LoginQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let session = generic_http_err!(
-            services::user_service::login(&data.pool, query.email.clone(), query.password.clone())
+        let login_result = generic_http_err!(
+            services::user_service::login(&app_data.pool, &app_data.config, query.email.clone(), query.password.clone(), request_user_agent(&req), request_ip(&req))
             .await);
 
-        Ok(HttpResponse::Ok()
-            .cookie(
-                Cookie::build("session_id", session.id)
-                    .path("/")
-                    .secure(true)
-                    .http_only(true)
-                    .same_site(SameSite::None)
-                    .finish()
-            ).json(SuccessJSON {
-                success: true
-            })
-        )
+        match login_result {
+            LoginResult::Session(session) => Ok(HttpResponse::Ok()
+                .cookie(session_cookie(&app_data.config, session.id))
+                .json(SuccessJSON {
+                    success: true
+                })
+            ),
+            LoginResult::TwoFactorRequired(pending_login_token) => Ok(HttpResponse::Ok().json(TwoFactorRequiredJSON {
+                two_factor_required: true,
+                pending_login_token
+            })),
+        }
     }
 
     /// Logs out
     #[get("/logout")]
     pub async fn logout(
         req: HttpRequest,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         if let Some(ref session_cookie) = req.cookie("session_id") {
             generic_http_err!(
-                services::session_service::delete_session(&data.pool, session_cookie.value().to_string())
+                services::session_service::delete_session(&app_data.pool, session_cookie.value().to_string())
                 .await);
 
             Ok(HttpResponse::Ok()
@@ -114,15 +121,11 @@ pub mod login_register_routes {
     /// Logs out everywhere, removing all sessions
     #[get("/logout_everywhere")]
     pub async fn logout_everywhere(
-        req: HttpRequest,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        user: AuthUser,
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
-
         generic_http_err!(
-            services::session_service::delete_user_sessions(&data.pool, user.id)
+            services::session_service::delete_user_sessions(&app_data.pool, user.id)
             .await);
 
         Ok(HttpResponse::Ok()
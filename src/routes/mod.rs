@@ -5,6 +5,16 @@ mod poll_vote;
 mod login_register;
 mod verify;
 mod password_reset;
+mod email_change;
+mod account;
+mod admin;
+mod report;
+mod session;
+mod api_token;
+mod two_factor;
+mod notification;
+mod webhook;
+mod health;
 
 pub use user::*;
 pub use poll::*;
@@ -13,3 +23,13 @@ pub use poll_vote::*;
 pub use login_register::*;
 pub use verify::*;
 pub use password_reset::*;
+pub use email_change::*;
+pub use account::*;
+pub use admin::*;
+pub use report::*;
+pub use session::*;
+pub use api_token::*;
+pub use two_factor::*;
+pub use notification::*;
+pub use webhook::*;
+pub use health::*;
@@ -0,0 +1,176 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web, get};
+use serde::{Serialize, Deserialize};
+use serde_json::Value as JsonValue;
+use crate::{services, generic_http_err};
+use crate::services::NotificationPreference;
+use crate::auth::AuthUser;
+use crate::i18n;
+use crate::util::{AppData, audit_context, page_bounds, ErrorJSON, success_json};
+
+/// Query parameters for getting notifications
+#[derive(Serialize, Deserialize)]
+pub struct GetNotificationsQuery {
+    unread_only: Option<bool>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Query parameters for marking a notification as read
+#[derive(Serialize, Deserialize)]
+pub struct MarkNotificationReadQuery {
+    notification_id: i32,
+}
+
+/// Query parameters for setting notification preferences, where any left out are unchanged
+#[derive(Serialize, Deserialize)]
+pub struct SetNotificationPreferencesQuery {
+    in_app: Option<bool>,
+    email: Option<bool>,
+    digest: Option<bool>,
+}
+
+/// JSON representation of a notification
+#[derive(Serialize, Deserialize)]
+pub struct NotificationJSON {
+    pub id: i32,
+    pub kind: String,
+    pub poll_id: Option<i32>,
+    pub message: String,
+    pub data: JsonValue,
+    pub read: bool,
+    pub create_time: i64,
+}
+
+/// JSON representation of the number of unread notifications
+#[derive(Serialize, Deserialize)]
+pub struct UnreadNotificationsJSON {
+    pub unread: i64,
+}
+
+/// JSON representation of notification preferences
+#[derive(Serialize, Deserialize)]
+pub struct NotificationPreferencesJSON {
+    pub in_app: bool,
+    pub email: bool,
+    pub digest: bool,
+}
+
+/// Converts notification preferences into their JSON representation
+/// 
+/// # Arguments
+/// 
+/// * `preference` - The notification preference record
+fn notification_preferences_json(preference: NotificationPreference) -> NotificationPreferencesJSON {
+    NotificationPreferencesJSON {
+        in_app: preference.in_app,
+        email: preference.email,
+        digest: preference.digest,
+    }
+}
+
+/// The notification routes
+pub mod notification_routes {
+    use super::*;
+
+    /// Returns the current user's notifications, newest first, translated into the request's locale
+    #[get("/get_notifications")]
+    pub async fn get_notifications(
+        user: AuthUser,
+        query: web::Query<GetNotificationsQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let (limit, offset) = page_bounds(query.limit, query.offset);
+
+        let notifications = generic_http_err!(
+            services::notification_service::get_notifications(&app_data.pool, user.id, query.unread_only.unwrap_or(false), limit, offset)
+            .await);
+
+        let locale = i18n::current_locale();
+        let notifications: Vec<NotificationJSON> = notifications.into_iter().map(|notification| NotificationJSON {
+            message: notification.message(locale),
+            id: notification.id,
+            kind: notification.kind,
+            poll_id: notification.poll_id,
+            data: notification.data,
+            read: notification.read,
+            create_time: notification.create_time.assume_utc().unix_timestamp()
+        }).collect();
+
+        Ok(HttpResponse::Ok().json(notifications))
+    }
+
+    /// Returns the number of the current user's notifications that have not been read
+    #[get("/get_unread_notification_count")]
+    pub async fn get_unread_notification_count(
+        user: AuthUser,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let unread = generic_http_err!(
+            services::notification_service::get_num_unread_notifications(&app_data.pool, user.id)
+            .await);
+
+        Ok(HttpResponse::Ok().json(UnreadNotificationsJSON {
+            unread
+        }))
+    }
+
+    /// Marks one of the current user's notifications as read
+    #[get("/mark_notification_read")]
+    pub async fn mark_notification_read(
+        user: AuthUser,
+        query: web::Query<MarkNotificationReadQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::notification_service::mark_notification_read(&app_data.pool, user.id, query.notification_id)
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Marks all of the current user's notifications as read
+    #[get("/mark_all_notifications_read")]
+    pub async fn mark_all_notifications_read(
+        user: AuthUser,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::notification_service::mark_all_notifications_read(&app_data.pool, user.id)
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Returns how the current user receives notifications
+    #[get("/get_notification_preferences")]
+    pub async fn get_notification_preferences(
+        user: AuthUser,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let preference = generic_http_err!(
+            services::notification_service::get_notification_preference(&app_data.pool, user.id)
+            .await);
+
+        Ok(HttpResponse::Ok().json(notification_preferences_json(preference)))
+    }
+
+    /// Sets how the current user receives notifications: listed in the app, emailed as they happen, and emailed in a daily digest
+    #[get("/set_notification_preferences")]
+    pub async fn set_notification_preferences(
+        req: HttpRequest,
+        user: AuthUser,
+        query: web::Query<SetNotificationPreferencesQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let current = generic_http_err!(
+            services::notification_service::get_notification_preference(&app_data.pool, user.id)
+            .await);
+
+        let preference = generic_http_err!(
+            services::notification_service::set_notification_preference(&app_data.pool, &audit_context(&req, Some(user.id)), user.id,
+                query.in_app.unwrap_or(current.in_app), query.email.unwrap_or(current.email), query.digest.unwrap_or(current.digest))
+            .await);
+
+        Ok(HttpResponse::Ok().json(notification_preferences_json(preference)))
+    }
+}
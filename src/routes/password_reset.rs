@@ -1,10 +1,11 @@
-use actix_web::{HttpResponse, Result, web, get};
+use actix_web::{HttpRequest, HttpResponse, Result, web, get};
 use serde::{Serialize, Deserialize};
-use std::sync::{Mutex, Arc};
-use std::io::{Error, ErrorKind};
+use serde_json::json;
+use std::io::Error;
 use crate::{services, generic_http_err};
-use crate::util::{AppData, ErrorJSON, ExistsJSON, success_json, FRONTEND_URL};
-use crate::emailer;
+use crate::templates::EmailTemplate;
+use crate::i18n;
+use crate::util::{AppData, audit_context, ErrorJSON, ExistsJSON, success_json};
 
 /// Query parameters for requesting a password reset
 #[derive(Serialize, Deserialize)]
@@ -33,22 +34,25 @@ pub mod password_reset_routes {
     #[get("/request_password_reset")]
     pub async fn request_password_reset(
         query: web::Query<RequestPasswordResetQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         let password_reset = generic_http_err!(
-            services::password_reset_service::create_password_reset(&data.pool, query.email.clone())
+            services::password_reset_service::create_password_reset(&app_data.pool, query.email.clone())
+            .await);
+
+        let user = generic_http_err!(
+            services::user_service::get_user_by_email(&app_data.pool, query.email.clone())
             .await);
 
-        match emailer::send_formatted_email(
+        match services::email_outbox_service::queue_email(
+            &app_data.pool,
             query.email.clone(),
-            "GreenPoll - Password Reset".to_string(),
-            "password_reset".to_string(),
-            [("url", FRONTEND_URL), ("reset_id", &password_reset.id[..])].iter().cloned().collect()
-        ) {
+            i18n::preferred_locale(user.locale.as_deref()),
+            EmailTemplate::PasswordReset,
+            json!({ "url": app_data.config.server.frontend_url, "reset_id": password_reset.id })
+        ).await {
             Ok(_) => Ok(()),
-            Err(_) => Err(Error::new(ErrorKind::Other, "Failed to send password reset email"))
+            Err(_) => Err(Error::other("Failed to queue password reset email"))
         }?;
 
         Ok(success_json())
@@ -58,12 +62,10 @@ pub mod password_reset_routes {
     #[get("/password_reset_exists")]
     pub async fn password_reset_exists(
         query: web::Query<PasswordResetExistsQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         let exists = generic_http_err!(
-            services::password_reset_service::password_reset_exists(&data.pool, query.reset_id.clone())
+            services::password_reset_service::password_reset_exists(&app_data.pool, &app_data.config, query.reset_id.clone())
             .await);
 
         Ok(HttpResponse::Ok().json(ExistsJSON {
@@ -74,13 +76,12 @@ pub mod password_reset_routes {
     /// Resets a password
     #[get("/reset_password")]
     pub async fn reset_password(
+        req: HttpRequest,
         query: web::Query<ResetPasswordQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         generic_http_err!(
-            services::password_reset_service::reset_password(&data.pool, query.reset_id.clone(), query.new_password.clone())
+            services::password_reset_service::reset_password(&app_data.pool, &app_data.config, &audit_context(&req, None), query.reset_id.clone(), query.new_password.clone())
             .await);
 
         Ok(success_json())
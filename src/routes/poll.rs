@@ -1,8 +1,9 @@
 use actix_web::{HttpRequest, HttpResponse, Result, web, get};
 use serde::{Serialize, Deserialize};
-use std::sync::{Mutex, Arc};
+use serde_json::Value as JsonValue;
 use crate::{services, generic_http_err};
-use crate::util::{AppData, ErrorJSON, success_json, error_json, get_user_by_session};
+use crate::auth::{AuthUser, OptionalAuthUser, ReadPolls, CreatePolls};
+use crate::util::{AppData, audit_context, page_bounds, ErrorJSON, success_json, error_json};
 use crate::routes::{PollOptionJSON, PollVoteJSON};
 
 /// Query parameters for creating a poll
@@ -44,12 +45,55 @@ pub struct SetDescriptionQuery {
     description: String,
 }
 
+/// Query parameters for setting the number of times a user may change their vote on a poll
+#[derive(Serialize, Deserialize)]
+pub struct SetMaxVoteChangesQuery {
+    poll_id: i32,
+    max_vote_changes: Option<i32>,
+}
+
+/// Query parameters for setting when a poll closes
+#[derive(Serialize, Deserialize)]
+pub struct SetCloseTimeQuery {
+    poll_id: i32,
+    close_time: Option<i64>,
+}
+
+/// Query parameters for getting how a poll's vote counts changed over time
+#[derive(Serialize, Deserialize)]
+pub struct GetPollVoteTimelineQuery {
+    poll_id: i32,
+}
+
+/// Query parameters for getting a poll's votes grouped by time
+#[derive(Serialize, Deserialize)]
+pub struct GetPollVoteAnalyticsQuery {
+    poll_id: i32,
+    interval: Option<String>,
+    tz: Option<String>,
+}
+
 /// Query parameters for deleting a poll
 #[derive(Serialize, Deserialize)]
 pub struct DeletePollQuery {
     poll_id: i32,
 }
 
+/// Query parameters for inviting a user to vote on a poll
+#[derive(Serialize, Deserialize)]
+pub struct InviteToPollQuery {
+    poll_id: i32,
+    username: String,
+}
+
+/// Query parameters for getting a poll's change history
+#[derive(Serialize, Deserialize)]
+pub struct GetPollHistoryQuery {
+    poll_id: i32,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
 /// JSON representation of a poll
 #[derive(Serialize, Deserialize)]
 pub struct PollJSON {
@@ -57,6 +101,10 @@ pub struct PollJSON {
     pub user_id: i32,
     pub title: String,
     pub description: String,
+    pub closed: bool,
+    pub hidden: bool,
+    pub max_vote_changes: Option<i32>,
+    pub close_time: Option<i64>,
     pub create_time: i64,
 }
 
@@ -70,6 +118,36 @@ pub struct PollUserVoteJSON {
     pub vote_time: i64,
 }
 
+/// JSON representation of a poll option's running vote count after a vote change
+#[derive(Serialize, Deserialize)]
+pub struct PollVoteTallyJSON {
+    pub change_time: i64,
+    pub poll_option_id: i32,
+    pub votes: i64,
+}
+
+/// JSON representation of the votes for a poll option cast within one interval
+#[derive(Serialize, Deserialize)]
+pub struct PollVoteBucketJSON {
+    pub bucket_time: i64,
+    pub poll_option_id: i32,
+    pub votes: i64,
+    pub cumulative_votes: i64,
+}
+
+/// JSON representation of a change made to a poll, its options or its votes
+#[derive(Serialize, Deserialize)]
+pub struct PollHistoryEventJSON {
+    pub id: i32,
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: i32,
+    pub before_state: Option<JsonValue>,
+    pub after_state: Option<JsonValue>,
+    pub event_time: i64,
+}
+
 /// The poll routes
 pub mod poll_routes {
     use super::*;
@@ -78,15 +156,12 @@ pub mod poll_routes {
     #[get("/create_poll")]
     pub async fn create_poll(
         req: HttpRequest,
+        user: AuthUser<CreatePolls>,
         query: web::Query<CreatePollQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
-
         let poll = generic_http_err!(
-            services::poll_service::create_poll(&data.pool, user.id, query.title.clone(), query.description.clone())
+            services::poll_service::create_poll(&app_data.pool, &audit_context(&req, Some(user.id)), user.id, query.title.clone(), query.description.clone())
             .await);
 
         Ok(HttpResponse::Ok().json(PollJSON {
@@ -94,20 +169,23 @@ pub mod poll_routes {
             user_id: poll.user_id,
             title: poll.title,
             description: poll.description,
-            create_time: poll.create_time.timestamp()
+            closed: poll.closed,
+            hidden: poll.hidden,
+            max_vote_changes: poll.max_vote_changes,
+            close_time: poll.close_time.map(|close_time| close_time.assume_utc().unix_timestamp()),
+            create_time: poll.create_time.assume_utc().unix_timestamp()
         }))
     }
 
     /// Returns the poll details
     #[get("/get_poll_info")]
     pub async fn get_poll_info(
+        viewer: OptionalAuthUser<ReadPolls>,
         query: web::Query<GetPollQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         let poll = generic_http_err!(
-            services::poll_service::get_poll(&data.pool, query.poll_id)
+            services::poll_service::get_visible_poll(&app_data.pool, query.poll_id, viewer.user.as_ref())
             .await);
 
         Ok(HttpResponse::Ok().json(PollJSON {
@@ -115,20 +193,27 @@ pub mod poll_routes {
             user_id: poll.user_id,
             title: poll.title,
             description: poll.description,
-            create_time: poll.create_time.timestamp()
+            closed: poll.closed,
+            hidden: poll.hidden,
+            max_vote_changes: poll.max_vote_changes,
+            close_time: poll.close_time.map(|close_time| close_time.assume_utc().unix_timestamp()),
+            create_time: poll.create_time.assume_utc().unix_timestamp()
         }))
     }
 
     /// Returns all poll options associated with a poll
     #[get("/get_poll_options")]
     pub async fn get_poll_options(
+        viewer: OptionalAuthUser<ReadPolls>,
         query: web::Query<GetPollOptionsQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
+        generic_http_err!(
+            services::poll_service::get_visible_poll(&app_data.pool, query.poll_id, viewer.user.as_ref())
+            .await);
 
         let poll_options = generic_http_err!(
-            services::poll_service::get_poll_options(&data.pool, query.poll_id)
+            services::poll_service::get_poll_options(&app_data.pool, query.poll_id)
             .await);
 
         let options: Vec<PollOptionJSON> = poll_options.iter().map(|option| PollOptionJSON {
@@ -143,13 +228,16 @@ pub mod poll_routes {
     /// Returns all poll votes associated with a poll
     #[get("/get_poll_votes")]
     pub async fn get_poll_votes(
+        viewer: OptionalAuthUser<ReadPolls>,
         query: web::Query<GetPollVotesQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
+        generic_http_err!(
+            services::poll_service::get_visible_poll(&app_data.pool, query.poll_id, viewer.user.as_ref())
+            .await);
 
         let poll_votes = generic_http_err!(
-            services::poll_service::get_poll_votes(&data.pool, query.poll_id)
+            services::poll_service::get_poll_votes(&app_data.pool, query.poll_id)
             .await);
 
         let votes: Vec<PollVoteJSON> = poll_votes.iter().map(|vote| PollVoteJSON {
@@ -157,7 +245,7 @@ pub mod poll_routes {
             user_id: vote.user_id,
             poll_id: vote.poll_id,
             poll_option_id: vote.poll_option_id,
-            vote_time: vote.vote_time.timestamp()
+            vote_time: vote.vote_time.assume_utc().unix_timestamp()
         }).collect();
 
         Ok(HttpResponse::Ok().json(votes))
@@ -166,13 +254,16 @@ pub mod poll_routes {
     /// Returns all poll votes and user information associated with a poll
     #[get("/get_poll_user_votes")]
     pub async fn get_poll_user_votes(
+        viewer: OptionalAuthUser<ReadPolls>,
         query: web::Query<GetPollVotesQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
+        generic_http_err!(
+            services::poll_service::get_visible_poll(&app_data.pool, query.poll_id, viewer.user.as_ref())
+            .await);
 
         let poll_user_votes = generic_http_err!(
-            services::poll_service::get_poll_user_votes(&data.pool, query.poll_id)
+            services::poll_service::get_poll_user_votes(&app_data.pool, query.poll_id)
             .await);
 
         let votes: Vec<PollUserVoteJSON> = poll_user_votes.iter().map(|vote| PollUserVoteJSON {
@@ -180,7 +271,7 @@ pub mod poll_routes {
             username: vote.username.clone(),
             poll_option_id: vote.poll_option_id,
             poll_option_value: vote.poll_option_value.clone(),
-            vote_time: vote.vote_time.timestamp()
+            vote_time: vote.vote_time.assume_utc().unix_timestamp()
         }).collect();
 
         Ok(HttpResponse::Ok().json(votes))
@@ -190,24 +281,22 @@ pub mod poll_routes {
     #[get("/set_poll_title")]
     pub async fn set_poll_title(
         req: HttpRequest,
+        user: AuthUser<CreatePolls>,
         query: web::Query<SetTitleQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
         let poll = generic_http_err!(
-            services::poll_service::get_poll(&data.pool, query.poll_id)
+            services::poll_service::get_poll(&app_data.pool, query.poll_id)
             .await);
 
         if user.id == poll.user_id {
             generic_http_err!(
-                services::poll_service::set_title(&data.pool, query.poll_id, query.title.clone())
+                services::poll_service::set_title(&app_data.pool, &audit_context(&req, Some(user.id)), query.poll_id, query.title.clone())
                 .await);
 
             Ok(success_json())
         } else {
-            Ok(error_json("You do not have permission to edit this poll"))
+            Ok(error_json("poll_edit_forbidden"))
         }
     }
 
@@ -215,49 +304,197 @@ pub mod poll_routes {
     #[get("/set_poll_description")]
     pub async fn set_poll_description(
         req: HttpRequest,
+        user: AuthUser<CreatePolls>,
         query: web::Query<SetDescriptionQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
+        let poll = generic_http_err!(
+            services::poll_service::get_poll(&app_data.pool, query.poll_id)
+            .await);
+
+        if user.id == poll.user_id {
+            generic_http_err!(
+                services::poll_service::set_description(&app_data.pool, &audit_context(&req, Some(user.id)), query.poll_id, query.description.clone())
+                .await);
 
-        let user = get_user_by_session(&data.pool, req).await?;
+            Ok(success_json())
+        } else {
+            Ok(error_json("poll_edit_forbidden"))
+        }
+    }
+
+    /// Sets the number of times a user may change their vote on a poll, omitting the limit to allow unlimited changes
+    #[get("/set_poll_max_vote_changes")]
+    pub async fn set_poll_max_vote_changes(
+        req: HttpRequest,
+        user: AuthUser<CreatePolls>,
+        query: web::Query<SetMaxVoteChangesQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
         let poll = generic_http_err!(
-            services::poll_service::get_poll(&data.pool, query.poll_id)
+            services::poll_service::get_poll(&app_data.pool, query.poll_id)
             .await);
 
         if user.id == poll.user_id {
             generic_http_err!(
-                services::poll_service::set_description(&data.pool, query.poll_id, query.description.clone())
+                services::poll_service::set_max_vote_changes(&app_data.pool, &audit_context(&req, Some(user.id)), query.poll_id, query.max_vote_changes)
                 .await);
 
             Ok(success_json())
         } else {
-            Ok(error_json("You do not have permission to edit this poll"))
+            Ok(error_json("poll_edit_forbidden"))
         }
     }
 
+    /// Sets the time, in seconds since the Unix epoch, that a poll automatically closes to voting, omitting it to leave the poll open
+    #[get("/set_poll_close_time")]
+    pub async fn set_poll_close_time(
+        req: HttpRequest,
+        user: AuthUser<CreatePolls>,
+        query: web::Query<SetCloseTimeQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let poll = generic_http_err!(
+            services::poll_service::get_poll(&app_data.pool, query.poll_id)
+            .await);
+
+        if user.id == poll.user_id {
+            generic_http_err!(
+                services::poll_service::set_close_time(&app_data.pool, &audit_context(&req, Some(user.id)), query.poll_id, query.close_time)
+                .await);
+
+            Ok(success_json())
+        } else {
+            Ok(error_json("poll_edit_forbidden"))
+        }
+    }
+
+    /// Returns how the vote count of each poll option changed over time
+    #[get("/get_poll_vote_timeline")]
+    pub async fn get_poll_vote_timeline(
+        viewer: OptionalAuthUser<ReadPolls>,
+        query: web::Query<GetPollVoteTimelineQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::poll_service::get_visible_poll(&app_data.pool, query.poll_id, viewer.user.as_ref())
+            .await);
+
+        let poll_vote_timeline = generic_http_err!(
+            services::poll_vote_service::get_poll_vote_timeline(&app_data.pool, query.poll_id)
+            .await);
+
+        let timeline: Vec<PollVoteTallyJSON> = poll_vote_timeline.iter().map(|tally| PollVoteTallyJSON {
+            change_time: tally.change_time.assume_utc().unix_timestamp(),
+            poll_option_id: tally.poll_option_id,
+            votes: tally.votes
+        }).collect();
+
+        Ok(HttpResponse::Ok().json(timeline))
+    }
+
     /// Deletes a poll
     #[get("/delete_poll")]
     pub async fn delete_poll(
         req: HttpRequest,
+        user: AuthUser<CreatePolls>,
         query: web::Query<DeletePollQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
         let poll = generic_http_err!(
-            services::poll_service::get_poll(&data.pool, query.poll_id)
+            services::poll_service::get_poll(&app_data.pool, query.poll_id)
             .await);
 
         if user.id == poll.user_id {
             generic_http_err!(
-                services::poll_service::delete_poll(&data.pool, query.poll_id)
+                services::poll_service::delete_poll(&app_data.pool, &audit_context(&req, Some(user.id)), query.poll_id)
                 .await);
 
             Ok(success_json())
         } else {
-            Ok(error_json("You do not have permission to edit this poll"))
+            Ok(error_json("poll_edit_forbidden"))
+        }
+    }
+
+    /// Invites a user to vote on a poll, sending them a notification
+    #[get("/invite_to_poll")]
+    pub async fn invite_to_poll(
+        req: HttpRequest,
+        user: AuthUser<CreatePolls>,
+        query: web::Query<InviteToPollQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::notification_service::invite_to_poll(&app_data.pool, &app_data.config, &audit_context(&req, Some(user.id)), &user, query.poll_id, query.username.clone())
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Returns the change history of a poll, most recent first
+    #[get("/get_poll_history")]
+    pub async fn get_poll_history(
+        user: AuthUser<ReadPolls>,
+        query: web::Query<GetPollHistoryQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let poll = generic_http_err!(
+            services::poll_service::get_poll(&app_data.pool, query.poll_id)
+            .await);
+
+        if user.id == poll.user_id {
+            let (limit, offset) = page_bounds(query.limit, query.offset);
+
+            let audit_events = generic_http_err!(
+                services::audit_event_service::get_poll_audit_events(&app_data.pool, query.poll_id, limit, offset)
+                .await);
+
+            let events: Vec<PollHistoryEventJSON> = audit_events.into_iter().map(|event| PollHistoryEventJSON {
+                id: event.id,
+                actor_id: event.actor_id,
+                action: event.action,
+                target_type: event.target_type,
+                target_id: event.target_id,
+                before_state: event.before_state,
+                after_state: event.after_state,
+                event_time: event.event_time.assume_utc().unix_timestamp()
+            }).collect();
+
+            Ok(HttpResponse::Ok().json(events))
+        } else {
+            Ok(error_json("poll_history_forbidden"))
+        }
+    }
+
+    /// Returns when the votes on a poll were cast, grouped by option into minutes, hours or days
+    #[get("/get_poll_vote_analytics")]
+    pub async fn get_poll_vote_analytics(
+        user: AuthUser<ReadPolls>,
+        query: web::Query<GetPollVoteAnalyticsQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let poll = generic_http_err!(
+            services::poll_service::get_poll(&app_data.pool, query.poll_id)
+            .await);
+
+        if user.id == poll.user_id {
+            let interval = query.interval.as_deref().unwrap_or("hour");
+            let time_zone = query.tz.as_deref().unwrap_or("UTC");
+
+            let poll_vote_buckets = generic_http_err!(
+                services::poll_vote_service::get_poll_vote_buckets(&app_data.pool, query.poll_id, interval, time_zone)
+                .await);
+
+            let buckets: Vec<PollVoteBucketJSON> = poll_vote_buckets.iter().map(|bucket| PollVoteBucketJSON {
+                bucket_time: bucket.bucket_time.assume_utc().unix_timestamp(),
+                poll_option_id: bucket.poll_option_id,
+                votes: bucket.votes,
+                cumulative_votes: bucket.cumulative_votes
+            }).collect();
+
+            Ok(HttpResponse::Ok().json(buckets))
+        } else {
+            Ok(error_json("poll_analytics_forbidden"))
         }
     }
 }
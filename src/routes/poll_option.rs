@@ -1,8 +1,8 @@
 use actix_web::{HttpRequest, HttpResponse, Result, web, get};
 use serde::{Serialize, Deserialize};
-use std::sync::{Mutex, Arc};
 use crate::{services, generic_http_err};
-use crate::util::{AppData, ErrorJSON, success_json, error_json, get_user_by_session};
+use crate::auth::{AuthUser, CreatePolls};
+use crate::util::{AppData, audit_context, ErrorJSON, success_json, error_json};
 use crate::routes::PollJSON;
 
 /// Query parameters for creating a poll option
@@ -53,19 +53,17 @@ pub mod poll_option_routes {
     #[get("/create_poll_option")]
     pub async fn create_poll_option(
         req: HttpRequest,
+        user: AuthUser<CreatePolls>,
         query: web::Query<CreatePollOptionQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
         let poll = generic_http_err!(
-            services::poll_service::get_poll(&data.pool, query.poll_id)
+            services::poll_service::get_poll(&app_data.pool, query.poll_id)
             .await);
 
         if user.id == poll.user_id {
             let poll_option = generic_http_err!(
-                services::poll_option_service::create_poll_option(&data.pool, query.poll_id, query.value.clone())
+                services::poll_option_service::create_poll_option(&app_data.pool, &audit_context(&req, Some(user.id)), query.poll_id, query.value.clone())
                 .await);
 
             Ok(HttpResponse::Ok().json(PollOptionJSON {
@@ -74,7 +72,7 @@ pub mod poll_option_routes {
                 value: poll_option.value
             }))
         } else {
-            Ok(error_json("You do not have permission to edit this poll"))
+            Ok(error_json("poll_edit_forbidden"))
         }
     }
 
@@ -82,12 +80,10 @@ pub mod poll_option_routes {
     #[get("/get_poll_option_info")]
     pub async fn get_poll_option_info(
         query: web::Query<GetPollOptionQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         let poll_option = generic_http_err!(
-            services::poll_option_service::get_poll_option(&data.pool, query.poll_option_id)
+            services::poll_option_service::get_poll_option(&app_data.pool, query.poll_option_id)
             .await);
 
         Ok(HttpResponse::Ok().json(PollOptionJSON {
@@ -101,24 +97,22 @@ pub mod poll_option_routes {
     #[get("/set_poll_option_value")]
     pub async fn set_poll_option_value(
         req: HttpRequest,
+        user: AuthUser<CreatePolls>,
         query: web::Query<SetPollOptionValueQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
         let poll = generic_http_err!(
-            services::poll_option_service::get_poll_option_poll(&data.pool, query.poll_option_id)
+            services::poll_option_service::get_poll_option_poll(&app_data.pool, query.poll_option_id)
             .await);
 
         if user.id == poll.user_id {
             generic_http_err!(
-                services::poll_option_service::set_poll_option_value(&data.pool, query.poll_option_id, query.new_value.clone())
+                services::poll_option_service::set_poll_option_value(&app_data.pool, &audit_context(&req, Some(user.id)), query.poll_option_id, query.new_value.clone())
                 .await);
 
             Ok(success_json())
         } else {
-            Ok(error_json("You do not have permission to edit this poll"))
+            Ok(error_json("poll_edit_forbidden"))
         }
     }
 
@@ -126,12 +120,10 @@ pub mod poll_option_routes {
     #[get("/get_poll_option_poll")]
     pub async fn get_poll_option_poll(
         query: web::Query<GetPollOptionPollQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         let poll = generic_http_err!(
-            services::poll_option_service::get_poll_option_poll(&data.pool, query.poll_option_id)
+            services::poll_option_service::get_poll_option_poll(&app_data.pool, query.poll_option_id)
             .await);
 
         Ok(HttpResponse::Ok().json(PollJSON {
@@ -139,7 +131,11 @@ pub mod poll_option_routes {
             user_id: poll.user_id,
             title: poll.title,
             description: poll.description,
-            create_time: poll.create_time.timestamp()
+            closed: poll.closed,
+            hidden: poll.hidden,
+            max_vote_changes: poll.max_vote_changes,
+            close_time: poll.close_time.map(|close_time| close_time.assume_utc().unix_timestamp()),
+            create_time: poll.create_time.assume_utc().unix_timestamp()
         }))
     }
 
@@ -147,24 +143,22 @@ pub mod poll_option_routes {
     #[get("/delete_poll_option")]
     pub async fn delete_poll_option(
         req: HttpRequest,
+        user: AuthUser,
         query: web::Query<DeletePollOptionQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
         let poll = generic_http_err!(
-            services::poll_option_service::get_poll_option_poll(&data.pool, query.poll_option_id)
+            services::poll_option_service::get_poll_option_poll(&app_data.pool, query.poll_option_id)
             .await);
 
         if user.id == poll.user_id {
             generic_http_err!(
-                services::poll_option_service::delete_poll_option(&data.pool, query.poll_option_id)
+                services::poll_option_service::delete_poll_option(&app_data.pool, &audit_context(&req, Some(user.id)), query.poll_option_id)
                 .await);
 
             Ok(success_json())
         } else {
-            Ok(error_json("You do not have permission to edit this poll"))
+            Ok(error_json("poll_edit_forbidden"))
         }
     }
 }
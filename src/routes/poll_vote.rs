@@ -1,8 +1,8 @@
 use actix_web::{HttpRequest, HttpResponse, Result, web, get};
 use serde::{Serialize, Deserialize};
-use std::sync::{Mutex, Arc};
 use crate::{services, generic_http_err};
-use crate::util::{AppData, ErrorJSON, success_json, get_user_by_session};
+use crate::auth::{AuthUser, ReadPolls, Vote};
+use crate::util::{AppData, audit_context, ErrorJSON, success_json};
 use crate::routes::PollJSON;
 
 /// Query parameters for voting on a poll
@@ -29,6 +29,22 @@ pub struct GetUserVoteQuery {
     poll_id: i32,
 }
 
+/// Query parameters for getting the vote history of the current user
+#[derive(Serialize, Deserialize)]
+pub struct GetUserVoteHistoryQuery {
+    poll_id: i32,
+}
+
+/// JSON representation of a change to a user's vote, where a missing poll option marks a removed vote
+#[derive(Serialize, Deserialize)]
+pub struct PollVoteHistoryJSON {
+    pub id: i32,
+    pub user_id: i32,
+    pub poll_id: i32,
+    pub poll_option_id: Option<i32>,
+    pub change_time: i64,
+}
+
 /// JSON representation of a poll vote
 #[derive(Serialize, Deserialize)]
 pub struct PollVoteJSON {
@@ -47,15 +63,12 @@ pub mod poll_vote_routes {
     #[get("/poll_vote")]
     pub async fn poll_vote(
         req: HttpRequest,
+        user: AuthUser<Vote>,
         query: web::Query<PollVoteQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
-
         let vote = generic_http_err!(
-            services::poll_vote_service::vote(&data.pool, user.id, query.poll_option_id)
+            services::poll_vote_service::vote(&app_data.pool, &app_data.config, &audit_context(&req, Some(user.id)), user.id, query.poll_option_id)
             .await);
 
         Ok(HttpResponse::Ok().json(PollVoteJSON {
@@ -63,7 +76,7 @@ pub mod poll_vote_routes {
             user_id: vote.user_id,
             poll_id: vote.poll_id,
             poll_option_id: vote.poll_option_id,
-            vote_time: vote.vote_time.timestamp()
+            vote_time: vote.vote_time.assume_utc().unix_timestamp()
         }))
     }
 
@@ -71,15 +84,12 @@ pub mod poll_vote_routes {
     #[get("/poll_unvote")]
     pub async fn poll_unvote(
         req: HttpRequest,
+        user: AuthUser<Vote>,
         query: web::Query<PollUnvoteQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
-
         generic_http_err!(
-            services::poll_vote_service::unvote(&data.pool, user.id, query.poll_id)
+            services::poll_vote_service::unvote(&app_data.pool, &audit_context(&req, Some(user.id)), user.id, query.poll_id)
             .await);
 
         Ok(success_json())
@@ -89,12 +99,10 @@ pub mod poll_vote_routes {
     #[get("/get_poll_vote_poll")]
     pub async fn get_poll_vote_poll(
         query: web::Query<GetPollVotePollQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         let poll = generic_http_err!(
-            services::poll_vote_service::get_poll_vote_poll(&data.pool, query.poll_vote_id)
+            services::poll_vote_service::get_poll_vote_poll(&app_data.pool, query.poll_vote_id)
             .await);
 
         Ok(HttpResponse::Ok().json(PollJSON {
@@ -102,23 +110,23 @@ pub mod poll_vote_routes {
             user_id: poll.user_id,
             title: poll.title,
             description: poll.description,
-            create_time: poll.create_time.timestamp()
+            closed: poll.closed,
+            hidden: poll.hidden,
+            max_vote_changes: poll.max_vote_changes,
+            close_time: poll.close_time.map(|close_time| close_time.assume_utc().unix_timestamp()),
+            create_time: poll.create_time.assume_utc().unix_timestamp()
         }))
     }
 
     /// Returns the vote the current user created
     #[get("/get_user_vote")]
     pub async fn get_user_vote(
-        req: HttpRequest,
+        user: AuthUser<ReadPolls>,
         query: web::Query<GetUserVoteQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
-
         let vote = generic_http_err!(
-            services::poll_vote_service::get_poll_vote(&data.pool, user.id, query.poll_id)
+            services::poll_vote_service::get_poll_vote(&app_data.pool, user.id, query.poll_id)
             .await);
 
         Ok(HttpResponse::Ok().json(PollVoteJSON {
@@ -126,7 +134,29 @@ pub mod poll_vote_routes {
             user_id: vote.user_id,
             poll_id: vote.poll_id,
             poll_option_id: vote.poll_option_id,
-            vote_time: vote.vote_time.timestamp()
+            vote_time: vote.vote_time.assume_utc().unix_timestamp()
         }))
     }
+
+    /// Returns every choice the current user has made on a poll, oldest first
+    #[get("/get_user_vote_history")]
+    pub async fn get_user_vote_history(
+        user: AuthUser<ReadPolls>,
+        query: web::Query<GetUserVoteHistoryQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let vote_history = generic_http_err!(
+            services::poll_vote_service::get_user_vote_history(&app_data.pool, user.id, query.poll_id)
+            .await);
+
+        let history: Vec<PollVoteHistoryJSON> = vote_history.iter().map(|change| PollVoteHistoryJSON {
+            id: change.id,
+            user_id: change.user_id,
+            poll_id: change.poll_id,
+            poll_option_id: change.poll_option_id,
+            change_time: change.change_time.assume_utc().unix_timestamp()
+        }).collect();
+
+        Ok(HttpResponse::Ok().json(history))
+    }
 }
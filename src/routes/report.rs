@@ -0,0 +1,85 @@
+use actix_web::{HttpResponse, Result, web, get};
+use serde::{Serialize, Deserialize};
+use crate::{services, generic_http_err};
+use crate::auth::AuthUser;
+use crate::util::{AppData, ErrorJSON, success_json};
+
+/// Query parameters for reporting a poll
+#[derive(Serialize, Deserialize)]
+pub struct ReportPollQuery {
+    poll_id: i32,
+    reason: String,
+}
+
+/// Query parameters for reporting a poll option
+#[derive(Serialize, Deserialize)]
+pub struct ReportPollOptionQuery {
+    poll_option_id: i32,
+    reason: String,
+}
+
+/// Query parameters for reporting a user
+#[derive(Serialize, Deserialize)]
+pub struct ReportUserQuery {
+    user_id: i32,
+    reason: String,
+}
+
+/// JSON representation of a report
+#[derive(Serialize, Deserialize)]
+pub struct ReportJSON {
+    pub id: i32,
+    pub reporter_id: i32,
+    pub target_type: String,
+    pub target_id: i32,
+    pub reason: String,
+    pub status: String,
+    pub create_time: i64,
+}
+
+/// The report routes
+pub mod report_routes {
+    use super::*;
+
+    /// Reports a poll
+    #[get("/report_poll")]
+    pub async fn report_poll(
+        user: AuthUser,
+        query: web::Query<ReportPollQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::report_service::create_report(&app_data.pool, &app_data.config, user.id, "poll", query.poll_id, query.reason.clone())
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Reports a poll option
+    #[get("/report_poll_option")]
+    pub async fn report_poll_option(
+        user: AuthUser,
+        query: web::Query<ReportPollOptionQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::report_service::create_report(&app_data.pool, &app_data.config, user.id, "poll_option", query.poll_option_id, query.reason.clone())
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Reports a user
+    #[get("/report_user")]
+    pub async fn report_user(
+        user: AuthUser,
+        query: web::Query<ReportUserQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::report_service::create_report(&app_data.pool, &app_data.config, user.id, "user", query.user_id, query.reason.clone())
+            .await);
+
+        Ok(success_json())
+    }
+}
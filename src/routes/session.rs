@@ -0,0 +1,89 @@
+use actix_web::{HttpRequest, HttpResponse, HttpMessage, Result, web, get};
+use actix_web::cookie::{Cookie, SameSite};
+use serde::{Serialize, Deserialize};
+use crate::{services, generic_http_err};
+use crate::auth::AuthUser;
+use crate::util::{AppData, SuccessJSON, ErrorJSON, success_json};
+
+/// Query parameters for revoking a session
+#[derive(Serialize, Deserialize)]
+pub struct RevokeSessionQuery {
+    handle: String,
+}
+
+/// JSON representation of a session
+#[derive(Serialize, Deserialize)]
+pub struct SessionJSON {
+    pub handle: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub current: bool,
+    pub create_time: i64,
+    pub last_seen: i64,
+}
+
+/// The session routes
+pub mod session_routes {
+    use super::*;
+
+    /// Returns the current user's sessions, most recently used first
+    #[get("/get_sessions")]
+    pub async fn get_sessions(
+        req: HttpRequest,
+        user: AuthUser,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let current_session = generic_http_err!(
+            services::session_service::get_session(&app_data.pool, req.cookie("session_id").unwrap().value().to_string())
+            .await);
+
+        let user_sessions = generic_http_err!(
+            services::session_service::get_user_sessions(&app_data.pool, &app_data.config, user.id)
+            .await);
+
+        let sessions: Vec<SessionJSON> = user_sessions.into_iter().map(|session| SessionJSON {
+            current: session.handle == current_session.handle,
+            handle: session.handle,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            create_time: session.create_time.assume_utc().unix_timestamp(),
+            last_seen: session.last_seen.assume_utc().unix_timestamp()
+        }).collect();
+
+        Ok(HttpResponse::Ok().json(sessions))
+    }
+
+    /// Revokes one of the current user's sessions, logging out if it is the current session
+    #[get("/revoke_session")]
+    pub async fn revoke_session(
+        req: HttpRequest,
+        user: AuthUser,
+        query: web::Query<RevokeSessionQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let current_session = generic_http_err!(
+            services::session_service::get_session(&app_data.pool, req.cookie("session_id").unwrap().value().to_string())
+            .await);
+
+        generic_http_err!(
+            services::session_service::delete_session_by_handle(&app_data.pool, user.id, query.handle.clone())
+            .await);
+
+        if current_session.handle == query.handle {
+            Ok(HttpResponse::Ok()
+                .cookie(
+                    Cookie::build("session_id", "")
+                        .path("/")
+                        .secure(true)
+                        .http_only(true)
+                        .same_site(SameSite::None)
+                        .finish()
+                ).json(SuccessJSON {
+                    success: true
+                })
+            )
+        } else {
+            Ok(success_json())
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web, get};
+use serde::{Serialize, Deserialize};
+use crate::{services, generic_http_err};
+use crate::auth::AuthUser;
+use crate::util::{AppData, audit_context, SuccessJSON, ErrorJSON, success_json, session_cookie, request_ip, request_user_agent};
+
+/// Query parameters for confirming two-factor enrollment
+#[derive(Serialize, Deserialize)]
+pub struct ConfirmTwoFactorQuery {
+    code: String,
+}
+
+/// Query parameters for actions that require the current password
+#[derive(Serialize, Deserialize)]
+pub struct TwoFactorPasswordQuery {
+    password: String,
+}
+
+/// Query parameters for the second step of logging in
+#[derive(Serialize, Deserialize)]
+pub struct LoginTwoFactorQuery {
+    pending_login_token: String,
+    code: String,
+}
+
+/// JSON representation of a started two-factor enrollment
+#[derive(Serialize, Deserialize)]
+pub struct TwoFactorEnrollmentJSON {
+    pub otpauth_uri: String,
+}
+
+/// JSON representation of a set of recovery codes, the only time they are shown
+#[derive(Serialize, Deserialize)]
+pub struct RecoveryCodesJSON {
+    pub recovery_codes: Vec<String>,
+}
+
+/// The two-factor authentication routes
+pub mod two_factor_routes {
+    use super::*;
+
+    /// Starts enrolling in two-factor authentication, returning the otpauth URI for an authenticator app
+    #[get("/enable_two_factor")]
+    pub async fn enable_two_factor(
+        user: AuthUser,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let otpauth_uri = generic_http_err!(
+            services::two_factor_service::begin_enrollment(&app_data.pool, &user)
+            .await);
+
+        Ok(HttpResponse::Ok().json(TwoFactorEnrollmentJSON {
+            otpauth_uri
+        }))
+    }
+
+    /// Finishes enrolling in two-factor authentication with a code from the authenticator app, returning recovery codes
+    #[get("/confirm_two_factor")]
+    pub async fn confirm_two_factor(
+        req: HttpRequest,
+        user: AuthUser,
+        query: web::Query<ConfirmTwoFactorQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let recovery_codes = generic_http_err!(
+            services::two_factor_service::confirm_enrollment(&app_data.pool, &audit_context(&req, Some(user.id)), &user, query.code.clone())
+            .await);
+
+        Ok(HttpResponse::Ok().json(RecoveryCodesJSON {
+            recovery_codes
+        }))
+    }
+
+    /// Disables two-factor authentication
+    #[get("/disable_two_factor")]
+    pub async fn disable_two_factor(
+        req: HttpRequest,
+        user: AuthUser,
+        query: web::Query<TwoFactorPasswordQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        generic_http_err!(
+            services::two_factor_service::disable(&app_data.pool, &app_data.config, &audit_context(&req, Some(user.id)), &user, query.password.clone())
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Replaces the current user's recovery codes with new ones
+    #[get("/regenerate_recovery_codes")]
+    pub async fn regenerate_recovery_codes(
+        req: HttpRequest,
+        user: AuthUser,
+        query: web::Query<TwoFactorPasswordQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let recovery_codes = generic_http_err!(
+            services::two_factor_service::regenerate_recovery_codes(&app_data.pool, &app_data.config, &audit_context(&req, Some(user.id)), &user, query.password.clone())
+            .await);
+
+        Ok(HttpResponse::Ok().json(RecoveryCodesJSON {
+            recovery_codes
+        }))
+    }
+
+    /// Finishes logging in with a code from the authenticator app or a recovery code
+    #[get("/login_two_factor")]
+    pub async fn login_two_factor(
+        req: HttpRequest,
+        query: web::Query<LoginTwoFactorQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let session = generic_http_err!(
+            services::two_factor_service::complete_login(&app_data.pool, &app_data.config, query.pending_login_token.clone(), query.code.clone(), request_user_agent(&req), request_ip(&req))
+            .await);
+
+        Ok(HttpResponse::Ok()
+            .cookie(session_cookie(&app_data.config, session.id))
+            .json(SuccessJSON {
+                success: true
+            })
+        )
+    }
+}
@@ -1,9 +1,9 @@
 use actix_web::{HttpRequest, HttpResponse, Result, web, get};
 use serde::{Serialize, Deserialize};
-use std::sync::{Mutex, Arc};
 use crate::{services, generic_http_err};
 use crate::routes::PollJSON;
-use crate::util::{AppData, ErrorJSON, success_json, get_user_by_session};
+use crate::auth::{AuthUser, ReadPolls};
+use crate::util::{AppData, audit_context, ErrorJSON, success_json};
 
 /// Query parameters for getting a specific user's info
 #[derive(Serialize, Deserialize)]
@@ -23,12 +23,20 @@ pub struct SetPasswordQuery {
     new_password: String,
 }
 
+/// Query parameters for setting a user's locale
+#[derive(Serialize, Deserialize)]
+pub struct SetLocaleQuery {
+    locale: Option<String>,
+}
+
 /// JSON representation of a user
 #[derive(Serialize, Deserialize)]
 pub struct UserJSON {
     pub id: i32,
     pub username: String,
     pub email: String,
+    pub delete_time: Option<i64>,
+    pub locale: Option<String>,
     pub join_time: i64,
 }
 
@@ -47,18 +55,17 @@ pub mod user_routes {
     /// Returns a user's details
     #[get("/get_user_info")]
     pub async fn get_user_info(
-        req: HttpRequest,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        user: AuthUser<ReadPolls>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
+        let user = user.user;
 
         Ok(HttpResponse::Ok().json(UserJSON {
             id: user.id,
             username: user.username,
             email: user.email,
-            join_time: user.join_time.timestamp()
+            delete_time: user.delete_time.map(|delete_time| delete_time.assume_utc().unix_timestamp()),
+            locale: user.locale,
+            join_time: user.join_time.assume_utc().unix_timestamp()
         }))
     }
 
@@ -66,18 +73,16 @@ pub mod user_routes {
     #[get("/get_specific_user_info")]
     pub async fn get_specific_user_info(
         query: web::Query<GetSpecificUserQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         let user = generic_http_err!(
-            services::user_service::get_user(&data.pool, query.user_id)
+            services::user_service::get_user(&app_data.pool, query.user_id)
             .await);
 
         Ok(HttpResponse::Ok().json(SpecificUserJSON {
             id: user.id,
             username: user.username,
-            join_time: user.join_time.timestamp()
+            join_time: user.join_time.assume_utc().unix_timestamp()
         }))
     }
 
@@ -85,15 +90,27 @@ pub mod user_routes {
     #[get("/set_username")]
     pub async fn set_username(
         req: HttpRequest,
+        user: AuthUser,
         query: web::Query<SetUsernameQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
+        generic_http_err!(
+            services::user_service::set_username(&app_data.pool, &audit_context(&req, Some(user.id)), user.id, query.new_username.clone())
+            .await);
 
-        let user = get_user_by_session(&data.pool, req).await?;
+        Ok(success_json())
+    }
 
+    /// Sets the locale a user's messages and emails are translated into, or clears it if no locale is given
+    #[get("/set_locale")]
+    pub async fn set_locale(
+        req: HttpRequest,
+        user: AuthUser,
+        query: web::Query<SetLocaleQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
         generic_http_err!(
-            services::user_service::set_username(&data.pool, user.id, query.new_username.clone())
+            services::user_service::set_locale(&app_data.pool, &audit_context(&req, Some(user.id)), user.id, query.locale.clone())
             .await);
 
         Ok(success_json())
@@ -103,15 +120,12 @@ pub mod user_routes {
     #[get("/set_password")]
     pub async fn set_password(
         req: HttpRequest,
+        user: AuthUser,
         query: web::Query<SetPasswordQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
-
         generic_http_err!(
-            services::user_service::set_password(&data.pool, user.id, query.new_password.clone())
+            services::user_service::set_password(&app_data.pool, &audit_context(&req, Some(user.id)), user.id, query.new_password.clone())
             .await);
 
         Ok(success_json())
@@ -120,15 +134,11 @@ pub mod user_routes {
     /// Gets a user's polls
     #[get("/get_user_polls")]
     pub async fn get_user_polls(
-        req: HttpRequest,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        user: AuthUser<ReadPolls>,
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
-        let user = get_user_by_session(&data.pool, req).await?;
-
         let user_polls = generic_http_err!(
-            services::user_service::get_user_polls(&data.pool, user.id)
+            services::user_service::get_user_polls(&app_data.pool, user.id)
             .await);
 
         let polls: Vec<PollJSON> = user_polls.iter().map(|poll| PollJSON {
@@ -136,7 +146,11 @@ pub mod user_routes {
             user_id: poll.user_id,
             title: poll.title.clone(),
             description: poll.description.clone(),
-            create_time: poll.create_time.timestamp()
+            closed: poll.closed,
+            hidden: poll.hidden,
+            max_vote_changes: poll.max_vote_changes,
+            close_time: poll.close_time.map(|close_time| close_time.assume_utc().unix_timestamp()),
+            create_time: poll.create_time.assume_utc().unix_timestamp()
         }).collect();
 
         Ok(HttpResponse::Ok().json(polls))
@@ -1,8 +1,7 @@
-use actix_web::{HttpResponse, Result, web, get};
+use actix_web::{HttpRequest, HttpResponse, Result, web, get};
 use serde::{Serialize, Deserialize};
-use std::sync::{Mutex, Arc};
 use crate::{services, generic_http_err};
-use crate::util::{AppData, ErrorJSON, success_json};
+use crate::util::{AppData, audit_context, ErrorJSON, success_json};
 
 /// Query parameters for account verification
 #[derive(Serialize, Deserialize)]
@@ -17,13 +16,12 @@ pub mod verify_routes {
     /// Verifies a user's account
     #[get("/verify_account")]
     pub async fn verify_account(
+        req: HttpRequest,
         query: web::Query<VerifyAccountQuery>,
-        app_data: web::Data<Arc<Mutex<AppData>>>
+        app_data: web::Data<AppData>
     ) -> Result<HttpResponse> {
-        let data = app_data.lock().unwrap();
-
         generic_http_err!(
-            services::verify_service::verify_user(&data.pool, query.verify_id.clone())
+            services::verify_service::verify_user(&app_data.pool, &app_data.config, &audit_context(&req, None), query.verify_id.clone())
             .await);
 
         Ok(success_json())
@@ -0,0 +1,219 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web, get};
+use serde::{Serialize, Deserialize};
+use crate::{services, generic_http_err};
+use crate::services::{Webhook, WebhookDelivery};
+use crate::auth::{AuthUser, CreatePolls};
+use crate::util::{AppData, audit_context, page_bounds, ErrorJSON, success_json, error_json};
+
+/// Query parameters for registering a webhook
+#[derive(Serialize, Deserialize)]
+pub struct CreateWebhookQuery {
+    poll_id: i32,
+    url: String,
+    events: String,
+}
+
+/// Query parameters for getting the webhooks registered on a poll
+#[derive(Serialize, Deserialize)]
+pub struct GetWebhooksQuery {
+    poll_id: i32,
+}
+
+/// Query parameters for actions performed on a webhook
+#[derive(Serialize, Deserialize)]
+pub struct WebhookQuery {
+    webhook_id: i32,
+}
+
+/// Query parameters for getting a webhook's delivery log
+#[derive(Serialize, Deserialize)]
+pub struct GetWebhookDeliveriesQuery {
+    webhook_id: i32,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// JSON representation of a webhook, without its secret
+#[derive(Serialize, Deserialize)]
+pub struct WebhookJSON {
+    pub id: i32,
+    pub poll_id: i32,
+    pub url: String,
+    pub events: Vec<String>,
+    pub create_time: i64,
+}
+
+/// JSON representation of a newly registered webhook, which is the only time its secret is shown
+#[derive(Serialize, Deserialize)]
+pub struct NewWebhookJSON {
+    pub secret: String,
+    pub webhook: WebhookJSON,
+}
+
+/// JSON representation of a webhook delivery
+#[derive(Serialize, Deserialize)]
+pub struct WebhookDeliveryJSON {
+    pub id: i32,
+    pub event: String,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub create_time: i64,
+    pub next_attempt_time: i64,
+    pub last_attempt_time: Option<i64>,
+    pub delivered_time: Option<i64>,
+}
+
+/// Converts a webhook record into its JSON representation
+/// 
+/// # Arguments
+/// 
+/// * `webhook` - The webhook record
+fn webhook_json(webhook: Webhook) -> WebhookJSON {
+    WebhookJSON {
+        id: webhook.id,
+        poll_id: webhook.poll_id,
+        url: webhook.url,
+        events: webhook.events,
+        create_time: webhook.create_time.assume_utc().unix_timestamp()
+    }
+}
+
+/// Converts a webhook delivery record into its JSON representation
+/// 
+/// # Arguments
+/// 
+/// * `delivery` - The webhook delivery record
+fn webhook_delivery_json(delivery: WebhookDelivery) -> WebhookDeliveryJSON {
+    WebhookDeliveryJSON {
+        id: delivery.id,
+        event: delivery.event,
+        payload: delivery.payload,
+        status: delivery.status,
+        attempts: delivery.attempts,
+        response_status: delivery.response_status,
+        last_error: delivery.last_error,
+        create_time: delivery.create_time.assume_utc().unix_timestamp(),
+        next_attempt_time: delivery.next_attempt_time.assume_utc().unix_timestamp(),
+        last_attempt_time: delivery.last_attempt_time.map(|last_attempt_time| last_attempt_time.assume_utc().unix_timestamp()),
+        delivered_time: delivery.delivered_time.map(|delivered_time| delivered_time.assume_utc().unix_timestamp())
+    }
+}
+
+/// The webhook routes
+pub mod webhook_routes {
+    use super::*;
+
+    /// Registers a webhook on one of the current user's polls, with a comma-separated list of events to deliver
+    #[get("/create_webhook")]
+    pub async fn create_webhook(
+        req: HttpRequest,
+        user: AuthUser<CreatePolls>,
+        query: web::Query<CreateWebhookQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let poll = generic_http_err!(
+            services::poll_service::get_poll(&app_data.pool, query.poll_id)
+            .await);
+
+        if user.id == poll.user_id {
+            let events: Vec<String> = query.events.split(',').map(|event| event.trim().to_string()).filter(|event| !event.is_empty()).collect();
+
+            let webhook = generic_http_err!(
+                services::webhook_service::create_webhook(&app_data.pool, &app_data.config, &audit_context(&req, Some(user.id)), poll.id, query.url.clone(), events)
+                .await);
+
+            Ok(HttpResponse::Ok().json(NewWebhookJSON {
+                secret: webhook.secret.clone(),
+                webhook: webhook_json(webhook)
+            }))
+        } else {
+            Ok(error_json("poll_edit_forbidden"))
+        }
+    }
+
+    /// Returns the webhooks registered on one of the current user's polls
+    #[get("/get_webhooks")]
+    pub async fn get_webhooks(
+        user: AuthUser<CreatePolls>,
+        query: web::Query<GetWebhooksQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let poll = generic_http_err!(
+            services::poll_service::get_poll(&app_data.pool, query.poll_id)
+            .await);
+
+        if user.id == poll.user_id {
+            let webhooks = generic_http_err!(
+                services::webhook_service::get_poll_webhooks(&app_data.pool, poll.id)
+                .await);
+
+            let webhooks: Vec<WebhookJSON> = webhooks.into_iter().map(webhook_json).collect();
+
+            Ok(HttpResponse::Ok().json(webhooks))
+        } else {
+            Ok(error_json("poll_edit_forbidden"))
+        }
+    }
+
+    /// Deletes a webhook from one of the current user's polls
+    #[get("/delete_webhook")]
+    pub async fn delete_webhook(
+        req: HttpRequest,
+        user: AuthUser<CreatePolls>,
+        query: web::Query<WebhookQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let webhook = generic_http_err!(
+            services::webhook_service::get_user_webhook(&app_data.pool, user.id, query.webhook_id)
+            .await);
+
+        generic_http_err!(
+            services::webhook_service::delete_webhook(&app_data.pool, &audit_context(&req, Some(user.id)), webhook.id)
+            .await);
+
+        Ok(success_json())
+    }
+
+    /// Returns a webhook's delivery log, newest first
+    #[get("/get_webhook_deliveries")]
+    pub async fn get_webhook_deliveries(
+        user: AuthUser<CreatePolls>,
+        query: web::Query<GetWebhookDeliveriesQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let webhook = generic_http_err!(
+            services::webhook_service::get_user_webhook(&app_data.pool, user.id, query.webhook_id)
+            .await);
+
+        let (limit, offset) = page_bounds(query.limit, query.offset);
+
+        let deliveries = generic_http_err!(
+            services::webhook_service::get_webhook_deliveries(&app_data.pool, webhook.id, limit, offset)
+            .await);
+
+        let deliveries: Vec<WebhookDeliveryJSON> = deliveries.into_iter().map(webhook_delivery_json).collect();
+
+        Ok(HttpResponse::Ok().json(deliveries))
+    }
+
+    /// Sends a test event to a webhook straight away and returns the outcome of the delivery
+    #[get("/test_webhook")]
+    pub async fn test_webhook(
+        user: AuthUser<CreatePolls>,
+        query: web::Query<WebhookQuery>,
+        app_data: web::Data<AppData>
+    ) -> Result<HttpResponse> {
+        let webhook = generic_http_err!(
+            services::webhook_service::get_user_webhook(&app_data.pool, user.id, query.webhook_id)
+            .await);
+
+        let delivery = generic_http_err!(
+            services::webhook_service::send_test_event(&app_data.pool, &app_data.config, &webhook)
+            .await);
+
+        Ok(HttpResponse::Ok().json(webhook_delivery_json(delivery)))
+    }
+}
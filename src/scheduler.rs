@@ -0,0 +1,208 @@
+use std::io::{Error, Result};
+use std::time::Duration;
+use rand::Rng;
+use std::sync::Arc;
+use crate::config::{Config, SchedulerConfig};
+use crate::metrics;
+use crate::services;
+use crate::services::scheduled_job_service;
+use crate::util::DBPool;
+
+/// A background job run periodically by the scheduler
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Job {
+    ExpireSessions,
+    PruneVerifications,
+    PrunePasswordResets,
+    PruneEmailChanges,
+    PrunePendingLogins,
+    PruneLoginThrottles,
+    PruneDeletedUsers,
+    PruneSentEmails,
+    PruneNotifications,
+    PruneWebhookDeliveries,
+    ClosePolls,
+    SendDigests,
+}
+
+impl Job {
+    /// Every job
+    pub const ALL: &'static [Job] = &[
+        Job::ExpireSessions,
+        Job::PruneVerifications,
+        Job::PrunePasswordResets,
+        Job::PruneEmailChanges,
+        Job::PrunePendingLogins,
+        Job::PruneLoginThrottles,
+        Job::PruneDeletedUsers,
+        Job::PruneSentEmails,
+        Job::PruneNotifications,
+        Job::PruneWebhookDeliveries,
+        Job::ClosePolls,
+        Job::SendDigests,
+    ];
+
+    /// Returns the name of the job, which is also its key in the scheduler config
+    pub fn name(self) -> &'static str {
+        match self {
+            Job::ExpireSessions => "expire_sessions",
+            Job::PruneVerifications => "prune_verifications",
+            Job::PrunePasswordResets => "prune_password_resets",
+            Job::PruneEmailChanges => "prune_email_changes",
+            Job::PrunePendingLogins => "prune_pending_logins",
+            Job::PruneLoginThrottles => "prune_login_throttles",
+            Job::PruneDeletedUsers => "prune_deleted_users",
+            Job::PruneSentEmails => "prune_sent_emails",
+            Job::PruneNotifications => "prune_notifications",
+            Job::PruneWebhookDeliveries => "prune_webhook_deliveries",
+            Job::ClosePolls => "close_polls",
+            Job::SendDigests => "send_digests",
+        }
+    }
+
+    /// Returns the number of seconds between runs of the job
+    /// 
+    /// # Arguments
+    /// 
+    /// * `config` - The scheduler config
+    fn interval(self, config: &SchedulerConfig) -> u64 {
+        match self {
+            Job::ExpireSessions => config.expire_sessions,
+            Job::PruneVerifications => config.prune_verifications,
+            Job::PrunePasswordResets => config.prune_password_resets,
+            Job::PruneEmailChanges => config.prune_email_changes,
+            Job::PrunePendingLogins => config.prune_pending_logins,
+            Job::PruneLoginThrottles => config.prune_login_throttles,
+            Job::PruneDeletedUsers => config.prune_deleted_users,
+            Job::PruneSentEmails => config.prune_sent_emails,
+            Job::PruneNotifications => config.prune_notifications,
+            Job::PruneWebhookDeliveries => config.prune_webhook_deliveries,
+            Job::ClosePolls => config.close_polls,
+            Job::SendDigests => config.send_digests,
+        }
+    }
+
+    /// Does the job's work
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    async fn run(self, pool: &DBPool, config: &Config) -> Result<()> {
+        match self {
+            Job::ExpireSessions => services::session_service::prune_sessions(pool, config).await,
+            Job::PruneVerifications => services::verify_service::prune_verifications(pool, config).await,
+            Job::PrunePasswordResets => services::password_reset_service::prune_password_resets(pool, config).await,
+            Job::PruneEmailChanges => services::email_change_service::prune_email_changes(pool, config).await,
+            Job::PrunePendingLogins => services::two_factor_service::prune_pending_logins(pool).await,
+            Job::PruneLoginThrottles => services::login_throttle_service::prune_login_throttles(pool, config).await,
+            Job::PruneDeletedUsers => services::user_service::prune_deleted_users(pool).await,
+            Job::PruneSentEmails => services::email_outbox_service::prune_sent_emails(pool).await,
+            Job::PruneNotifications => services::notification_service::prune_notifications(pool).await,
+            Job::PruneWebhookDeliveries => services::webhook_service::prune_webhook_deliveries(pool).await,
+            Job::ClosePolls => services::poll_service::close_due_polls(pool, config).await,
+            Job::SendDigests => services::notification_service::send_digests(pool, config).await,
+        }
+    }
+}
+
+/// Returns a job's interval moved randomly earlier or later by up to the configured jitter
+/// 
+/// # Arguments
+/// 
+/// * `interval` - The number of seconds between runs of the job
+/// * `jitter` - The largest fraction of the interval to move it by
+fn jittered(interval: u64, jitter: f64) -> Duration {
+    let factor = if jitter > 0.0 {
+        1.0 + rand::thread_rng().gen_range(-jitter..jitter)
+    } else {
+        1.0
+    };
+
+    Duration::from_secs_f64(interval as f64 * factor)
+}
+
+/// Runs a job once, unless another instance is running it or ran it too recently
+/// 
+/// The job's advisory lock is held in a transaction left open for the whole run, so no two instances run a job at once,
+/// and the lock cannot outlive the run even if releasing it fails. The run is recorded so instances that wake up shortly
+/// after it finishes skip their turn instead of repeating it.
+/// 
+/// # Arguments
+/// 
+/// * `pool` - The database pool
+/// * `config` - The configuration
+/// * `job` - The job to run
+/// * `min_gap` - The fewest seconds there must be between runs on any instance
+async fn run_job(pool: &DBPool, config: &Config, job: Job, min_gap: i64) -> Result<()> {
+    let mut tx = match pool.begin().await {
+        Ok(val) => Ok(val),
+        Err(e) => Err(Error::other(format!("Failed to begin a transaction for job {}: {}", job.name(), e)))
+    }?;
+
+    if !scheduled_job_service::try_lock_job(&mut tx, job.name()).await? {
+        metrics::record_job_run(job.name(), "skipped");
+        return Ok(());
+    }
+
+    let res = match scheduled_job_service::claim_job_run(pool, job.name(), min_gap).await {
+        Ok(true) => {
+            let res = job.run(pool, config).await;
+            let error = res.as_ref().err().map(|e| e.to_string());
+            metrics::record_job_run(job.name(), if res.is_ok() { "success" } else { "failure" });
+
+            scheduled_job_service::set_job_result(pool, job.name(), error).await.and(res)
+        },
+        Ok(false) => {
+            metrics::record_job_run(job.name(), "skipped");
+            Ok(())
+        },
+        Err(e) => Err(e),
+    };
+
+    // Ending the transaction releases the lock, and if the connection is broken it is closed rather than returned to the pool, which releases it too
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to release the lock for job {}: {}", job.name(), e);
+    }
+
+    res
+}
+
+/// Runs a job forever, sleeping for its jittered interval between runs
+/// 
+/// # Arguments
+/// 
+/// * `pool` - The database pool
+/// * `config` - The configuration
+/// * `job` - The job to run
+async fn schedule_job(pool: DBPool, config: Arc<Config>, job: Job) {
+    let interval = job.interval(&config.scheduler);
+    let jitter = config.scheduler.jitter;
+
+    // Runs on the same instance can be as close together as the shortest jittered interval, less a second for timing slack
+    let min_gap = ((interval as f64 * (1.0 - jitter)) as i64 - 1).max(0);
+
+    // Stagger the first run, so jobs do not all start at once
+    let first_run_delay = Duration::from_secs_f64(interval as f64 * jitter * rand::thread_rng().gen::<f64>());
+    actix_web::rt::time::delay_for(first_run_delay).await;
+
+    loop {
+        if let Err(e) = run_job(&pool, &config, job, min_gap).await {
+            eprintln!("Scheduled job {} failed: {}", job.name(), e);
+        }
+
+        actix_web::rt::time::delay_for(jittered(interval, jitter)).await;
+    }
+}
+
+/// Starts running every job in the background at its configured interval
+/// 
+/// # Arguments
+/// 
+/// * `pool` - The database pool
+/// * `config` - The configuration
+pub fn start(pool: &DBPool, config: &Arc<Config>) {
+    for job in Job::ALL.iter() {
+        actix_web::rt::spawn(schedule_job(pool.clone(), config.clone(), *job));
+    }
+}
@@ -0,0 +1,114 @@
+use std::io::{Error, ErrorKind, Result};
+use sqlx::types::time::PrimitiveDateTime;
+use crate::util::{DBPool, generate_token, hash_token};
+use crate::{generic_service_err, generic_err};
+use crate::services;
+use crate::services::User;
+
+/// The permissions an API token can be granted
+pub const API_TOKEN_SCOPES: [&str; 3] = ["read_polls", "create_polls", "vote"];
+
+/// Representation of the API token database table, leaving out the token hash, which is only ever used to look a token up
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub create_time: PrimitiveDateTime,
+    pub expire_time: Option<PrimitiveDateTime>,
+    pub last_used: Option<PrimitiveDateTime>,
+}
+
+/// The API token service
+pub mod api_token_service {
+    use super::*;
+
+    /// Creates an API token and returns the token along with the resulting record
+    /// 
+    /// The token itself is only available here, as just its hash is stored.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user the token acts on behalf of
+    /// * `name` - A name describing what the token is used for
+    /// * `scopes` - The permissions granted to the token
+    /// * `expires_in` - The number of seconds until the token expires, or `None` if it never expires
+    pub async fn create_api_token(pool: &DBPool, user_id: i32, name: String, scopes: Vec<String>, expires_in: Option<i64>) -> Result<(String, ApiToken)> {
+        if name.is_empty() || name.len() > 63 {
+            generic_err!("api_token_name_length")
+        } else if scopes.is_empty() {
+            generic_err!("api_token_scope_required")
+        } else if scopes.iter().any(|scope| !API_TOKEN_SCOPES.contains(&&scope[..])) {
+            generic_err!("invalid_api_token_scope")
+        } else if matches!(expires_in, Some(expires_in) if expires_in <= 0) {
+            generic_err!("api_token_expiry_in_past")
+        } else {
+            let token = generate_token();
+
+            let mut res = generic_service_err!(
+                sqlx::query_file_as!(ApiToken, "sql/api_token/create_api_token.sql", user_id, name, hash_token(&token), &scopes[..], expires_in)
+                .fetch_all(pool).await,
+                "Failed to create new API token");
+
+            Ok((token, res.remove(0)))
+        }
+    }
+
+    /// Returns an unexpired API token record and the user it belongs to, marking the token as just used
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `token` - The API token
+    pub async fn use_api_token(pool: &DBPool, token: String) -> Result<(ApiToken, User)> {
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(ApiToken, "sql/api_token/use_api_token.sql", hash_token(&token))
+            .fetch_all(pool).await,
+            "Failed to fetch API token");
+
+        if res.len() == 1 {
+            let api_token = res.remove(0);
+            let user = services::user_service::get_user(pool, api_token.user_id).await?;
+
+            Ok((api_token, user))
+        } else {
+            Err(Error::other("invalid_api_token"))
+        }
+    }
+
+    /// Returns all API tokens belonging to a user, newest first
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    pub async fn get_user_api_tokens(pool: &DBPool, user_id: i32) -> Result<Vec<ApiToken>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(ApiToken, "sql/api_token/get_user_api_tokens.sql", user_id)
+            .fetch_all(pool).await,
+            "Failed to get user API tokens");
+
+        Ok(res)
+    }
+
+    /// Deletes one of a user's API tokens
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    /// * `api_token_id` - The ID of the API token
+    pub async fn delete_api_token(pool: &DBPool, user_id: i32, api_token_id: i32) -> Result<()> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(ApiToken, "sql/api_token/delete_api_token.sql", user_id, api_token_id)
+            .fetch_all(pool).await,
+            "Failed to delete API token");
+
+        if res.len() == 1 {
+            Ok(())
+        } else {
+            Err(Error::other("api_token_not_found"))
+        }
+    }
+}
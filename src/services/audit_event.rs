@@ -0,0 +1,132 @@
+use std::io::{Error, ErrorKind, Result};
+use serde_json::Value as JsonValue;
+use sqlx::types::time::PrimitiveDateTime;
+use crate::util::DBPool;
+use crate::generic_service_err;
+
+/// Representation of the audit event database table
+pub struct AuditEvent {
+    pub id: i32,
+    pub actor_id: Option<i32>,
+    pub impersonator_id: Option<i32>,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: i32,
+    pub poll_id: Option<i32>,
+    pub before_state: Option<JsonValue>,
+    pub after_state: Option<JsonValue>,
+    pub ip_address: Option<String>,
+    pub event_time: PrimitiveDateTime,
+}
+
+/// Who performed a mutation, and from where
+#[derive(Clone)]
+pub struct AuditContext {
+    pub actor_id: Option<i32>,
+    /// The administrator acting as the actor, if the actor is being impersonated
+    pub impersonator_id: Option<i32>,
+    pub ip_address: Option<String>,
+}
+
+impl AuditContext {
+    /// The context for changes made by the application itself rather than a user
+    pub fn system() -> Self {
+        Self {
+            actor_id: None,
+            impersonator_id: None,
+            ip_address: None,
+        }
+    }
+
+    /// Returns a copy of the context attributed to a different actor
+    /// 
+    /// # Arguments
+    /// 
+    /// * `actor_id` - The ID of the user performing the action
+    pub fn with_actor(&self, actor_id: i32) -> Self {
+        Self {
+            actor_id: Some(actor_id),
+            impersonator_id: self.impersonator_id,
+            ip_address: self.ip_address.clone(),
+        }
+    }
+}
+
+/// A record that can be captured in an audit event's before and after snapshots
+pub trait Auditable {
+    /// Returns a JSON snapshot of the record, excluding any secrets
+    fn audit_snapshot(&self) -> JsonValue;
+}
+
+/// The audit event service
+pub mod audit_event_service {
+    use super::*;
+
+    /// Records an audit event and returns the resulting record
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who performed the action, and from where
+    /// * `action` - The name of the action performed
+    /// * `target_type` - The type of record the action was performed on
+    /// * `target_id` - The ID of the record the action was performed on
+    /// * `poll_id` - The ID of the poll the record belongs to, if any
+    /// * `before` - The record before the action, if it existed
+    /// * `after` - The record after the action, if it still exists
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_audit_event(
+        pool: &DBPool,
+        context: &AuditContext,
+        action: &str,
+        target_type: &str,
+        target_id: i32,
+        poll_id: Option<i32>,
+        before: Option<&dyn Auditable>,
+        after: Option<&dyn Auditable>
+    ) -> Result<AuditEvent> {
+        let before_state = before.map(|record| record.audit_snapshot());
+        let after_state = after.map(|record| record.audit_snapshot());
+
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(AuditEvent, "sql/audit_event/create_audit_event.sql",
+                context.actor_id, context.impersonator_id, action, target_type, target_id, poll_id, before_state, after_state, context.ip_address)
+            .fetch_all(pool).await,
+            "Failed to create audit event");
+
+        Ok(res.remove(0))
+    }
+
+    /// Returns audit events, most recent first
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `limit` - The maximum number of events to return
+    /// * `offset` - The number of events to skip
+    pub async fn get_audit_events(pool: &DBPool, limit: i64, offset: i64) -> Result<Vec<AuditEvent>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(AuditEvent, "sql/audit_event/get_audit_events.sql", limit, offset)
+            .fetch_all(pool).await,
+            "Failed to fetch audit events");
+
+        Ok(res)
+    }
+
+    /// Returns the audit events concerning a poll and its options and votes, most recent first
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `poll_id` - The ID of the poll
+    /// * `limit` - The maximum number of events to return
+    /// * `offset` - The number of events to skip
+    pub async fn get_poll_audit_events(pool: &DBPool, poll_id: i32, limit: i64, offset: i64) -> Result<Vec<AuditEvent>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(AuditEvent, "sql/audit_event/get_poll_audit_events.sql", poll_id, limit, offset)
+            .fetch_all(pool).await,
+            "Failed to fetch poll audit events");
+
+        Ok(res)
+    }
+}
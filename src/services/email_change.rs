@@ -0,0 +1,114 @@
+use std::io::{Error, ErrorKind, Result};
+use crate::util::{DBPool, generate_token, hash_token};
+use crate::{generic_service_err, generic_err};
+use crate::services;
+use crate::services::{User, AuditContext};
+use crate::config::Config;
+
+/// Representation of the email change database table, where the ID is the hash of the ID sent to the user
+pub struct EmailChange {
+    pub id: String,
+    pub user_id: i32,
+    pub new_email: String,
+}
+
+/// The email change service
+pub mod email_change_service {
+    use super::*;
+
+    /// Creates an email change record after checking the user's password, replacing any previous one for the user, and returns the resulting record with the unhashed ID
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `user` - The user changing their email address
+    /// * `password` - The user's current password
+    /// * `new_email` - The email address to change to
+    /// * `ip_address` - The IP address the change is being requested from
+    pub async fn create_email_change(pool: &DBPool, config: &Config, user: &User, password: String, new_email: String, ip_address: Option<String>) -> Result<EmailChange> {
+        services::user_service::check_password_throttled(pool, config, user, password, ip_address.as_deref()).await?;
+
+        let email_exists = services::user_service::user_exists_for_email(pool, new_email.clone()).await?;
+
+        if email_exists {
+            generic_err!("email_in_use")
+        } else if new_email.len() < 5 || new_email.len() > 63 {
+            generic_err!("email_length")
+        } else {
+            generic_service_err!(
+                sqlx::query_file!("sql/email_change/delete_email_change_by_user.sql", user.id)
+                .fetch_all(pool).await,
+                "Failed to delete previous email change record");
+
+            let email_change_id = generate_token();
+
+            let mut res = generic_service_err!(
+                sqlx::query_file_as!(EmailChange, "sql/email_change/create_email_change.sql", hash_token(&email_change_id), user.id, new_email)
+                .fetch_all(pool).await,
+                "Failed to create new email change record");
+
+            let mut email_change = res.remove(0);
+            email_change.id = email_change_id;
+
+            Ok(email_change)
+        }
+    }
+
+    /// Returns an email change record
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `email_change_id` - The ID of the email change record
+    pub async fn get_email_change(pool: &DBPool, config: &Config, email_change_id: String) -> Result<EmailChange> {
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(EmailChange, "sql/email_change/get_email_change.sql", hash_token(&email_change_id), config.expiry.email_change)
+            .fetch_all(pool).await,
+            "Failed to fetch email change record");
+
+        if res.len() == 1 {
+            Ok(res.remove(0))
+        } else {
+            Err(Error::other("email_change_not_found"))
+        }
+    }
+
+    /// Changes a user's email address to the one being confirmed and deletes the email change record
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `context` - Where the email change is being confirmed from
+    /// * `email_change_id` - The ID of the email change record
+    pub async fn confirm_email_change(pool: &DBPool, config: &Config, context: &AuditContext, email_change_id: String) -> Result<()> {
+        let email_change = match get_email_change(pool, config, email_change_id.clone()).await {
+            Ok(email_change) => Ok(email_change),
+            Err(_) => Err(Error::other("invalid_email_change_id"))
+        }?;
+
+        generic_service_err!(
+            sqlx::query_file!("sql/email_change/delete_email_change.sql", hash_token(&email_change_id))
+            .fetch_all(pool).await,
+            "Failed to delete email change record");
+
+        services::user_service::set_email(pool, &context.with_actor(email_change.user_id), email_change.user_id, email_change.new_email).await
+    }
+
+    /// Prunes all old email change records
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    pub async fn prune_email_changes(pool: &DBPool, config: &Config) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/email_change/prune_email_changes.sql", config.expiry.email_change)
+            .fetch_all(pool).await,
+            "Failed to prune email change records");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+use actix_web::web;
+use actix_web::error::BlockingError;
+use sqlx::types::time::PrimitiveDateTime;
+use crate::util::DBPool;
+use crate::config::Config;
+use crate::generic_service_err;
+use serde_json::Value as JsonValue;
+use crate::emailer::{EmailTransport, EmailMessage};
+use crate::templates;
+use crate::metrics;
+use crate::templates::EmailTemplate;
+
+/// The number of seconds before the first retry of a failed email, doubling for each later retry
+const EMAIL_RETRY_BASE_DELAY: i64 = 30;
+
+/// The longest number of seconds between retries of a failed email
+const EMAIL_RETRY_MAX_DELAY: i64 = 6 * 60 * 60;
+
+/// The number of seconds a claimed email is left alone before another worker may try it, in case the claiming worker dies
+const EMAIL_CLAIM_LEASE: i64 = 5 * 60;
+
+/// The number of due emails a worker claims at a time
+const EMAIL_BATCH_SIZE: i64 = 20;
+
+/// Representation of the email outbox database table
+pub struct OutboxEmail {
+    pub id: i32,
+    pub to_address: String,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub create_time: PrimitiveDateTime,
+    pub next_attempt_time: PrimitiveDateTime,
+    pub last_attempt_time: Option<PrimitiveDateTime>,
+}
+
+/// Returns the number of seconds to wait before retrying an email that has failed a number of times
+/// 
+/// # Arguments
+/// 
+/// * `attempts` - The number of failed attempts so far, including the latest
+fn email_retry_delay(attempts: i32) -> i64 {
+    let exponent = (attempts - 1).clamp(0, 20) as u32;
+
+    (EMAIL_RETRY_BASE_DELAY * 2i64.pow(exponent)).min(EMAIL_RETRY_MAX_DELAY)
+}
+
+/// The email outbox service
+pub mod email_outbox_service {
+    use super::*;
+
+    /// Renders an email and queues it for delivery, returning the resulting record
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `to_address` - The address to send the email to
+    /// * `locale` - The locale to render the email in
+    /// * `template` - The email to render
+    /// * `context` - An object holding the values of the email's placeholders
+    pub async fn queue_email(pool: &DBPool, to_address: String, locale: &str, template: EmailTemplate, context: JsonValue) -> Result<OutboxEmail> {
+        let message = templates::render_email(template, locale, to_address, &context)?;
+
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(OutboxEmail, "sql/email_outbox/create_outbox_email.sql", message.to, message.subject, message.html, message.text)
+            .fetch_all(pool).await,
+            "Failed to queue email");
+
+        Ok(res.remove(0))
+    }
+
+    /// Attempts delivery of every queued email that is due, scheduling a retry with exponential backoff for each that fails, or giving up on it once it has failed too many times
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `email_transport` - The transport to deliver emails with
+    pub async fn deliver_due_emails(pool: &DBPool, config: &Config, email_transport: &Arc<dyn EmailTransport>) -> Result<()> {
+        let emails = generic_service_err!(
+            sqlx::query_file_as!(OutboxEmail, "sql/email_outbox/claim_due_emails.sql", EMAIL_BATCH_SIZE, EMAIL_CLAIM_LEASE)
+            .fetch_all(pool).await,
+            "Failed to claim due emails");
+
+        for email in emails {
+            let email_transport = email_transport.clone();
+            let message = EmailMessage {
+                to: email.to_address,
+                subject: email.subject,
+                html: email.html,
+                text: email.text,
+            };
+
+            // Transports block, so deliver on the thread pool rather than the worker thread
+            let res = web::block(move || email_transport.send(&message)).await;
+
+            match res {
+                Ok(_) => {
+                    metrics::record_email_sent();
+
+                    generic_service_err!(
+                        sqlx::query_file!("sql/email_outbox/set_email_sent.sql", email.id)
+                        .fetch_all(pool).await,
+                        "Failed to mark email as sent");
+                },
+                Err(e) => {
+                    metrics::record_email_failed();
+
+                    let error = match e {
+                        BlockingError::Error(e) => e.to_string(),
+                        BlockingError::Canceled => "Email delivery was canceled".to_string(),
+                    };
+                    let attempts = email.attempts + 1;
+                    let status = if attempts >= config.email.max_attempts { "failed" } else { "pending" };
+
+                    generic_service_err!(
+                        sqlx::query_file!("sql/email_outbox/set_email_attempt_failed.sql", email.id, status, error, email_retry_delay(attempts))
+                        .fetch_all(pool).await,
+                        "Failed to record failed email attempt");
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns a page of emails that were given up on, most recently attempted first
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `limit` - The maximum number of emails to return
+    /// * `offset` - The number of emails to skip
+    pub async fn get_failed_emails(pool: &DBPool, limit: i64, offset: i64) -> Result<Vec<OutboxEmail>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(OutboxEmail, "sql/email_outbox/get_failed_emails.sql", limit, offset)
+            .fetch_all(pool).await,
+            "Failed to fetch failed emails");
+
+        Ok(res)
+    }
+
+    /// Prunes all emails that were delivered or given up on more than a week ago
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    pub async fn prune_sent_emails(pool: &DBPool) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/email_outbox/prune_sent_emails.sql")
+            .fetch_all(pool).await,
+            "Failed to prune sent emails");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::email_outbox_service::*;
+    use crate::emailer::MemoryEmailTransport;
+    use crate::util::generate_token;
+    use crate::dbinit;
+    use serde_json::json;
+
+    #[actix_rt::test]
+    async fn delivers_queued_verify_email() {
+        let (pool, schema) = dbinit::test_pool("email_outbox").await;
+        let config = Config::default();
+        // Another test may have loaded the templates already
+        let _ = templates::load_templates(None);
+
+        let to = "outbox_test@example.com".to_string();
+        let verify_id = generate_token();
+        let queued = queue_email(&pool, to.clone(), "en", EmailTemplate::Verify,
+            json!({ "url": "https://greenpoll.example", "verify_id": verify_id })).await.unwrap();
+        assert_eq!(queued.status, "pending");
+
+        let memory_transport = Arc::new(MemoryEmailTransport::default());
+        let email_transport: Arc<dyn EmailTransport> = memory_transport.clone();
+        deliver_due_emails(&pool, &config, &email_transport).await.unwrap();
+
+        let sent = memory_transport.sent();
+        let link = format!("https://greenpoll.example/verify/{}", verify_id);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].subject, queued.subject);
+        assert!(sent[0].text.contains(&link));
+        // Values in HTML emails are escaped, which includes slashes
+        assert!(sent[0].html.contains(&format!("href=\"{}\"", link.replace('/', "&#x2F;"))));
+
+        // The body holds the verify token, so it is not kept once sent
+        let (status, html, text): (String, String, String) = sqlx::query_as("SELECT status, html, text FROM email_outbox WHERE id = $1")
+            .bind(queued.id)
+            .fetch_one(&pool).await.unwrap();
+        assert_eq!(status, "sent");
+        assert!(html.is_empty() && text.is_empty());
+
+        dbinit::drop_test_schema(&pool, &schema).await;
+    }
+}
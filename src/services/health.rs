@@ -0,0 +1,51 @@
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+use crate::util::DBPool;
+use crate::dbinit::SCHEMA_VERSION;
+use crate::generic_service_err;
+
+/// The number of seconds to wait for a pooled connection when checking the database, kept short so a busy pool fails the check instead of holding it up
+const DATABASE_ACQUIRE_TIMEOUT: u64 = 2;
+
+/// The health service
+pub mod health_service {
+    use super::*;
+
+    /// Checks a connection can be taken from the pool and answers queries
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    pub async fn check_database(pool: &DBPool) -> Result<()> {
+        let mut conn = match actix_web::rt::time::timeout(Duration::from_secs(DATABASE_ACQUIRE_TIMEOUT), pool.acquire()).await {
+            Ok(Ok(val)) => Ok(val),
+            Ok(Err(e)) => Err(Error::other(format!("Failed to acquire a database connection: {}", e))),
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, "Timed out acquiring a database connection")),
+        }?;
+
+        generic_service_err!(
+            sqlx::query_file!("sql/health/ping.sql")
+            .fetch_one(&mut conn).await,
+            "Failed to query the database");
+
+        Ok(())
+    }
+
+    /// Checks the database's schema version, as recorded by the migrations, is the one this version expects
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    pub async fn check_schema(pool: &DBPool) -> Result<()> {
+        let res = generic_service_err!(
+            sqlx::query_file!("sql/health/get_schema_version.sql")
+            .fetch_one(pool).await,
+            "Failed to check the database schema version");
+
+        if res.version >= SCHEMA_VERSION {
+            Ok(())
+        } else {
+            Err(Error::other(format!("Database schema is at version {}, not {}", res.version, SCHEMA_VERSION)))
+        }
+    }
+}
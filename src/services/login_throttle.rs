@@ -0,0 +1,173 @@
+use std::io::{Error, ErrorKind, Result};
+use serde_json::json;
+use crate::util::DBPool;
+use crate::config::Config;
+use crate::generic_service_err;
+use crate::services::User;
+use crate::services;
+use crate::i18n;
+use crate::templates::EmailTemplate;
+
+/// The number of failed logins to an account after which each further attempt must wait
+const ACCOUNT_BACKOFF_START: i32 = 3;
+
+/// The number of failed logins from an IP address after which each further attempt must wait
+const IP_BACKOFF_START: i32 = 10;
+
+/// Returns the number of seconds the next login must wait after a number of failures, doubling with each failure past the backoff start
+/// 
+/// # Arguments
+/// 
+/// * `failures` - The number of recent failed logins
+/// * `backoff_start` - The number of failures after which logins must wait
+/// * `lockout_threshold` - The number of failures after which logins are locked out
+/// * `lockout_duration` - The number of seconds a lockout lasts
+fn login_delay(failures: i32, backoff_start: i32, lockout_threshold: i32, lockout_duration: i64) -> Option<i64> {
+    if failures >= lockout_threshold {
+        Some(lockout_duration)
+    } else if failures >= backoff_start {
+        let exponent = (failures - backoff_start).min(30) as u32;
+        Some(2i64.pow(exponent).min(lockout_duration))
+    } else {
+        None
+    }
+}
+
+/// Queues an email to a user to let them know their account has been locked
+/// 
+/// # Arguments
+/// 
+/// * `pool` - The database pool
+/// * `config` - The configuration
+/// * `user` - The locked user
+async fn send_lockout_email(pool: &DBPool, config: &Config, user: &User) -> Result<()> {
+    let minutes = (config.login.lockout_duration + 59) / 60;
+    let context = json!({ "url": config.server.frontend_url, "minutes": minutes });
+
+    services::email_outbox_service::queue_email(pool, user.email.clone(), i18n::preferred_locale(user.locale.as_deref()), EmailTemplate::Lockout, context).await?;
+
+    Ok(())
+}
+
+/// The login throttle service
+pub mod login_throttle_service {
+    use super::*;
+
+    /// Returns an error if logins for an email address or from an IP address must currently wait
+    /// 
+    /// Email addresses are throttled whether or not an account exists for them, so the response does not reveal which do.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `email` - The email address being logged in to
+    /// * `ip_address` - The IP address the login is coming from
+    pub async fn check_login_allowed(pool: &DBPool, email: &str, ip_address: Option<&str>) -> Result<()> {
+        let res = generic_service_err!(
+            sqlx::query_file!("sql/login_throttle/get_login_retry_after.sql", email.to_lowercase(), ip_address)
+            .fetch_one(pool).await,
+            "Failed to check login throttle");
+
+        match res.retry_after {
+            Some(retry_after) => Err(Error::other(i18n::message_with_args("too_many_login_attempts", &[("seconds", &retry_after.max(1).to_string())]))),
+            None => Ok(()),
+        }
+    }
+
+    /// Records a failed login against an email address and an IP address, delaying or locking out further attempts as needed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `email` - The email address that was logged in to
+    /// * `ip_address` - The IP address the login came from
+    /// * `user` - The user with the email address, if one exists, who is emailed if their account becomes locked
+    pub async fn record_login_failure(pool: &DBPool, config: &Config, email: &str, ip_address: Option<&str>, user: Option<&User>) -> Result<()> {
+        let account_failures = add_login_failure(pool, config, "account", &email.to_lowercase()).await?;
+
+        if let Some(delay) = login_delay(account_failures, ACCOUNT_BACKOFF_START, config.login.lockout_threshold, config.login.lockout_duration) {
+            set_login_block(pool, "account", &email.to_lowercase(), delay).await?;
+        }
+
+        if let (Some(user), true) = (user, account_failures == config.login.lockout_threshold) {
+            if let Err(e) = send_lockout_email(pool, config, user).await {
+                eprintln!("Failed to queue lockout email: {}", e);
+            }
+        }
+
+        if let Some(ip_address) = ip_address {
+            let ip_failures = add_login_failure(pool, config, "ip", ip_address).await?;
+
+            if let Some(delay) = login_delay(ip_failures, IP_BACKOFF_START, config.login.ip_lockout_threshold, config.login.lockout_duration) {
+                set_login_block(pool, "ip", ip_address, delay).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the failed logins to an email address after a successful login
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `email` - The email address that was logged in to
+    pub async fn clear_login_failures(pool: &DBPool, email: &str) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/login_throttle/delete_login_throttle.sql", "account", email.to_lowercase())
+            .fetch_all(pool).await,
+            "Failed to clear failed logins");
+
+        Ok(())
+    }
+
+    /// Increments the recent failed logins for a subject and returns the new count
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `scope` - What the subject is, either `account` or `ip`
+    /// * `subject` - The email address or IP address
+    async fn add_login_failure(pool: &DBPool, config: &Config, scope: &str, subject: &str) -> Result<i32> {
+        let res = generic_service_err!(
+            sqlx::query_file!("sql/login_throttle/add_login_failure.sql", scope, subject, config.login.lockout_duration)
+            .fetch_one(pool).await,
+            "Failed to record failed login");
+
+        Ok(res.failures)
+    }
+
+    /// Blocks logins for a subject for a number of seconds
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `scope` - What the subject is, either `account` or `ip`
+    /// * `subject` - The email address or IP address
+    /// * `seconds` - How long to block logins for
+    async fn set_login_block(pool: &DBPool, scope: &str, subject: &str, seconds: i64) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/login_throttle/set_login_block.sql", scope, subject, seconds)
+            .fetch_all(pool).await,
+            "Failed to throttle logins");
+
+        Ok(())
+    }
+
+    /// Prunes all failed logins that are no longer recent or blocking
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    pub async fn prune_login_throttles(pool: &DBPool, config: &Config) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/login_throttle/prune_login_throttles.sql", config.login.lockout_duration)
+            .fetch_all(pool).await,
+            "Failed to prune failed logins");
+
+        Ok(())
+    }
+}
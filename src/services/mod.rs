@@ -5,6 +5,18 @@ mod poll_vote;
 mod session;
 mod verify;
 mod password_reset;
+mod email_change;
+mod email_outbox;
+mod audit_event;
+mod report;
+mod api_token;
+mod two_factor;
+mod login_throttle;
+mod rate_limit;
+mod notification;
+mod webhook;
+mod scheduled_job;
+mod health;
 
 pub use user::*;
 pub use poll::*;
@@ -13,3 +25,15 @@ pub use poll_vote::*;
 pub use session::*;
 pub use verify::*;
 pub use password_reset::*;
+pub use email_change::*;
+pub use email_outbox::*;
+pub use audit_event::*;
+pub use report::*;
+pub use api_token::*;
+pub use two_factor::*;
+pub use login_throttle::*;
+pub use rate_limit::*;
+pub use notification::*;
+pub use webhook::*;
+pub use scheduled_job::*;
+pub use health::*;
@@ -0,0 +1,413 @@
+use std::io::{Error, ErrorKind, Result};
+use sqlx::types::time::PrimitiveDateTime;
+use serde_json::{json, Value as JsonValue};
+use crate::util::DBPool;
+use crate::config::Config;
+use crate::i18n;
+use crate::{generic_service_err, generic_err};
+use crate::services;
+use crate::services::{Poll, User, AuditContext, Auditable};
+use crate::services::audit_event_service::create_audit_event;
+use crate::templates::EmailTemplate;
+
+/// The vote counts at which a poll's creator is notified, after which they are notified at every multiple of `VOTE_MILESTONE_STEP`
+const VOTE_MILESTONES: [i64; 6] = [10, 25, 50, 100, 250, 500];
+
+/// The number of votes between milestones once a poll has passed the last of `VOTE_MILESTONES`
+const VOTE_MILESTONE_STEP: i64 = 1000;
+
+/// Representation of the notification database table
+pub struct Notification {
+    pub id: i32,
+    pub user_id: i32,
+    pub kind: String,
+    pub poll_id: Option<i32>,
+    pub data: JsonValue,
+    pub read: bool,
+    pub create_time: PrimitiveDateTime,
+}
+
+/// Representation of the notification preference database table, controlling how a user receives notifications
+pub struct NotificationPreference {
+    pub user_id: i32,
+    pub in_app: bool,
+    pub email: bool,
+    pub digest: bool,
+}
+
+/// The events a user can be notified of
+#[derive(Clone, Copy)]
+pub enum NotificationKind {
+    /// A poll the user created has reached a milestone number of votes
+    VoteMilestone,
+    /// A poll the user created or voted on has been closed
+    PollClosed,
+    /// Another user has invited the user to vote on a poll
+    PollInvite,
+}
+
+impl NotificationKind {
+    /// Returns the name the kind of notification is stored as
+    pub fn name(self) -> &'static str {
+        match self {
+            NotificationKind::VoteMilestone => "vote_milestone",
+            NotificationKind::PollClosed => "poll_closed",
+            NotificationKind::PollInvite => "poll_invite",
+        }
+    }
+}
+
+impl Auditable for Notification {
+    fn audit_snapshot(&self) -> JsonValue {
+        json!({
+            "id": self.id,
+            "user_id": self.user_id,
+            "kind": self.kind,
+            "poll_id": self.poll_id,
+            "data": self.data,
+            "create_time": self.create_time.assume_utc().unix_timestamp(),
+        })
+    }
+}
+
+impl Auditable for NotificationPreference {
+    fn audit_snapshot(&self) -> JsonValue {
+        json!({
+            "user_id": self.user_id,
+            "in_app": self.in_app,
+            "email": self.email,
+            "digest": self.digest,
+        })
+    }
+}
+
+impl Notification {
+    /// Returns the text of the notification, translated into a locale
+    /// 
+    /// # Arguments
+    /// 
+    /// * `locale` - The locale to translate into
+    pub fn message(&self, locale: &str) -> String {
+        let values: Vec<(String, String)> = match self.data.as_object() {
+            Some(data) => data.iter().map(|(key, value)| match value {
+                JsonValue::String(value) => (key.clone(), value.clone()),
+                value => (key.clone(), value.to_string()),
+            }).collect(),
+            None => Vec::new(),
+        };
+        let args: Vec<(&str, &str)> = values.iter().map(|(key, value)| (&key[..], &value[..])).collect();
+
+        i18n::translate_with_args(locale, &format!("notification_{}", self.kind), &args)
+            .unwrap_or_else(|| self.kind.clone())
+    }
+}
+
+/// Returns whether or not a poll's creator should be notified that it has reached a number of votes
+/// 
+/// # Arguments
+/// 
+/// * `votes` - The number of votes on the poll
+fn is_vote_milestone(votes: i64) -> bool {
+    VOTE_MILESTONES.contains(&votes) || (votes >= VOTE_MILESTONE_STEP && votes % VOTE_MILESTONE_STEP == 0)
+}
+
+/// Returns the locale a user's emails are written in, which does not depend on the request that caused them
+/// 
+/// # Arguments
+/// 
+/// * `user` - The user
+fn email_locale(user: &User) -> &'static str {
+    user.locale.as_deref().and_then(i18n::supported_locale).unwrap_or(i18n::DEFAULT_LOCALE)
+}
+
+/// The notification service
+pub mod notification_service {
+    use super::*;
+
+    /// Returns a user's notification preferences, which are only in-app notifications if they have never been set
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    pub async fn get_notification_preference(pool: &DBPool, user_id: i32) -> Result<NotificationPreference> {
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(NotificationPreference, "sql/notification/get_notification_preference.sql", user_id)
+            .fetch_all(pool).await,
+            "Failed to fetch notification preferences");
+
+        if res.len() == 1 {
+            Ok(res.remove(0))
+        } else {
+            Ok(NotificationPreference {
+                user_id,
+                in_app: true,
+                email: false,
+                digest: false,
+            })
+        }
+    }
+
+    /// Sets how a user receives notifications and returns the resulting record
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
+    /// * `user_id` - The ID of the user
+    /// * `in_app` - Whether or not notifications are listed in the app
+    /// * `email` - Whether or not each notification is emailed as it happens
+    /// * `digest` - Whether or not unread notifications are emailed in a daily digest
+    pub async fn set_notification_preference(pool: &DBPool, context: &AuditContext, user_id: i32, in_app: bool, email: bool, digest: bool) -> Result<NotificationPreference> {
+        let before = get_notification_preference(pool, user_id).await?;
+
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(NotificationPreference, "sql/notification/set_notification_preference.sql", user_id, in_app, email, digest)
+            .fetch_all(pool).await,
+            "Failed to set notification preferences");
+        let after = res.remove(0);
+
+        create_audit_event(pool, context, "set_notification_preference", "user", user_id, None, Some(&before), Some(&after)).await?;
+
+        Ok(after)
+    }
+
+    /// Notifies a user of an event according to their preferences, returning the resulting record, or nothing if the user has
+    /// turned notifications off or has already been notified of the same event
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `user_id` - The ID of the user to notify
+    /// * `kind` - The kind of event
+    /// * `poll_id` - The ID of the poll the event happened on
+    /// * `data` - An object holding the values of the notification's placeholders
+    /// * `dedupe_key` - A key identifying the event, so the user is notified of it only once
+    pub async fn create_notification(pool: &DBPool, config: &Config, user_id: i32, kind: NotificationKind, poll_id: i32, data: JsonValue, dedupe_key: Option<String>) -> Result<Option<Notification>> {
+        let preference = get_notification_preference(pool, user_id).await?;
+
+        if !preference.in_app && !preference.email && !preference.digest {
+            return Ok(None);
+        }
+
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(Notification, "sql/notification/create_notification.sql",
+                user_id, kind.name(), poll_id, data, dedupe_key, preference.in_app, !preference.digest)
+            .fetch_all(pool).await,
+            "Failed to create notification");
+
+        if res.is_empty() {
+            return Ok(None);
+        }
+
+        let notification = res.remove(0);
+
+        if preference.email {
+            let user = services::user_service::get_user(pool, user_id).await?;
+            let locale = email_locale(&user);
+            let context = json!({ "url": config.server.frontend_url, "message": notification.message(locale), "poll_id": poll_id });
+
+            services::email_outbox_service::queue_email(pool, user.email, locale, EmailTemplate::Notification, context).await?;
+        }
+
+        Ok(Some(notification))
+    }
+
+    /// Notifies a poll's creator if it has just reached a milestone number of votes
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `poll` - The poll that was voted on
+    pub async fn notify_vote_milestone(pool: &DBPool, config: &Config, poll: &Poll) -> Result<()> {
+        let votes = services::poll_service::get_num_poll_votes(pool, poll.id).await?;
+
+        if is_vote_milestone(votes) {
+            create_notification(pool, config, poll.user_id, NotificationKind::VoteMilestone, poll.id,
+                json!({ "title": poll.title, "votes": votes }),
+                Some(format!("vote_milestone:{}:{}", poll.id, votes))).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Notifies a poll's creator and everyone who voted on it that it has been closed, other than whoever closed it
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `context` - Who closed the poll
+    /// * `poll` - The poll that was closed
+    pub async fn notify_poll_closed(pool: &DBPool, config: &Config, context: &AuditContext, poll: &Poll) -> Result<()> {
+        let mut user_ids: Vec<i32> = services::poll_service::get_poll_votes(pool, poll.id).await?
+            .into_iter()
+            .map(|vote| vote.user_id)
+            .collect();
+        user_ids.push(poll.user_id);
+        user_ids.sort_unstable();
+        user_ids.dedup();
+
+        for user_id in user_ids.into_iter().filter(|user_id| Some(*user_id) != context.actor_id) {
+            create_notification(pool, config, user_id, NotificationKind::PollClosed, poll.id,
+                json!({ "title": poll.title }), None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Invites a user to vote on a poll by notifying them, which only the poll's creator may do, and only once per user
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `context` - Where the invitation is being sent from
+    /// * `inviter` - The user sending the invitation
+    /// * `poll_id` - The ID of the poll
+    /// * `username` - The username of the user to invite
+    pub async fn invite_to_poll(pool: &DBPool, config: &Config, context: &AuditContext, inviter: &User, poll_id: i32, username: String) -> Result<()> {
+        let poll = services::poll_service::get_poll(pool, poll_id).await?;
+        let invitee = services::user_service::get_user_by_username(pool, username).await?;
+
+        if poll.user_id != inviter.id {
+            generic_err!("poll_invite_forbidden")
+        } else if invitee.id == inviter.id {
+            generic_err!("invite_self")
+        } else if poll.is_closed() {
+            generic_err!("poll_closed")
+        } else if poll.hidden {
+            generic_err!("poll_hidden")
+        } else {
+            let notification = create_notification(pool, config, invitee.id, NotificationKind::PollInvite, poll.id,
+                json!({ "title": poll.title, "username": inviter.username }),
+                Some(format!("poll_invite:{}", poll.id))).await?;
+
+            if let Some(notification) = notification {
+                create_audit_event(pool, context, "invite_to_poll", "notification", notification.id, Some(poll.id), None, Some(&notification)).await?;
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Returns a page of a user's in-app notifications, newest first
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    /// * `unread_only` - Whether or not to leave out notifications that have been read
+    /// * `limit` - The maximum number of notifications to return
+    /// * `offset` - The number of notifications to skip
+    pub async fn get_notifications(pool: &DBPool, user_id: i32, unread_only: bool, limit: i64, offset: i64) -> Result<Vec<Notification>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(Notification, "sql/notification/get_user_notifications.sql", user_id, unread_only, limit, offset)
+            .fetch_all(pool).await,
+            "Failed to fetch notifications");
+
+        Ok(res)
+    }
+
+    /// Returns the number of a user's in-app notifications that have not been read
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    pub async fn get_num_unread_notifications(pool: &DBPool, user_id: i32) -> Result<i64> {
+        let res = generic_service_err!(
+            sqlx::query_file!("sql/notification/get_num_unread_notifications.sql", user_id)
+            .fetch_one(pool).await,
+            "Failed to count unread notifications");
+
+        Ok(res.count)
+    }
+
+    /// Marks one of a user's notifications as read
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    /// * `notification_id` - The ID of the notification
+    pub async fn mark_notification_read(pool: &DBPool, user_id: i32, notification_id: i32) -> Result<()> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(Notification, "sql/notification/mark_notification_read.sql", notification_id, user_id)
+            .fetch_all(pool).await,
+            "Failed to mark notification as read");
+
+        if res.len() == 1 {
+            Ok(())
+        } else {
+            generic_err!("notification_not_found")
+        }
+    }
+
+    /// Marks all of a user's notifications as read
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    pub async fn mark_all_notifications_read(pool: &DBPool, user_id: i32) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/notification/mark_all_notifications_read.sql", user_id)
+            .fetch_all(pool).await,
+            "Failed to mark notifications as read");
+
+        Ok(())
+    }
+
+    /// Queues a digest email of unread notifications for every user who wants one and has not had one within the digest
+    /// interval
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    pub async fn send_digests(pool: &DBPool, config: &Config) -> Result<()> {
+        let users = generic_service_err!(
+            sqlx::query_file!("sql/notification/claim_due_digest_users.sql", config.notifications.digest_interval)
+            .fetch_all(pool).await,
+            "Failed to claim users due a digest");
+
+        for digest_user in users {
+            let notifications = generic_service_err!(
+                sqlx::query_file_as!(Notification, "sql/notification/claim_digest_notifications.sql", digest_user.user_id)
+                .fetch_all(pool).await,
+                "Failed to claim digest notifications");
+
+            if notifications.is_empty() {
+                continue;
+            }
+
+            let user = services::user_service::get_user(pool, digest_user.user_id).await?;
+            let locale = email_locale(&user);
+            let notifications: Vec<JsonValue> = notifications.iter()
+                .map(|notification| json!({ "message": notification.message(locale), "poll_id": notification.poll_id }))
+                .collect();
+            let context = json!({ "url": config.server.frontend_url, "notifications": notifications });
+
+            services::email_outbox_service::queue_email(pool, user.email, locale, EmailTemplate::Digest, context).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Prunes all read notifications created more than 90 days ago
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    pub async fn prune_notifications(pool: &DBPool) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/notification/prune_notifications.sql")
+            .fetch_all(pool).await,
+            "Failed to prune notifications");
+
+        Ok(())
+    }
+}
@@ -1,8 +1,12 @@
 use std::io::{Error, ErrorKind, Result};
-use sqlx::types::time::PrimitiveDateTime;
+use sqlx::types::time::{OffsetDateTime, PrimitiveDateTime};
+use serde_json::{json, Value as JsonValue};
 use crate::util::DBPool;
+use crate::config::Config;
 use crate::{generic_service_err, generic_err};
-use crate::services::{PollOption, PollVote};
+use crate::services;
+use crate::services::{User, PollOption, PollVote, AuditContext, Auditable};
+use crate::services::audit_event_service::create_audit_event;
 
 /// Representation of the poll database table
 pub struct Poll {
@@ -10,9 +14,36 @@ pub struct Poll {
     pub user_id: i32,
     pub title: String,
     pub description: String,
+    pub closed: bool,
+    pub hidden: bool,
+    pub max_vote_changes: Option<i32>,
+    pub close_time: Option<PrimitiveDateTime>,
     pub create_time: PrimitiveDateTime,
 }
 
+impl Poll {
+    /// Returns whether the poll is closed to voting, either by its owner or because its close time has passed
+    pub fn is_closed(&self) -> bool {
+        self.closed || matches!(self.close_time, Some(close_time) if close_time.assume_utc() <= OffsetDateTime::now_utc())
+    }
+}
+
+impl Auditable for Poll {
+    fn audit_snapshot(&self) -> JsonValue {
+        json!({
+            "id": self.id,
+            "user_id": self.user_id,
+            "title": self.title,
+            "description": self.description,
+            "closed": self.closed,
+            "hidden": self.hidden,
+            "max_vote_changes": self.max_vote_changes,
+            "close_time": self.close_time.map(|close_time| close_time.assume_utc().unix_timestamp()),
+            "create_time": self.create_time.assume_utc().unix_timestamp(),
+        })
+    }
+}
+
 /// Representation of a poll vote and voter information
 pub struct PollUserVote {
     pub user_id: i32,
@@ -31,21 +62,25 @@ pub mod poll_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is creating the poll, and from where
     /// * `user_id` - The ID of the user creating the poll
     /// * `title` - The poll title
     /// * `description` - The poll description
-    pub async fn create_poll(pool: &DBPool, user_id: i32, title: String, description: String) -> Result<Poll> {
+    pub async fn create_poll(pool: &DBPool, context: &AuditContext, user_id: i32, title: String, description: String) -> Result<Poll> {
         if title.len() < 1 || title.len() > 255 {
-            generic_err!("Title must be between 1 and 255 characters")
+            generic_err!("title_length")
         } else if description.len() > 1023 {
-            generic_err!("Description must be no more than 1023 characters")
+            generic_err!("description_length")
         } else {
             let mut res = generic_service_err!(
                 sqlx::query_file_as!(Poll, "sql/poll/create_poll.sql", user_id, title, description)
                 .fetch_all(pool).await,
                 "Failed to create new poll");
+            let poll = res.remove(0);
 
-            Ok(res.remove(0))
+            create_audit_event(pool, context, "create_poll", "poll", poll.id, Some(poll.id), None, Some(&poll)).await?;
+
+            Ok(poll)
         }
     }
 
@@ -79,7 +114,25 @@ pub mod poll_service {
         if res.len() == 1 {
             Ok(res.remove(0))
         } else {
-            Err(Error::new(ErrorKind::Other, "Poll does not exist"))
+            Err(Error::other("poll_not_found"))
+        }
+    }
+
+    /// Returns a poll, provided it has not been hidden pending review or the viewer is its owner or an administrator
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `poll_id` - The ID of the poll
+    /// * `viewer` - The user viewing the poll, if logged in
+    pub async fn get_visible_poll(pool: &DBPool, poll_id: i32, viewer: Option<&User>) -> Result<Poll> {
+        let poll = get_poll(pool, poll_id).await?;
+        let privileged = matches!(viewer, Some(viewer) if viewer.id == poll.user_id || viewer.is_admin);
+
+        if poll.hidden && !privileged {
+            generic_err!("poll_hidden")
+        } else {
+            Ok(poll)
         }
     }
 
@@ -113,6 +166,21 @@ pub mod poll_service {
         Ok(res)
     }
 
+    /// Returns the number of votes on a poll
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `poll_id` - The ID of the poll
+    pub async fn get_num_poll_votes(pool: &DBPool, poll_id: i32) -> Result<i64> {
+        let res = generic_service_err!(
+            sqlx::query_file!("sql/poll/get_num_poll_votes.sql", poll_id)
+            .fetch_one(pool).await,
+            "Failed to count poll votes");
+
+        Ok(res.count)
+    }
+
     /// Returns all poll votes and user information associated with a poll
     /// 
     /// # Arguments
@@ -133,17 +201,23 @@ pub mod poll_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
     /// * `poll_id` - The ID of the poll
     /// * `title` - The new poll title
-    pub async fn set_title(pool: &DBPool, poll_id: i32, title: String) -> Result<()> {
+    pub async fn set_title(pool: &DBPool, context: &AuditContext, poll_id: i32, title: String) -> Result<()> {
         if title.len() < 1 || title.len() > 255 {
-            generic_err!("Title must be between 1 and 255 characters")
+            generic_err!("title_length")
         } else {
+            let before = get_poll(pool, poll_id).await?;
+
             generic_service_err!(
                 sqlx::query_file!("sql/poll/set_title.sql", title, poll_id)
                 .fetch_all(pool).await,
                 "Failed to set poll title");
 
+            let after = get_poll(pool, poll_id).await?;
+            create_audit_event(pool, context, "set_poll_title", "poll", poll_id, Some(poll_id), Some(&before), Some(&after)).await?;
+
             Ok(())
         }
     }
@@ -153,33 +227,170 @@ pub mod poll_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
     /// * `poll_id` - The ID of the poll
     /// * `description` - The new poll description
-    pub async fn set_description(pool: &DBPool, poll_id: i32, description: String) -> Result<()> {
+    pub async fn set_description(pool: &DBPool, context: &AuditContext, poll_id: i32, description: String) -> Result<()> {
         if description.len() > 1023 {
-            generic_err!("Description must be no more than 1023 characters")
+            generic_err!("description_length")
         } else {
+            let before = get_poll(pool, poll_id).await?;
+
             generic_service_err!(
                 sqlx::query_file!("sql/poll/set_description.sql", description, poll_id)
                 .fetch_all(pool).await,
                 "Failed to set poll description");
 
+            let after = get_poll(pool, poll_id).await?;
+            create_audit_event(pool, context, "set_poll_description", "poll", poll_id, Some(poll_id), Some(&before), Some(&after)).await?;
+
             Ok(())
         }
     }
 
+    /// Sets whether or not a poll is closed to voting
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `context` - Who is making the change, and from where
+    /// * `poll_id` - The ID of the poll
+    /// * `closed` - The new closed status
+    pub async fn set_closed(pool: &DBPool, config: &Config, context: &AuditContext, poll_id: i32, closed: bool) -> Result<()> {
+        let before = get_poll(pool, poll_id).await?;
+
+        generic_service_err!(
+            sqlx::query_file!("sql/poll/set_closed.sql", closed, poll_id)
+            .fetch_all(pool).await,
+            "Failed to set poll closed status");
+
+        let after = get_poll(pool, poll_id).await?;
+        create_audit_event(pool, context, "set_poll_closed", "poll", poll_id, Some(poll_id), Some(&before), Some(&after)).await?;
+
+        if closed && !before.closed {
+            if let Err(e) = services::notification_service::notify_poll_closed(pool, config, context, &after).await {
+                eprintln!("{}", e);
+            }
+
+            if let Err(e) = services::webhook_service::dispatch_event(pool, poll_id, "poll_closed", json!({ "title": after.title })).await {
+                eprintln!("{}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets whether or not a poll is hidden pending review
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
+    /// * `poll_id` - The ID of the poll
+    /// * `hidden` - The new hidden status
+    pub async fn set_hidden(pool: &DBPool, context: &AuditContext, poll_id: i32, hidden: bool) -> Result<()> {
+        let before = get_poll(pool, poll_id).await?;
+
+        generic_service_err!(
+            sqlx::query_file!("sql/poll/set_hidden.sql", hidden, poll_id)
+            .fetch_all(pool).await,
+            "Failed to set poll hidden status");
+
+        let after = get_poll(pool, poll_id).await?;
+        create_audit_event(pool, context, "set_poll_hidden", "poll", poll_id, Some(poll_id), Some(&before), Some(&after)).await?;
+
+        Ok(())
+    }
+
+    /// Sets the number of times a user may change their vote on a poll
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
+    /// * `poll_id` - The ID of the poll
+    /// * `max_vote_changes` - The maximum number of vote changes, zero to disallow changes, or `None` for no limit
+    pub async fn set_max_vote_changes(pool: &DBPool, context: &AuditContext, poll_id: i32, max_vote_changes: Option<i32>) -> Result<()> {
+        if max_vote_changes.unwrap_or(0) < 0 {
+            generic_err!("max_vote_changes_negative")
+        } else {
+            let before = get_poll(pool, poll_id).await?;
+
+            generic_service_err!(
+                sqlx::query_file!("sql/poll/set_max_vote_changes.sql", max_vote_changes, poll_id)
+                .fetch_all(pool).await,
+                "Failed to set poll maximum vote changes");
+
+            let after = get_poll(pool, poll_id).await?;
+            create_audit_event(pool, context, "set_poll_max_vote_changes", "poll", poll_id, Some(poll_id), Some(&before), Some(&after)).await?;
+
+            Ok(())
+        }
+    }
+
+    /// Sets the time a poll automatically closes to voting
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
+    /// * `poll_id` - The ID of the poll
+    /// * `close_time` - The time the poll closes, in seconds since the Unix epoch, or `None` to leave it open until closed by hand
+    pub async fn set_close_time(pool: &DBPool, context: &AuditContext, poll_id: i32, close_time: Option<i64>) -> Result<()> {
+        if matches!(close_time, Some(close_time) if close_time <= OffsetDateTime::now_utc().unix_timestamp()) {
+            generic_err!("close_time_in_past")
+        } else {
+            let before = get_poll(pool, poll_id).await?;
+
+            generic_service_err!(
+                sqlx::query_file!("sql/poll/set_close_time.sql", close_time, poll_id)
+                .fetch_all(pool).await,
+                "Failed to set poll close time");
+
+            let after = get_poll(pool, poll_id).await?;
+            create_audit_event(pool, context, "set_poll_close_time", "poll", poll_id, Some(poll_id), Some(&before), Some(&after)).await?;
+
+            Ok(())
+        }
+    }
+
+    /// Closes every open poll whose close time has passed, notifying voters as if their owners had closed them
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    pub async fn close_due_polls(pool: &DBPool, config: &Config) -> Result<()> {
+        let polls = generic_service_err!(
+            sqlx::query_file_as!(Poll, "sql/poll/get_polls_due_to_close.sql")
+            .fetch_all(pool).await,
+            "Failed to fetch polls due to close");
+
+        for poll in polls {
+            set_closed(pool, config, &AuditContext::system(), poll.id, true).await?;
+        }
+
+        Ok(())
+    }
+
     /// Deletes a poll
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is deleting the poll, and from where
     /// * `poll_id` - The ID of the poll
-    pub async fn delete_poll(pool: &DBPool, poll_id: i32) -> Result<()> {
+    pub async fn delete_poll(pool: &DBPool, context: &AuditContext, poll_id: i32) -> Result<()> {
+        let before = get_poll(pool, poll_id).await?;
+
         generic_service_err!(
             sqlx::query_file!("sql/poll/delete_poll.sql", poll_id)
             .fetch_all(pool).await,
             "Failed to delete poll");
 
+        create_audit_event(pool, context, "delete_poll", "poll", poll_id, Some(poll_id), Some(&before), None).await?;
+
         Ok(())
     }
 }
@@ -1,8 +1,10 @@
 use std::io::{Error, ErrorKind, Result};
+use serde_json::{json, Value as JsonValue};
 use crate::util::DBPool;
 use crate::{generic_service_err, generic_err};
 use crate::services;
-use crate::services::Poll;
+use crate::services::{Poll, AuditContext, Auditable};
+use crate::services::audit_event_service::create_audit_event;
 
 /// The maximum number of options per poll
 const NUM_POLL_OPTIONS: usize = 16;
@@ -14,6 +16,16 @@ pub struct PollOption {
     pub value: String,
 }
 
+impl Auditable for PollOption {
+    fn audit_snapshot(&self) -> JsonValue {
+        json!({
+            "id": self.id,
+            "poll_id": self.poll_id,
+            "value": self.value,
+        })
+    }
+}
+
 /// The poll option service
 pub mod poll_option_service {
     use super::*;
@@ -23,22 +35,31 @@ pub mod poll_option_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is creating the poll option, and from where
     /// * `poll_id` - The ID of the poll
     /// * `value` - The text representing the poll option
-    pub async fn create_poll_option(pool: &DBPool, poll_id: i32, value: String) -> Result<PollOption> {
+    pub async fn create_poll_option(pool: &DBPool, context: &AuditContext, poll_id: i32, value: String) -> Result<PollOption> {
         let num_poll_options = get_num_poll_options(pool, poll_id).await?;
 
         if num_poll_options >= NUM_POLL_OPTIONS {
-            generic_err!("Maximum number of poll options has been reached")
+            generic_err!("poll_option_limit")
         } else if value.len() < 1 || value.len() > 255 {
-            generic_err!("Option value must be between 1 and 255 characters")
+            generic_err!("option_value_length")
         } else {
             let mut res = generic_service_err!(
                 sqlx::query_file_as!(PollOption, "sql/poll_option/create_poll_option.sql", poll_id, value)
                 .fetch_all(pool).await,
                 "Failed to create new poll option");
+            let poll_option = res.remove(0);
 
-            Ok(res.remove(0))
+            create_audit_event(pool, context, "create_poll_option", "poll_option", poll_option.id, Some(poll_id), None, Some(&poll_option)).await?;
+
+            let event = json!({ "change": "created", "poll_option_id": poll_option.id, "value": poll_option.value });
+            if let Err(e) = services::webhook_service::dispatch_event(pool, poll_id, "option_changed", event).await {
+                eprintln!("{}", e);
+            }
+
+            Ok(poll_option)
         }
     }
 
@@ -72,7 +93,7 @@ pub mod poll_option_service {
         if res.len() == 1 {
             Ok(res.remove(0))
         } else {
-            Err(Error::new(ErrorKind::Other, "Poll option does not exist"))
+            Err(Error::other("poll_option_not_found"))
         }
     }
 
@@ -96,17 +117,28 @@ pub mod poll_option_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
     /// * `poll_option_id` - The ID of the poll option
     /// * `value` - The new text representing the poll option
-    pub async fn set_poll_option_value(pool: &DBPool, poll_option_id: i32, value: String) -> Result<()> {
+    pub async fn set_poll_option_value(pool: &DBPool, context: &AuditContext, poll_option_id: i32, value: String) -> Result<()> {
         if value.len() < 1 || value.len() > 255 {
-            generic_err!("Option value must be between 1 and 255 characters")
+            generic_err!("option_value_length")
         } else {
+            let before = get_poll_option(pool, poll_option_id).await?;
+
             generic_service_err!(
                 sqlx::query_file!("sql/poll_option/set_poll_option_value.sql", value, poll_option_id)
                 .fetch_all(pool).await,
                 "Failed to set poll option value");
 
+            let after = get_poll_option(pool, poll_option_id).await?;
+            create_audit_event(pool, context, "set_poll_option_value", "poll_option", poll_option_id, Some(after.poll_id), Some(&before), Some(&after)).await?;
+
+            let event = json!({ "change": "updated", "poll_option_id": poll_option_id, "value": after.value, "previous_value": before.value });
+            if let Err(e) = services::webhook_service::dispatch_event(pool, after.poll_id, "option_changed", event).await {
+                eprintln!("{}", e);
+            }
+
             Ok(())
         }
     }
@@ -128,13 +160,23 @@ pub mod poll_option_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is deleting the poll option, and from where
     /// * `poll_option_id` - The ID of the poll option
-    pub async fn delete_poll_option(pool: &DBPool, poll_option_id: i32) -> Result<()> {
+    pub async fn delete_poll_option(pool: &DBPool, context: &AuditContext, poll_option_id: i32) -> Result<()> {
+        let before = get_poll_option(pool, poll_option_id).await?;
+
         generic_service_err!(
             sqlx::query_file!("sql/poll_option/delete_poll_option.sql", poll_option_id)
             .fetch_all(pool).await,
             "Failed to delete poll option");
 
+        create_audit_event(pool, context, "delete_poll_option", "poll_option", poll_option_id, Some(before.poll_id), Some(&before), None).await?;
+
+        let event = json!({ "change": "deleted", "poll_option_id": poll_option_id, "value": before.value });
+        if let Err(e) = services::webhook_service::dispatch_event(pool, before.poll_id, "option_changed", event).await {
+            eprintln!("{}", e);
+        }
+
         Ok(())
     }
 }
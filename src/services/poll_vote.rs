@@ -1,9 +1,13 @@
 use std::io::{Error, ErrorKind, Result};
 use sqlx::types::time::PrimitiveDateTime;
+use serde_json::{json, Value as JsonValue};
 use crate::util::DBPool;
-use crate::generic_service_err;
+use crate::config::Config;
+use crate::{generic_service_err, generic_err};
 use crate::services;
-use crate::services::Poll;
+use crate::metrics;
+use crate::services::{Poll, AuditContext, Auditable};
+use crate::services::audit_event_service::create_audit_event;
 
 /// Representation of the poll vote database table
 pub struct PollVote {
@@ -14,6 +18,45 @@ pub struct PollVote {
     pub vote_time: PrimitiveDateTime,
 }
 
+/// Representation of the poll vote history database table, where a missing poll option marks a removed vote
+pub struct PollVoteHistory {
+    pub id: i32,
+    pub user_id: i32,
+    pub poll_id: i32,
+    pub poll_option_id: Option<i32>,
+    pub change_time: PrimitiveDateTime,
+}
+
+/// The running vote count for a poll option immediately after a vote change
+pub struct PollVoteTally {
+    pub change_time: PrimitiveDateTime,
+    pub poll_option_id: i32,
+    pub votes: i64,
+}
+
+/// The intervals that votes can be grouped into for analytics
+pub const VOTE_BUCKET_INTERVALS: [&str; 3] = ["minute", "hour", "day"];
+
+/// The number of votes for a poll option cast within one interval
+pub struct PollVoteBucket {
+    pub bucket_time: PrimitiveDateTime,
+    pub poll_option_id: i32,
+    pub votes: i64,
+    pub cumulative_votes: i64,
+}
+
+impl Auditable for PollVote {
+    fn audit_snapshot(&self) -> JsonValue {
+        json!({
+            "id": self.id,
+            "user_id": self.user_id,
+            "poll_id": self.poll_id,
+            "poll_option_id": self.poll_option_id,
+            "vote_time": self.vote_time.assume_utc().unix_timestamp(),
+        })
+    }
+}
+
 /// The poll vote service
 pub mod poll_vote_service {
     use super::*;
@@ -50,7 +93,7 @@ pub mod poll_vote_service {
         if res.len() == 1 {
             Ok(res.remove(0))
         } else {
-            Err(Error::new(ErrorKind::Other, "Poll vote does not exist"))
+            Err(Error::other("poll_vote_not_found"))
         }
     }
 
@@ -69,7 +112,7 @@ pub mod poll_vote_service {
         if res.len() == 1 {
             Ok(res.remove(0))
         } else {
-            Err(Error::new(ErrorKind::Other, "Poll vote does not exist"))
+            Err(Error::other("poll_vote_not_found"))
         }
     }
 
@@ -88,24 +131,78 @@ pub mod poll_vote_service {
         Ok(res.remove(0))
     }
 
-    /// Creates a poll vote record
+    /// Returns all of a user's votes, oldest first
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    pub async fn get_user_poll_votes(pool: &DBPool, user_id: i32) -> Result<Vec<PollVote>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(PollVote, "sql/poll_vote/get_user_poll_votes.sql", user_id)
+            .fetch_all(pool).await,
+            "Failed to fetch user votes");
+
+        Ok(res)
+    }
+
+    /// Creates or changes a user's vote on a poll and returns the resulting record
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `context` - Who is voting, and from where
     /// * `user_id` - The ID of the user voting on the poll
     /// * `poll_option_id` - The ID of the poll option
-    pub async fn vote(pool: &DBPool, user_id: i32, poll_option_id: i32) -> Result<PollVote> {
+    pub async fn vote(pool: &DBPool, config: &Config, context: &AuditContext, user_id: i32, poll_option_id: i32) -> Result<PollVote> {
         let poll = services::poll_option_service::get_poll_option_poll(pool, poll_option_id).await?;
+        let before = get_poll_vote(pool, user_id, poll.id).await.ok();
 
-        unvote(pool, user_id, poll.id).await?;
+        if poll.is_closed() {
+            generic_err!("poll_closed")
+        } else if poll.hidden {
+            generic_err!("poll_hidden")
+        } else if matches!(&before, Some(before) if before.poll_option_id == poll_option_id) {
+            get_poll_vote(pool, user_id, poll.id).await
+        } else if !can_change_vote(pool, &poll, user_id).await? {
+            generic_err!("vote_change_limit")
+        } else {
+            let mut res = match before {
+                Some(_) => generic_service_err!(
+                    sqlx::query_file_as!(PollVote, "sql/poll_vote/change_vote.sql", user_id, poll.id, poll_option_id)
+                    .fetch_all(pool).await,
+                    "Failed to change vote on poll"),
+                None => generic_service_err!(
+                    sqlx::query_file_as!(PollVote, "sql/poll_vote/vote.sql", user_id, poll.id, poll_option_id)
+                    .fetch_all(pool).await,
+                    "Failed to vote on poll"),
+            };
+            let vote = res.remove(0);
 
-        let mut res = generic_service_err!(
-            sqlx::query_file_as!(PollVote, "sql/poll_vote/vote.sql", user_id, poll.id, poll_option_id)
-            .fetch_all(pool).await,
-            "Failed to vote on poll");
+            create_poll_vote_history(pool, user_id, poll.id, Some(poll_option_id)).await?;
+            create_audit_event(pool, context, "vote", "poll_vote", vote.id, Some(poll.id),
+                before.as_ref().map(|before| before as &dyn Auditable), Some(&vote)).await?;
+            metrics::record_vote();
 
-        Ok(res.remove(0))
+            if before.is_none() {
+                if let Err(e) = services::notification_service::notify_vote_milestone(pool, config, &poll).await {
+                    eprintln!("{}", e);
+                }
+            }
+
+            let event = json!({
+                "vote_id": vote.id,
+                "user_id": user_id,
+                "poll_option_id": poll_option_id,
+                "previous_poll_option_id": before.map(|before| before.poll_option_id),
+            });
+            if let Err(e) = services::webhook_service::dispatch_event(pool, poll.id, "vote_cast", event).await {
+                eprintln!("{}", e);
+            }
+
+            Ok(vote)
+        }
     }
 
     /// Removes a user's vote from a poll
@@ -113,15 +210,37 @@ pub mod poll_vote_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is removing the vote, and from where
     /// * `user_id` - The ID of the user
     /// * `poll_id` - The ID of the poll
-    pub async fn unvote(pool: &DBPool, user_id: i32, poll_id: i32) -> Result<()> {
-        generic_service_err!(
-            sqlx::query_file!("sql/poll_vote/unvote.sql", user_id, poll_id)
-            .fetch_all(pool).await,
-            "Failed to remove vote from poll");
+    pub async fn unvote(pool: &DBPool, context: &AuditContext, user_id: i32, poll_id: i32) -> Result<()> {
+        let poll = services::poll_service::get_poll(pool, poll_id).await?;
+        let before = get_poll_vote(pool, user_id, poll_id).await.ok();
+
+        if poll.is_closed() {
+            generic_err!("poll_closed")
+        } else if let Some(before) = before {
+            if !can_change_vote(pool, &poll, user_id).await? {
+                generic_err!("vote_change_limit")
+            } else {
+                generic_service_err!(
+                    sqlx::query_file!("sql/poll_vote/unvote.sql", user_id, poll_id)
+                    .fetch_all(pool).await,
+                    "Failed to remove vote from poll");
+
+                create_poll_vote_history(pool, user_id, poll_id, None).await?;
+                create_audit_event(pool, context, "unvote", "poll_vote", before.id, Some(poll_id), Some(&before), None).await?;
+
+                let event = json!({ "vote_id": before.id, "user_id": user_id, "poll_option_id": before.poll_option_id });
+                if let Err(e) = services::webhook_service::dispatch_event(pool, poll_id, "vote_removed", event).await {
+                    eprintln!("{}", e);
+                }
 
-        Ok(())
+                Ok(())
+            }
+        } else {
+            Ok(())
+        }
     }
 
     /// Removes a user's vote from a poll given the poll option ID
@@ -129,14 +248,118 @@ pub mod poll_vote_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is removing the vote, and from where
     /// * `user_id` - The ID of the user
     /// * `poll_option_id` - The ID of the poll option
-    pub async fn unvote_by_poll_option_id(pool: &DBPool, user_id: i32, poll_option_id: i32) -> Result<()> {
-        generic_service_err!(
-            sqlx::query_file!("sql/poll_vote/unvote_by_poll_option_id.sql", user_id, poll_option_id)
+    pub async fn unvote_by_poll_option_id(pool: &DBPool, context: &AuditContext, user_id: i32, poll_option_id: i32) -> Result<()> {
+        let poll_option = services::poll_option_service::get_poll_option(pool, poll_option_id).await?;
+        let vote = get_poll_vote(pool, user_id, poll_option.poll_id).await.ok();
+
+        match vote {
+            Some(vote) if vote.poll_option_id == poll_option_id => unvote(pool, context, user_id, poll_option.poll_id).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns whether or not a user who has already voted on a poll may change their vote again
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `poll` - The poll
+    /// * `user_id` - The ID of the user
+    pub async fn can_change_vote(pool: &DBPool, poll: &Poll, user_id: i32) -> Result<bool> {
+        match poll.max_vote_changes {
+            Some(max_vote_changes) => {
+                let res = generic_service_err!(
+                    sqlx::query_file!("sql/poll_vote/get_num_vote_history_entries.sql", user_id, poll.id)
+                    .fetch_one(pool).await,
+                    "Failed to count vote changes");
+
+                // The first history entry is the original vote rather than a change
+                Ok(res.count == 0 || res.count - 1 < max_vote_changes as i64)
+            },
+            None => Ok(true),
+        }
+    }
+
+    /// Records a change to a user's vote on a poll
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    /// * `poll_id` - The ID of the poll
+    /// * `poll_option_id` - The ID of the newly chosen poll option, or `None` if the vote was removed
+    pub async fn create_poll_vote_history(pool: &DBPool, user_id: i32, poll_id: i32, poll_option_id: Option<i32>) -> Result<PollVoteHistory> {
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(PollVoteHistory, "sql/poll_vote/create_poll_vote_history.sql", user_id, poll_id, poll_option_id)
+            .fetch_all(pool).await,
+            "Failed to record vote history");
+
+        Ok(res.remove(0))
+    }
+
+    /// Returns every choice a user has made on a poll, oldest first
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    /// * `poll_id` - The ID of the poll
+    pub async fn get_user_vote_history(pool: &DBPool, user_id: i32, poll_id: i32) -> Result<Vec<PollVoteHistory>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(PollVoteHistory, "sql/poll_vote/get_user_vote_history.sql", user_id, poll_id)
             .fetch_all(pool).await,
-            "Failed to remove vote from poll");
+            "Failed to fetch user vote history");
 
-        Ok(())
+        Ok(res)
+    }
+
+    /// Returns how the vote count of each poll option changed over time, oldest first
+    /// 
+    /// Each entry holds the running total for the option affected by a vote change.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `poll_id` - The ID of the poll
+    pub async fn get_poll_vote_timeline(pool: &DBPool, poll_id: i32) -> Result<Vec<PollVoteTally>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(PollVoteTally, "sql/poll_vote/get_poll_vote_timeline.sql", poll_id)
+            .fetch_all(pool).await,
+            "Failed to fetch poll vote timeline");
+
+        Ok(res)
+    }
+
+    /// Returns the current votes on a poll grouped by option and interval, with the running total for each option
+    /// 
+    /// Intervals in which an option received no votes are omitted.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `poll_id` - The ID of the poll
+    /// * `interval` - The interval to group votes by, one of `minute`, `hour` or `day`
+    /// * `time_zone` - The IANA time zone the interval boundaries are aligned to
+    pub async fn get_poll_vote_buckets(pool: &DBPool, poll_id: i32, interval: &str, time_zone: &str) -> Result<Vec<PollVoteBucket>> {
+        let time_zone_exists = generic_service_err!(
+            sqlx::query_file!("sql/poll_vote/time_zone_exists.sql", time_zone)
+            .fetch_one(pool).await,
+            "Failed to check time zone").exists;
+
+        if !VOTE_BUCKET_INTERVALS.contains(&interval) {
+            generic_err!("invalid_interval")
+        } else if !time_zone_exists {
+            generic_err!("invalid_time_zone")
+        } else {
+            let res = generic_service_err!(
+                sqlx::query_file_as!(PollVoteBucket, "sql/poll_vote/get_poll_vote_buckets.sql", poll_id, interval, time_zone)
+                .fetch_all(pool).await,
+                "Failed to fetch poll vote analytics");
+
+            Ok(res)
+        }
     }
 }
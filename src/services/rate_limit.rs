@@ -0,0 +1,46 @@
+use std::io::{Error, ErrorKind, Result};
+use crate::util::DBPool;
+use crate::generic_service_err;
+
+/// Representation of the rate limit bucket database table, as left after taking a token
+pub struct RateLimitBucket {
+    pub tokens: f64,
+    pub allowed: bool,
+}
+
+/// The rate limit service
+pub mod rate_limit_service {
+    use super::*;
+
+    /// Refills a token bucket for the time since it was last used, then takes a token from it if one is available, creating the bucket full if it does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `key` - The key identifying the bucket
+    /// * `capacity` - The maximum number of tokens the bucket holds
+    /// * `refill_rate` - The number of tokens added to the bucket each second
+    pub async fn take_rate_limit_token(pool: &DBPool, key: &str, capacity: f64, refill_rate: f64) -> Result<RateLimitBucket> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(RateLimitBucket, "sql/rate_limit/take_rate_limit_token.sql", key, capacity, refill_rate)
+            .fetch_one(pool).await,
+            "Failed to take rate limit token");
+
+        Ok(res)
+    }
+
+    /// Prunes all rate limit buckets that have not been used for long enough to have refilled
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `max_age` - The number of seconds after which any bucket has refilled
+    pub async fn prune_rate_limit_buckets(pool: &DBPool, max_age: i64) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/rate_limit/prune_rate_limit_buckets.sql", max_age)
+            .fetch_all(pool).await,
+            "Failed to prune rate limit buckets");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use std::io::{Error, ErrorKind, Result};
+use sqlx::types::time::PrimitiveDateTime;
+use crate::util::DBPool;
+use crate::config::Config;
+use crate::{generic_service_err, generic_err};
+use crate::services;
+use crate::services::AuditContext;
+
+/// The categories a report can be filed under
+pub const REPORT_REASONS: [&str; 6] = ["spam", "harassment", "hate", "explicit", "misinformation", "other"];
+
+/// The statuses a report can have
+pub const REPORT_STATUSES: [&str; 3] = ["open", "actioned", "dismissed"];
+
+/// Representation of the report database table
+pub struct Report {
+    pub id: i32,
+    pub reporter_id: i32,
+    pub target_type: String,
+    pub target_id: i32,
+    pub reason: String,
+    pub status: String,
+    pub create_time: PrimitiveDateTime,
+}
+
+/// The report service
+pub mod report_service {
+    use super::*;
+
+    /// Creates a report and returns the resulting record, hiding the reported poll if it has received too many reports
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `reporter_id` - The ID of the user filing the report
+    /// * `target_type` - The type of record being reported, one of `poll`, `poll_option` or `user`
+    /// * `target_id` - The ID of the record being reported
+    /// * `reason` - The category the report is filed under
+    pub async fn create_report(pool: &DBPool, config: &Config, reporter_id: i32, target_type: &str, target_id: i32, reason: String) -> Result<Report> {
+        let poll_id = match target_type {
+            "poll" => Some(services::poll_service::get_poll(pool, target_id).await?.id),
+            "poll_option" => Some(services::poll_option_service::get_poll_option(pool, target_id).await?.poll_id),
+            "user" => {
+                services::user_service::get_user(pool, target_id).await?;
+                None
+            },
+            _ => return generic_err!("invalid_report_target"),
+        };
+
+        let already_reported = report_exists_for_reporter(pool, reporter_id, target_type, target_id).await?;
+
+        if already_reported {
+            generic_err!("already_reported")
+        } else if !REPORT_REASONS.contains(&&reason[..]) {
+            generic_err!("invalid_report_reason")
+        } else {
+            let mut res = generic_service_err!(
+                sqlx::query_file_as!(Report, "sql/report/create_report.sql", reporter_id, target_type, target_id, reason)
+                .fetch_all(pool).await,
+                "Failed to create new report");
+
+            if let Some(poll_id) = poll_id {
+                let num_reporters = get_num_poll_reporters(pool, poll_id, "open").await?;
+
+                if num_reporters >= config.moderation.report_threshold {
+                    services::poll_service::set_hidden(pool, &AuditContext::system(), poll_id, true).await?;
+                }
+            }
+
+            Ok(res.remove(0))
+        }
+    }
+
+    /// Returns whether or not a user has already reported a record
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `reporter_id` - The ID of the user
+    /// * `target_type` - The type of record
+    /// * `target_id` - The ID of the record
+    pub async fn report_exists_for_reporter(pool: &DBPool, reporter_id: i32, target_type: &str, target_id: i32) -> Result<bool> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(Report, "sql/report/get_report_by_reporter.sql", reporter_id, target_type, target_id)
+            .fetch_all(pool).await,
+            "Failed to check if report exists");
+
+        Ok(res.len() == 1)
+    }
+
+    /// Returns a report
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `report_id` - The ID of the report
+    pub async fn get_report(pool: &DBPool, report_id: i32) -> Result<Report> {
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(Report, "sql/report/get_report.sql", report_id)
+            .fetch_all(pool).await,
+            "Failed to fetch report");
+
+        if res.len() == 1 {
+            Ok(res.remove(0))
+        } else {
+            Err(Error::other("report_not_found"))
+        }
+    }
+
+    /// Returns a page of reports with a given status, oldest first
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `status` - The status of the reports
+    /// * `limit` - The maximum number of reports to return
+    /// * `offset` - The number of reports to skip
+    pub async fn get_reports(pool: &DBPool, status: String, limit: i64, offset: i64) -> Result<Vec<Report>> {
+        if !REPORT_STATUSES.contains(&&status[..]) {
+            generic_err!("invalid_report_status")
+        } else {
+            let res = generic_service_err!(
+                sqlx::query_file_as!(Report, "sql/report/get_reports.sql", status, limit, offset)
+                .fetch_all(pool).await,
+                "Failed to fetch reports");
+
+            Ok(res)
+        }
+    }
+
+    /// Returns the number of distinct users with reports of a given status against a poll or its options
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `poll_id` - The ID of the poll
+    /// * `status` - The status of the reports
+    pub async fn get_num_poll_reporters(pool: &DBPool, poll_id: i32, status: &str) -> Result<i64> {
+        let res = generic_service_err!(
+            sqlx::query_file!("sql/report/get_num_poll_reporters.sql", poll_id, status)
+            .fetch_one(pool).await,
+            "Failed to count poll reports");
+
+        Ok(res.count)
+    }
+
+    /// Marks a report as reviewed, either actioned or dismissed
+    /// 
+    /// A poll hidden by reports is shown again once every report against it has been dismissed.
+    /// Actioning a report leaves the poll hidden.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who is reviewing the report, and from where
+    /// * `report_id` - The ID of the report
+    /// * `status` - The new status of the report
+    pub async fn review_report(pool: &DBPool, context: &AuditContext, report_id: i32, status: &str) -> Result<Report> {
+        let report = get_report(pool, report_id).await?;
+
+        if status != "actioned" && status != "dismissed" {
+            generic_err!("invalid_report_status")
+        } else if report.status != "open" {
+            generic_err!("report_already_reviewed")
+        } else {
+            generic_service_err!(
+                sqlx::query_file!("sql/report/set_report_status.sql", status, report_id)
+                .fetch_all(pool).await,
+                "Failed to set report status");
+
+            let poll_id = match &report.target_type[..] {
+                "poll" => services::poll_service::get_poll(pool, report.target_id).await.ok().map(|poll| poll.id),
+                "poll_option" => services::poll_option_service::get_poll_option(pool, report.target_id).await.ok().map(|option| option.poll_id),
+                _ => None,
+            };
+
+            if let (Some(poll_id), "dismissed") = (poll_id, status) {
+                let num_open = get_num_poll_reporters(pool, poll_id, "open").await?;
+                let num_actioned = get_num_poll_reporters(pool, poll_id, "actioned").await?;
+
+                if num_open == 0 && num_actioned == 0 {
+                    services::poll_service::set_hidden(pool, context, poll_id, false).await?;
+                }
+            }
+
+            get_report(pool, report_id).await
+        }
+    }
+}
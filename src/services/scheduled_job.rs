@@ -0,0 +1,83 @@
+use std::io::{Error, ErrorKind, Result};
+use sqlx::{Postgres, Transaction};
+use sqlx::types::time::PrimitiveDateTime;
+use crate::util::DBPool;
+use crate::generic_service_err;
+
+/// Representation of the scheduled job database table, recording when each background job last ran on any instance
+pub struct ScheduledJob {
+    pub name: String,
+    pub last_run_time: PrimitiveDateTime,
+    pub last_success_time: Option<PrimitiveDateTime>,
+    pub last_error: Option<String>,
+    pub runs: i32,
+    pub failures: i32,
+}
+
+/// The scheduled job service
+pub mod scheduled_job_service {
+    use super::*;
+
+    /// Tries to take the advisory lock for a job, returning whether it was taken
+    /// 
+    /// The lock belongs to the transaction, so it is released when the transaction ends, however it ends, including if the connection is lost.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tx` - The database transaction to hold the lock in
+    /// * `name` - The name of the job
+    pub async fn try_lock_job(tx: &mut Transaction<'_, Postgres>, name: &str) -> Result<bool> {
+        let res = generic_service_err!(
+            sqlx::query_file!("sql/scheduled_job/try_lock_job.sql", name)
+            .fetch_one(tx).await,
+            "Failed to lock scheduled job");
+
+        Ok(res.locked)
+    }
+
+    /// Records that a job is starting, unless any instance already ran it too recently, returning whether it should run
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `name` - The name of the job
+    /// * `min_gap` - The fewest seconds there must be between runs
+    pub async fn claim_job_run(pool: &DBPool, name: &str, min_gap: i64) -> Result<bool> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(ScheduledJob, "sql/scheduled_job/claim_job_run.sql", name, min_gap)
+            .fetch_all(pool).await,
+            "Failed to claim scheduled job run");
+
+        Ok(res.len() == 1)
+    }
+
+    /// Records the outcome of a run of a job
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `name` - The name of the job
+    /// * `error` - The error the run failed with, or `None` if it succeeded
+    pub async fn set_job_result(pool: &DBPool, name: &str, error: Option<String>) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/scheduled_job/set_job_result.sql", name, error.is_none(), error)
+            .fetch_all(pool).await,
+            "Failed to record scheduled job result");
+
+        Ok(())
+    }
+
+    /// Returns every job that has run, with the outcome of its last run
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    pub async fn get_scheduled_jobs(pool: &DBPool) -> Result<Vec<ScheduledJob>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(ScheduledJob, "sql/scheduled_job/get_scheduled_jobs.sql")
+            .fetch_all(pool).await,
+            "Failed to fetch scheduled jobs");
+
+        Ok(res)
+    }
+}
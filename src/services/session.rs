@@ -1,53 +1,75 @@
 use std::io::{Error, ErrorKind, Result};
 use sqlx::types::time::PrimitiveDateTime;
-use crate::util::DBPool;
+use crate::util::{DBPool, generate_token, hash_token};
 use crate::generic_service_err;
+use crate::config::Config;
 use crate::services::User;
 
-/// The maximum number of user sessions
-const NUM_USER_SESSIONS: i64 = 4;
-
-/// Representation of the session database table
+/// Representation of the session database table, where the ID is the hash of the session token
 pub struct Session {
     pub id: String,
+    pub handle: String,
     pub user_id: i32,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub impersonator_id: Option<i32>,
     pub create_time: PrimitiveDateTime,
+    pub last_seen: PrimitiveDateTime,
 }
 
 /// The session service
 pub mod session_service {
     use super::*;
 
-    /// Creates a user session and returns the resulting record
+    /// Creates a user session and returns the resulting record, with the session token in place of its hash
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `config` - The configuration
     /// * `user_id` - The ID of the user creating the session
-    pub async fn create_session(pool: &DBPool, user_id: i32) -> Result<Session> {
+    /// * `user_agent` - The user agent of the client the session is for
+    /// * `ip_address` - The IP address the session was created from
+    pub async fn create_session(pool: &DBPool, config: &Config, user_id: i32, user_agent: Option<String>, ip_address: Option<String>) -> Result<Session> {
+        let session_id = generate_token();
+
         let mut res = generic_service_err!(
-            sqlx::query_file_as!(Session, "sql/session/create_session.sql", user_id)
+            sqlx::query_file_as!(Session, "sql/session/create_session.sql", hash_token(&session_id), generate_token(), user_id, user_agent, ip_address)
             .fetch_all(pool).await,
             "Failed to create new session");
 
-        delete_old_user_sessions(pool, user_id).await?;
+        delete_old_user_sessions(pool, config, user_id).await?;
+
+        let mut session = res.remove(0);
+        session.id = session_id;
 
-        Ok(res.remove(0))
+        Ok(session)
     }
 
-    /// Returns whether or not a session exists
+    /// Creates a session for an administrator to act as a user, and returns the resulting record, with the session token in place of its hash
+    /// 
+    /// Unlike a session the user creates, this does not log the user out of their oldest sessions.
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
-    /// * `session_id` - The ID of the session
-    pub async fn session_exists(pool: &DBPool, session_id: String) -> Result<bool> {
-        let res = generic_service_err!(
-            sqlx::query_file_as!(Session, "sql/session/get_session.sql", session_id)
+    /// * `user_id` - The ID of the user being impersonated
+    /// * `impersonator_id` - The ID of the administrator impersonating the user
+    /// * `user_agent` - The user agent of the client the session is for
+    /// * `ip_address` - The IP address the session was created from
+    pub async fn create_impersonation_session(pool: &DBPool, user_id: i32, impersonator_id: i32, user_agent: Option<String>, ip_address: Option<String>) -> Result<Session> {
+        let session_id = generate_token();
+
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(Session, "sql/session/create_impersonation_session.sql",
+                hash_token(&session_id), generate_token(), user_id, user_agent, ip_address, impersonator_id)
             .fetch_all(pool).await,
-            "Failed to check if session exists");
+            "Failed to create new impersonation session");
 
-        Ok(res.len() == 1)
+        let mut session = res.remove(0);
+        session.id = session_id;
+
+        Ok(session)
     }
 
     /// Returns a user session record
@@ -58,45 +80,57 @@ pub mod session_service {
     /// * `session_id` - The ID of the session
     pub async fn get_session(pool: &DBPool, session_id: String) -> Result<Session> {
         let mut res = generic_service_err!(
-            sqlx::query_file_as!(Session, "sql/session/get_session.sql", session_id)
+            sqlx::query_file_as!(Session, "sql/session/get_session.sql", hash_token(&session_id))
             .fetch_all(pool).await,
             "Failed to fetch session");
 
         if res.len() == 1 {
             Ok(res.remove(0))
         } else {
-            Err(Error::new(ErrorKind::Other, "Session does not exist"))
+            Err(Error::other("session_not_found"))
         }
     }
 
-    /// Returns the user associated with the session
+    /// Returns the user associated with the session along with the session itself, provided the session has not expired, and marks the session as just used
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `config` - The configuration
     /// * `session_id` - The ID of the session
-    pub async fn get_user_by_session_id(pool: &DBPool, session_id: String) -> Result<User> {
+    pub async fn get_user_by_session_id(pool: &DBPool, config: &Config, session_id: String) -> Result<(User, Session)> {
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(Session, "sql/session/use_session.sql", hash_token(&session_id), config.sessions.max_age, config.sessions.idle_timeout)
+            .fetch_all(pool).await,
+            "Failed to fetch session");
+
+        if res.len() != 1 {
+            return Err(Error::other("user_or_session_not_found"));
+        }
+
+        let session = res.remove(0);
         let mut res = generic_service_err!(
-            sqlx::query_file_as!(User, "sql/session/get_user_by_session_id.sql", session_id)
+            sqlx::query_file_as!(User, "sql/user/get_user.sql", session.user_id)
             .fetch_all(pool).await,
             "Failed to fetch user with session ID");
 
         if res.len() == 1 {
-            Ok(res.remove(0))
+            Ok((res.remove(0), session))
         } else {
-            Err(Error::new(ErrorKind::Other, "User or session does not exist"))
+            Err(Error::other("user_or_session_not_found"))
         }
     }
 
-    /// Returns all sessions associated with a user
+    /// Returns all unexpired sessions associated with a user, most recently used first
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `config` - The configuration
     /// * `user_id` - The ID of the user
-    pub async fn get_user_sessions(pool: &DBPool, user_id: i32) -> Result<Vec<Session>> {
+    pub async fn get_user_sessions(pool: &DBPool, config: &Config, user_id: i32) -> Result<Vec<Session>> {
         let res = generic_service_err!(
-            sqlx::query_file_as!(Session, "sql/session/get_user_sessions.sql", user_id)
+            sqlx::query_file_as!(Session, "sql/session/get_user_sessions.sql", user_id, config.sessions.max_age, config.sessions.idle_timeout)
             .fetch_all(pool).await,
             "Failed to get user sessions");
 
@@ -111,13 +145,33 @@ pub mod session_service {
     /// * `session_id` - The ID of the session
     pub async fn delete_session(pool: &DBPool, session_id: String) -> Result<()> {
         generic_service_err!(
-            sqlx::query_file!("sql/session/delete_session.sql", session_id)
+            sqlx::query_file!("sql/session/delete_session.sql", hash_token(&session_id))
             .fetch_all(pool).await,
             "Failed to delete session");
 
         Ok(())
     }
 
+    /// Deletes one of a user's sessions given its handle
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    /// * `handle` - The handle of the session
+    pub async fn delete_session_by_handle(pool: &DBPool, user_id: i32, handle: String) -> Result<()> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(Session, "sql/session/delete_session_by_handle.sql", user_id, handle)
+            .fetch_all(pool).await,
+            "Failed to delete session");
+
+        if res.len() == 1 {
+            Ok(())
+        } else {
+            Err(Error::other("session_not_found"))
+        }
+    }
+
     /// Deletes all sessions associated with a user
     /// 
     /// # Arguments
@@ -138,13 +192,29 @@ pub mod session_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `config` - The configuration
     /// * `user_id` - The ID of the user
-    pub async fn delete_old_user_sessions(pool: &DBPool, user_id: i32) -> Result<()> {
+    pub async fn delete_old_user_sessions(pool: &DBPool, config: &Config, user_id: i32) -> Result<()> {
         generic_service_err!(
-            sqlx::query_file!("sql/session/delete_old_user_sessions.sql", user_id, NUM_USER_SESSIONS)
+            sqlx::query_file!("sql/session/delete_old_user_sessions.sql", user_id, config.sessions.max_per_user)
             .fetch_all(pool).await,
             "Failed to delete old user sessions");
 
         Ok(())
     }
+
+    /// Deletes all sessions that have passed their absolute or idle expiry
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    pub async fn prune_sessions(pool: &DBPool, config: &Config) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/session/prune_sessions.sql", config.sessions.max_age, config.sessions.idle_timeout)
+            .fetch_all(pool).await,
+            "Failed to prune sessions");
+
+        Ok(())
+    }
 }
@@ -0,0 +1,373 @@
+use std::io::{Error, ErrorKind, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+use hmac::{Hmac, Mac, NewMac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha1::Sha1;
+use crate::util::{DBPool, generate_token, hash_token};
+use crate::config::Config;
+use crate::{generic_service_err, generic_err};
+use crate::services;
+use crate::services::{User, Session, AuditContext};
+use crate::services::audit_event_service::create_audit_event;
+
+/// The name shown for accounts in authenticator apps
+const TOTP_ISSUER: &str = "GreenPoll";
+
+/// The number of random bytes in a TOTP secret
+const TOTP_SECRET_BYTES: usize = 20;
+
+/// The number of seconds each TOTP code is valid for
+const TOTP_STEP_SECONDS: u64 = 30;
+
+/// The number of digits in a TOTP code
+const TOTP_DIGITS: u32 = 6;
+
+/// The number of steps either side of the current one whose codes are still accepted, to allow for clock drift
+const TOTP_ALLOWED_DRIFT: i64 = 1;
+
+/// The number of recovery codes issued to a user
+const NUM_RECOVERY_CODES: usize = 10;
+
+/// The number of random bytes in a recovery code
+const RECOVERY_CODE_BYTES: usize = 5;
+
+/// Encodes bytes as unpadded base32, the format authenticator apps expect secrets in
+/// 
+/// # Arguments
+/// 
+/// * `bytes` - The bytes to encode
+fn base32_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            encoded.push(ALPHABET[((buffer >> (bits - 5)) & 31) as usize] as char);
+            bits -= 5;
+        }
+    }
+
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+
+    encoded
+}
+
+/// Returns the TOTP step for the current time
+fn current_totp_step() -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    (now.as_secs() / TOTP_STEP_SECONDS) as i64
+}
+
+/// Returns the TOTP code for a secret at a given step, as described in RFC 6238
+/// 
+/// # Arguments
+/// 
+/// * `secret` - The TOTP secret
+/// * `step` - The number of steps since the Unix epoch
+fn totp_code(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_varkey(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+
+    truncated % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Returns the step a TOTP code was generated for, if it is valid for the secret at the current time and its step has not been used yet
+/// 
+/// # Arguments
+/// 
+/// * `secret` - The hex encoded TOTP secret
+/// * `code` - The code entered by the user
+/// * `last_step` - The step of the last code the user used, if any
+fn verify_totp_code(secret: &str, code: &str, last_step: Option<i64>) -> Option<i64> {
+    let secret = hex::decode(secret.trim()).ok()?;
+    let code = code.trim();
+
+    if code.len() != TOTP_DIGITS as usize {
+        return None;
+    }
+
+    let code: u32 = code.parse().ok()?;
+    let current_step = current_totp_step();
+
+    (current_step - TOTP_ALLOWED_DRIFT..=current_step + TOTP_ALLOWED_DRIFT)
+        .filter(|step| last_step.is_none_or(|last_step| *step > last_step))
+        .find(|step| totp_code(&secret, *step) == code)
+}
+
+/// Normalizes a recovery code as entered by a user, ignoring case, spaces and dashes
+/// 
+/// # Arguments
+/// 
+/// * `code` - The recovery code
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The two-factor authentication service
+pub mod two_factor_service {
+    use super::*;
+
+    /// Generates a new TOTP secret for a user and returns the otpauth URI to add it to an authenticator app
+    /// 
+    /// Two-factor authentication is not enabled until a code from the secret has been confirmed.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user` - The user enrolling
+    pub async fn begin_enrollment(pool: &DBPool, user: &User) -> Result<String> {
+        if user.totp_enabled {
+            generic_err!("two_factor_already_enabled")
+        } else {
+            let mut secret = [0u8; TOTP_SECRET_BYTES];
+            OsRng.fill_bytes(&mut secret);
+
+            generic_service_err!(
+                sqlx::query_file!("sql/two_factor/set_totp_secret.sql", hex::encode(secret), user.id)
+                .fetch_all(pool).await,
+                "Failed to set two-factor secret");
+
+            let label = utf8_percent_encode(&format!("{}:{}", TOTP_ISSUER, user.email), NON_ALPHANUMERIC).to_string();
+
+            Ok(format!(
+                "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+                label, base32_encode(&secret), TOTP_ISSUER, TOTP_DIGITS, TOTP_STEP_SECONDS))
+        }
+    }
+
+    /// Enables two-factor authentication once the user has entered a valid code, returning their recovery codes
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who is enabling two-factor authentication, and from where
+    /// * `user` - The user enrolling
+    /// * `code` - A code from the user's authenticator app
+    pub async fn confirm_enrollment(pool: &DBPool, context: &AuditContext, user: &User, code: String) -> Result<Vec<String>> {
+        if user.totp_enabled {
+            return generic_err!("two_factor_already_enabled");
+        }
+
+        let secret = match &user.totp_secret {
+            Some(secret) => secret,
+            None => return generic_err!("two_factor_enrollment_not_started"),
+        };
+
+        match verify_totp_code(secret, &code, user.totp_last_step) {
+            Some(step) => {
+                generic_service_err!(
+                    sqlx::query_file!("sql/two_factor/enable_totp.sql", step, user.id)
+                    .fetch_all(pool).await,
+                    "Failed to enable two-factor authentication");
+
+                let recovery_codes = create_recovery_codes(pool, user.id).await?;
+
+                create_audit_event(pool, context, "enable_two_factor", "user", user.id, None, None, None).await?;
+
+                Ok(recovery_codes)
+            },
+            None => generic_err!("invalid_two_factor_code"),
+        }
+    }
+
+    /// Disables two-factor authentication and deletes the user's recovery codes
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `context` - Who is disabling two-factor authentication, and from where
+    /// * `user` - The user
+    /// * `password` - The user's current password
+    pub async fn disable(pool: &DBPool, config: &Config, context: &AuditContext, user: &User, password: String) -> Result<()> {
+        services::user_service::check_password_throttled(pool, config, user, password, context.ip_address.as_deref()).await?;
+
+        if !user.totp_enabled {
+            generic_err!("two_factor_not_enabled")
+        } else {
+            generic_service_err!(
+                sqlx::query_file!("sql/two_factor/disable_totp.sql", user.id)
+                .fetch_all(pool).await,
+                "Failed to disable two-factor authentication");
+
+            generic_service_err!(
+                sqlx::query_file!("sql/two_factor/delete_recovery_codes.sql", user.id)
+                .fetch_all(pool).await,
+                "Failed to delete recovery codes");
+
+            create_audit_event(pool, context, "disable_two_factor", "user", user.id, None, None, None).await?;
+
+            Ok(())
+        }
+    }
+
+    /// Replaces a user's recovery codes with new ones and returns them
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `context` - Who is regenerating the recovery codes, and from where
+    /// * `user` - The user
+    /// * `password` - The user's current password
+    pub async fn regenerate_recovery_codes(pool: &DBPool, config: &Config, context: &AuditContext, user: &User, password: String) -> Result<Vec<String>> {
+        services::user_service::check_password_throttled(pool, config, user, password, context.ip_address.as_deref()).await?;
+
+        if !user.totp_enabled {
+            generic_err!("two_factor_not_enabled")
+        } else {
+            let recovery_codes = create_recovery_codes(pool, user.id).await?;
+
+            create_audit_event(pool, context, "regenerate_recovery_codes", "user", user.id, None, None, None).await?;
+
+            Ok(recovery_codes)
+        }
+    }
+
+    /// Replaces a user's recovery codes with new ones, storing only their hashes, and returns them
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    pub async fn create_recovery_codes(pool: &DBPool, user_id: i32) -> Result<Vec<String>> {
+        generic_service_err!(
+            sqlx::query_file!("sql/two_factor/delete_recovery_codes.sql", user_id)
+            .fetch_all(pool).await,
+            "Failed to delete recovery codes");
+
+        let mut recovery_codes = Vec::with_capacity(NUM_RECOVERY_CODES);
+
+        for _ in 0..NUM_RECOVERY_CODES {
+            let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+            OsRng.fill_bytes(&mut bytes);
+            let code = hex::encode(bytes);
+
+            generic_service_err!(
+                sqlx::query_file!("sql/two_factor/create_recovery_code.sql", user_id, hash_token(&code))
+                .fetch_all(pool).await,
+                "Failed to create recovery code");
+
+            recovery_codes.push(format!("{}-{}", &code[..5], &code[5..]));
+        }
+
+        Ok(recovery_codes)
+    }
+
+    /// Creates a pending login for a user who has entered their password, returning the token that identifies it
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user logging in
+    pub async fn create_pending_login(pool: &DBPool, user_id: i32) -> Result<String> {
+        let pending_login_token = generate_token();
+
+        generic_service_err!(
+            sqlx::query_file!("sql/two_factor/create_pending_login.sql", hash_token(&pending_login_token), user_id)
+            .fetch_all(pool).await,
+            "Failed to create pending login");
+
+        Ok(pending_login_token)
+    }
+
+    /// Completes a pending login with a TOTP or recovery code and returns the new session
+    /// 
+    /// Each pending login allows a few attempts, taken atomically so concurrent requests cannot exceed them, and wrong
+    /// codes count as failed logins to the account, so the second factor is throttled along with the password.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `pending_login_token` - The token returned when the user entered their password
+    /// * `code` - A code from the user's authenticator app, or one of their recovery codes
+    /// * `user_agent` - The user agent of the client logging in
+    /// * `ip_address` - The IP address the user is logging in from
+    pub async fn complete_login(pool: &DBPool, config: &Config, pending_login_token: String, code: String, user_agent: Option<String>, ip_address: Option<String>) -> Result<Session> {
+        let pending_login_id = hash_token(&pending_login_token);
+
+        let mut res = generic_service_err!(
+            sqlx::query_file!("sql/two_factor/claim_pending_login_attempt.sql", pending_login_id)
+            .fetch_all(pool).await,
+            "Failed to record login attempt");
+
+        if res.len() != 1 {
+            return generic_err!("login_expired");
+        }
+
+        let user = services::user_service::get_user(pool, res.remove(0).user_id).await?;
+        services::login_throttle_service::check_login_allowed(pool, &user.email, ip_address.as_deref()).await?;
+
+        let totp_step = user.totp_secret.as_deref().and_then(|secret| verify_totp_code(secret, &code, user.totp_last_step));
+
+        let totp_valid = match totp_step {
+            Some(step) => {
+                let res = generic_service_err!(
+                    sqlx::query_file!("sql/two_factor/use_totp_step.sql", step, user.id)
+                    .fetch_all(pool).await,
+                    "Failed to record two-factor code use");
+
+                res.len() == 1
+            },
+            None => false,
+        };
+
+        let code_valid = totp_valid || {
+            let res = generic_service_err!(
+                sqlx::query_file!("sql/two_factor/use_recovery_code.sql", user.id, hash_token(&normalize_recovery_code(&code)))
+                .fetch_all(pool).await,
+                "Failed to check recovery code");
+
+            res.len() == 1
+        };
+
+        if code_valid && !user.suspended {
+            generic_service_err!(
+                sqlx::query_file!("sql/two_factor/delete_pending_login.sql", pending_login_id)
+                .fetch_all(pool).await,
+                "Failed to delete pending login");
+            services::login_throttle_service::clear_login_failures(pool, &user.email).await?;
+
+            services::session_service::create_session(pool, config, user.id, user_agent, ip_address).await
+        } else if code_valid {
+            generic_err!("account_suspended")
+        } else {
+            services::login_throttle_service::record_login_failure(pool, config, &user.email, ip_address.as_deref(), Some(&user)).await?;
+
+            generic_err!("invalid_two_factor_code")
+        }
+    }
+
+    /// Prunes all expired pending logins, and those with too many failed attempts
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    pub async fn prune_pending_logins(pool: &DBPool) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/two_factor/prune_pending_logins.sql")
+            .fetch_all(pool).await,
+            "Failed to prune pending logins");
+
+        Ok(())
+    }
+}
@@ -3,21 +3,58 @@ extern crate bcrypt;
 use std::io::{Error, ErrorKind, Result};
 use bcrypt::{DEFAULT_COST, hash, verify};
 use sqlx::types::time::PrimitiveDateTime;
+use serde_json::{json, Value as JsonValue};
 use crate::util::DBPool;
+use crate::i18n;
 use crate::{generic_service_err, generic_err};
 use crate::services;
-use crate::services::{Poll, Session};
+use crate::services::{Poll, Session, AuditContext, Auditable};
+use crate::services::audit_event_service::create_audit_event;
+use crate::config::Config;
 
 /// Representation of the user database table
+#[derive(Clone)]
 pub struct User {
     pub id: i32,
     pub username: String,
     pub email: String,
     pub password: String,
     pub verified: bool,
+    pub is_admin: bool,
+    pub suspended: bool,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_last_step: Option<i64>,
+    pub delete_time: Option<PrimitiveDateTime>,
+    pub locale: Option<String>,
     pub join_time: PrimitiveDateTime,
 }
 
+/// The outcome of a successful password check at login
+pub enum LoginResult {
+    /// The user is logged in with a new session
+    Session(Session),
+    /// The user must also enter a two-factor code, identified by a pending login token
+    TwoFactorRequired(String),
+}
+
+impl Auditable for User {
+    fn audit_snapshot(&self) -> JsonValue {
+        json!({
+            "id": self.id,
+            "username": self.username,
+            "email": self.email,
+            "verified": self.verified,
+            "is_admin": self.is_admin,
+            "suspended": self.suspended,
+            "totp_enabled": self.totp_enabled,
+            "delete_time": self.delete_time.map(|delete_time| delete_time.assume_utc().unix_timestamp()),
+            "locale": self.locale,
+            "join_time": self.join_time.assume_utc().unix_timestamp(),
+        })
+    }
+}
+
 /// The user service
 pub mod user_service {
     use super::*;
@@ -27,25 +64,31 @@ pub mod user_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `context` - Where the user is registering from
     /// * `username` - The user's username
     /// * `email` - The user's email
     /// * `password` - The user's password
-    pub async fn create_user(pool: &DBPool, username: String, email: String, password: String) -> Result<User> {
-        prune_unverified_users(pool).await?;
+    pub async fn create_user(pool: &DBPool, config: &Config, context: &AuditContext, username: String, email: String, password: String) -> Result<User> {
+        // Free up the username and email address if they belong to an expired unverified account the scheduler has not deleted yet
+        generic_service_err!(
+            sqlx::query_file!("sql/user/delete_expired_unverified_user.sql", username, email, config.expiry.verification)
+            .fetch_all(pool).await,
+            "Failed to delete expired unverified user");
 
         let username_exists = user_exists_for_username(pool, username.clone()).await?;
         let email_exists = user_exists_for_email(pool, email.clone()).await?;
 
         if username_exists {
-            generic_err!("Username is in use")
+            generic_err!("username_in_use")
         } else if email_exists {
-            generic_err!("Email is in use")
+            generic_err!("email_in_use")
         } else if username.len() < 3 || username.len() > 63 {
-            generic_err!("Username must be between 3 and 63 characters")
+            generic_err!("username_length")
         } else if email.len() < 5 || email.len() > 63 {
-            generic_err!("Email must be between 5 and 63 characters")
+            generic_err!("email_length")
         } else if password.len() < 8 || password.len() > 255 {
-            generic_err!("Password must be at least 8 characters")
+            generic_err!("password_length")
         } else {
             let password_hash = generic_service_err!(
                 hash(password, DEFAULT_COST),
@@ -55,8 +98,11 @@ pub mod user_service {
                 sqlx::query_file_as!(User, "sql/user/create_user.sql", username.clone(), email.clone(), password_hash)
                 .fetch_all(pool).await,
                 "Failed to create new user");
+            let user = res.remove(0);
 
-            Ok(res.remove(0))
+            create_audit_event(pool, &context.with_actor(user.id), "create_user", "user", user.id, None, None, Some(&user)).await?;
+
+            Ok(user)
         }
     }
 
@@ -67,8 +113,6 @@ pub mod user_service {
     /// * `pool` - The database pool
     /// * `user_id` - The ID of the user
     pub async fn user_exists(pool: &DBPool, user_id: i32) -> Result<bool> {
-        prune_unverified_users(pool).await?;
-
         let res = generic_service_err!(
             sqlx::query_file_as!(User, "sql/user/get_user.sql", user_id)
             .fetch_all(pool).await,
@@ -84,8 +128,6 @@ pub mod user_service {
     /// * `pool` - The database pool
     /// * `username` - The username of the user
     pub async fn user_exists_for_username(pool: &DBPool, username: String) -> Result<bool> {
-        prune_unverified_users(pool).await?;
-
         let res = generic_service_err!(
             sqlx::query_file_as!(User, "sql/user/get_user_by_username.sql", username)
             .fetch_all(pool).await,
@@ -101,8 +143,6 @@ pub mod user_service {
     /// * `pool` - The database pool
     /// * `email` - The email address of the user
     pub async fn user_exists_for_email(pool: &DBPool, email: String) -> Result<bool> {
-        prune_unverified_users(pool).await?;
-
         let res = generic_service_err!(
             sqlx::query_file_as!(User, "sql/user/get_user_by_email.sql", email)
             .fetch_all(pool).await,
@@ -118,8 +158,6 @@ pub mod user_service {
     /// * `pool` - The database pool
     /// * `user_id` - The ID of the user
     pub async fn get_user(pool: &DBPool, user_id: i32) -> Result<User> {
-        prune_unverified_users(pool).await?;
-
         let mut res = generic_service_err!(
             sqlx::query_file_as!(User, "sql/user/get_user.sql", user_id)
             .fetch_all(pool).await,
@@ -128,7 +166,7 @@ pub mod user_service {
         if res.len() == 1 {
             Ok(res.remove(0))
         } else {
-            Err(Error::new(ErrorKind::Other, "User does not exist"))
+            Err(Error::other("user_not_found"))
         }
     }
 
@@ -139,8 +177,6 @@ pub mod user_service {
     /// * `pool` - The database pool
     /// * `username` - The username of the user
     pub async fn get_user_by_username(pool: &DBPool, username: String) -> Result<User> {
-        prune_unverified_users(pool).await?;
-
         let mut res = generic_service_err!(
             sqlx::query_file_as!(User, "sql/user/get_user_by_username.sql", username)
             .fetch_all(pool).await,
@@ -149,7 +185,7 @@ pub mod user_service {
         if res.len() == 1 {
             Ok(res.remove(0))
         } else {
-            Err(Error::new(ErrorKind::Other, "User does not exist"))
+            Err(Error::other("user_not_found"))
         }
     }
 
@@ -160,8 +196,6 @@ pub mod user_service {
     /// * `pool` - The database pool
     /// * `email` - The email address of the user
     pub async fn get_user_by_email(pool: &DBPool, email: String) -> Result<User> {
-        prune_unverified_users(pool).await?;
-
         let mut res = generic_service_err!(
             sqlx::query_file_as!(User, "sql/user/get_user_by_email.sql", email)
             .fetch_all(pool).await,
@@ -170,58 +204,129 @@ pub mod user_service {
         if res.len() == 1 {
             Ok(res.remove(0))
         } else {
-            Err(Error::new(ErrorKind::Other, "User does not exist"))
+            Err(Error::other("user_not_found"))
         }
     }
 
+    /// Returns a page of all users
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `limit` - The maximum number of users to return
+    /// * `offset` - The number of users to skip
+    pub async fn get_users(pool: &DBPool, limit: i64, offset: i64) -> Result<Vec<User>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(User, "sql/user/get_users.sql", limit, offset)
+            .fetch_all(pool).await,
+            "Failed to fetch users");
+
+        Ok(res)
+    }
+
+    /// Returns a page of users whose username or email address contains the search text
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `search` - The text to search for
+    /// * `limit` - The maximum number of users to return
+    /// * `offset` - The number of users to skip
+    pub async fn search_users(pool: &DBPool, search: String, limit: i64, offset: i64) -> Result<Vec<User>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(User, "sql/user/search_users.sql", search, limit, offset)
+            .fetch_all(pool).await,
+            "Failed to search users");
+
+        Ok(res)
+    }
+
     /// Sets a user's username
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
     /// * `user_id` - The ID of the user
     /// * `username` - The new username
-    pub async fn set_username(pool: &DBPool, user_id: i32, username: String) -> Result<()> {
-        prune_unverified_users(pool).await?;
-
+    pub async fn set_username(pool: &DBPool, context: &AuditContext, user_id: i32, username: String) -> Result<()> {
         let username_exists = user_exists_for_username(pool, username.clone()).await?;
 
         if username_exists {
-            generic_err!("Username is in use")
+            generic_err!("username_in_use")
         } else if username.len() < 3 || username.len() > 63 {
-            generic_err!("Username must be between 3 and 63 characters")
+            generic_err!("username_length")
         } else {
+            let before = get_user(pool, user_id).await?;
+
             generic_service_err!(
                 sqlx::query_file!("sql/user/set_username.sql", username.clone(), user_id)
                 .fetch_all(pool).await,
                 "Failed to set username");
 
+            let after = get_user(pool, user_id).await?;
+            create_audit_event(pool, context, "set_username", "user", user_id, None, Some(&before), Some(&after)).await?;
+
             Ok(())
         }
     }
 
+    /// Sets the locale a user's messages and emails are translated into, or clears it to follow the `Accept-Language` header
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
+    /// * `user_id` - The ID of the user
+    /// * `locale` - The new locale, if any
+    pub async fn set_locale(pool: &DBPool, context: &AuditContext, user_id: i32, locale: Option<String>) -> Result<()> {
+        let locale = match locale {
+            Some(locale) => match i18n::supported_locale(&locale) {
+                Some(locale) => Ok(Some(locale.to_string())),
+                None => Err(Error::other("unsupported_locale")),
+            },
+            None => Ok(None),
+        }?;
+
+        let before = get_user(pool, user_id).await?;
+
+        generic_service_err!(
+            sqlx::query_file!("sql/user/set_locale.sql", locale, user_id)
+            .fetch_all(pool).await,
+            "Failed to set locale");
+
+        let after = get_user(pool, user_id).await?;
+        create_audit_event(pool, context, "set_locale", "user", user_id, None, Some(&before), Some(&after)).await?;
+
+        Ok(())
+    }
+
     /// Sets the user's email address
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
     /// * `user_id` - The ID of the user
     /// * `email` - The new email address
-    pub async fn set_email(pool: &DBPool, user_id: i32, email: String) -> Result<()> {
-        prune_unverified_users(pool).await?;
-
+    pub async fn set_email(pool: &DBPool, context: &AuditContext, user_id: i32, email: String) -> Result<()> {
         let email_exists = user_exists_for_email(pool, email.clone()).await?;
 
         if email_exists {
-            generic_err!("Email is in use")
+            generic_err!("email_in_use")
         } else if email.len() < 5 || email.len() > 63 {
-            generic_err!("Email must be between 5 and 63 characters")
+            generic_err!("email_length")
         } else {
+            let before = get_user(pool, user_id).await?;
+
             generic_service_err!(
                 sqlx::query_file!("sql/user/set_email.sql", email.clone(), user_id)
                 .fetch_all(pool).await,
                 "Failed to set user email");
 
+            let after = get_user(pool, user_id).await?;
+            create_audit_event(pool, context, "set_email", "user", user_id, None, Some(&before), Some(&after)).await?;
+
             Ok(())
         }
     }
@@ -231,13 +336,12 @@ pub mod user_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
     /// * `user_id` - The ID of the user
     /// * `password` - The new password
-    pub async fn set_password(pool: &DBPool, user_id: i32, password: String) -> Result<()> {
-        prune_unverified_users(pool).await?;
-
+    pub async fn set_password(pool: &DBPool, context: &AuditContext, user_id: i32, password: String) -> Result<()> {
         if password.len() < 8 || password.len() > 255 {
-            generic_err!("Password must be at least 8 characters")
+            generic_err!("password_length")
         } else {
             let password_hash = generic_service_err!(
                 hash(password, DEFAULT_COST),
@@ -248,6 +352,8 @@ pub mod user_service {
                 .fetch_all(pool).await,
                 "Failed to set user password");
 
+            create_audit_event(pool, context, "set_password", "user", user_id, None, None, None).await?;
+
             Ok(())
         }
     }
@@ -257,16 +363,46 @@ pub mod user_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
     /// * `user_id` - The ID of the user
     /// * `verified` - The new verified status
-    pub async fn set_verified(pool: &DBPool, user_id: i32, verified: bool) -> Result<()> {
-        prune_unverified_users(pool).await?;
+    pub async fn set_verified(pool: &DBPool, context: &AuditContext, user_id: i32, verified: bool) -> Result<()> {
+        let before = get_user(pool, user_id).await?;
 
         generic_service_err!(
             sqlx::query_file!("sql/user/set_verified.sql", verified, user_id)
             .fetch_all(pool).await,
             "Failed to set user verified status");
 
+        let after = get_user(pool, user_id).await?;
+        create_audit_event(pool, context, "set_verified", "user", user_id, None, Some(&before), Some(&after)).await?;
+
+        Ok(())
+    }
+
+    /// Sets a user's suspended status, logging the user out everywhere when suspending
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
+    /// * `user_id` - The ID of the user
+    /// * `suspended` - The new suspended status
+    pub async fn set_suspended(pool: &DBPool, context: &AuditContext, user_id: i32, suspended: bool) -> Result<()> {
+        let before = get_user(pool, user_id).await?;
+
+        generic_service_err!(
+            sqlx::query_file!("sql/user/set_suspended.sql", suspended, user_id)
+            .fetch_all(pool).await,
+            "Failed to set user suspended status");
+
+        let after = get_user(pool, user_id).await?;
+        create_audit_event(pool, context, "set_suspended", "user", user_id, None, Some(&before), Some(&after)).await?;
+
+        if suspended {
+            services::session_service::delete_user_sessions(pool, user_id).await?;
+        }
+
         Ok(())
     }
 
@@ -277,8 +413,6 @@ pub mod user_service {
     /// * `poll` - The database pool
     /// * `user_id` - The ID of the user
     pub async fn get_user_polls(pool: &DBPool, user_id: i32) -> Result<Vec<Poll>> {
-        prune_unverified_users(pool).await?;
-
         let polls = generic_service_err!(
             sqlx::query_file_as!(Poll, "sql/user/get_user_polls.sql", user_id)
             .fetch_all(pool).await,
@@ -292,9 +426,11 @@ pub mod user_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
     /// * `user_id` - The ID of the user
-    pub async fn delete_user(pool: &DBPool, user_id: i32) -> Result<()> {
-        prune_unverified_users(pool).await?;
+    pub async fn delete_user(pool: &DBPool, context: &AuditContext, user_id: i32) -> Result<()> {
+        let before = get_user(pool, user_id).await?;
+        create_audit_event(pool, context, "delete_user", "user", user_id, None, Some(&before), None).await?;
 
         generic_service_err!(
             sqlx::query_file!("sql/user/delete_user.sql", user_id)
@@ -304,33 +440,171 @@ pub mod user_service {
         Ok(())
     }
 
-    /// Logs a user in and returns the new session
+    /// Schedules a user's account for deletion once the grace period has passed, after checking their password, and logs them out everywhere
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
-    /// * `email` - The user's email address
-    /// * `password` - The user's password
-    pub async fn login(pool: &DBPool, email: String, password: String) -> Result<Session> {
-        prune_unverified_users(pool).await?;
+    /// * `config` - The configuration
+    /// * `context` - Who is making the change, and from where
+    /// * `user` - The user
+    /// * `password` - The user's current password
+    pub async fn request_deletion(pool: &DBPool, config: &Config, context: &AuditContext, user: &User, password: String) -> Result<()> {
+        check_password_throttled(pool, config, user, password, context.ip_address.as_deref()).await?;
+
+        if user.delete_time.is_some() {
+            generic_err!("deletion_already_scheduled")
+        } else {
+            generic_service_err!(
+                sqlx::query_file!("sql/user/set_delete_time.sql", config.accounts.deletion_grace_period, user.id)
+                .fetch_all(pool).await,
+                "Failed to schedule user deletion");
 
-        let user_exists = user_exists_for_email(pool, email.clone()).await?;
+            let after = get_user(pool, user.id).await?;
+            create_audit_event(pool, context, "request_deletion", "user", user.id, None, Some(user), Some(&after)).await?;
 
-        if user_exists {
-            let user = get_user_by_email(pool, email.clone()).await?;
+            services::session_service::delete_user_sessions(pool, user.id).await?;
 
-            let password_match = generic_service_err!(
-                verify(password, &user.password[..]),
-                "Failed to verify password hash");
+            Ok(())
+        }
+    }
 
-            if password_match {
-                let session = services::session_service::create_session(pool, user.id).await?;
-                Ok(session)
-            } else {
-                Err(Error::new(ErrorKind::Other, "Invalid login"))
-            }
+    /// Cancels the scheduled deletion of a user's account
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who is making the change, and from where
+    /// * `user` - The user
+    pub async fn cancel_deletion(pool: &DBPool, context: &AuditContext, user: &User) -> Result<()> {
+        if user.delete_time.is_none() {
+            generic_err!("deletion_not_scheduled")
+        } else {
+            generic_service_err!(
+                sqlx::query_file!("sql/user/clear_delete_time.sql", user.id)
+                .fetch_all(pool).await,
+                "Failed to cancel user deletion");
+
+            let after = get_user(pool, user.id).await?;
+            create_audit_event(pool, context, "cancel_deletion", "user", user.id, None, Some(user), Some(&after)).await?;
+
+            Ok(())
+        }
+    }
+
+    /// Deletes every account whose deletion grace period has passed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    pub async fn prune_deleted_users(pool: &DBPool) -> Result<()> {
+        let users = generic_service_err!(
+            sqlx::query_file_as!(User, "sql/user/get_users_due_for_deletion.sql")
+            .fetch_all(pool).await,
+            "Failed to fetch users due for deletion");
+
+        for user in users {
+            delete_user(pool, &AuditContext::system(), user.id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks a user's password, returning an error if it does not match
+    /// 
+    /// # Arguments
+    /// 
+    /// * `user` - The user
+    /// * `password` - The password to check
+    pub fn check_password(user: &User, password: String) -> Result<()> {
+        let password_match = generic_service_err!(
+            verify(password, &user.password[..]),
+            "Failed to verify password hash");
+
+        if password_match {
+            Ok(())
         } else {
-            Err(Error::new(ErrorKind::Other, "Invalid login"))
+            generic_err!("incorrect_password")
+        }
+    }
+
+    /// Checks the password of a logged in user confirming a sensitive change, returning an error if it does not match
+    /// 
+    /// Wrong passwords are throttled and counted towards a lockout like failed logins, so a stolen session cannot be used to guess the password.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `user` - The user
+    /// * `password` - The password to check
+    /// * `ip_address` - The IP address the change is being made from
+    pub async fn check_password_throttled(pool: &DBPool, config: &Config, user: &User, password: String, ip_address: Option<&str>) -> Result<()> {
+        services::login_throttle_service::check_login_allowed(pool, &user.email, ip_address).await?;
+
+        match check_password(user, password) {
+            Ok(()) => services::login_throttle_service::clear_login_failures(pool, &user.email).await,
+            Err(e) => {
+                services::login_throttle_service::record_login_failure(pool, config, &user.email, ip_address, Some(user)).await?;
+                Err(e)
+            },
+        }
+    }
+
+    /// Logs a user in and returns the new session, or a pending login token if the user has two-factor authentication enabled
+    /// 
+    /// Failed logins are throttled per email address and per IP address, and take as long whether or not the email address belongs to an account.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `email` - The user's email address
+    /// * `password` - The user's password
+    /// * `user_agent` - The user agent of the client logging in
+    /// * `ip_address` - The IP address the user is logging in from
+    pub async fn login(pool: &DBPool, config: &Config, email: String, password: String, user_agent: Option<String>, ip_address: Option<String>) -> Result<LoginResult> {
+        services::login_throttle_service::check_login_allowed(pool, &email, ip_address.as_deref()).await?;
+
+        // Expired unverified accounts must not be logged in to, even if the scheduler has not deleted them yet
+        let user = generic_service_err!(
+            sqlx::query_file_as!(User, "sql/user/get_login_user.sql", email, config.expiry.verification)
+            .fetch_all(pool).await,
+            "Failed to fetch user by email").into_iter().next();
+
+        let password_match = match &user {
+            Some(user) => generic_service_err!(
+                verify(&password, &user.password[..]),
+                "Failed to verify password hash"),
+            None => {
+                // Hash the password anyway, so that a login to an unknown email takes as long as one to a known email
+                generic_service_err!(
+                    hash(&password, DEFAULT_COST),
+                    "Failed to verify password hash");
+                false
+            }
+        };
+
+        match user {
+            Some(user) if password_match && user.totp_enabled && !user.suspended => {
+                // Failed logins are only forgotten once the second factor has been entered correctly too
+                let pending_login_token = services::two_factor_service::create_pending_login(pool, user.id).await?;
+                Ok(LoginResult::TwoFactorRequired(pending_login_token))
+            },
+            Some(user) if password_match => {
+                services::login_throttle_service::clear_login_failures(pool, &email).await?;
+
+                if user.suspended {
+                    Err(Error::other("account_suspended"))
+                } else {
+                    let session = services::session_service::create_session(pool, config, user.id, user_agent, ip_address).await?;
+                    Ok(LoginResult::Session(session))
+                }
+            },
+            user => {
+                services::login_throttle_service::record_login_failure(pool, config, &email, ip_address.as_deref(), user.as_ref()).await?;
+                Err(Error::other("invalid_login"))
+            }
         }
     }
 
@@ -339,9 +613,10 @@ pub mod user_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
-    pub async fn prune_unverified_users(pool: &DBPool) -> Result<()> {
+    /// * `config` - The configuration
+    pub async fn prune_unverified_users(pool: &DBPool, config: &Config) -> Result<()> {
         generic_service_err!(
-            sqlx::query_file!("sql/user/prune_unverified_users.sql")
+            sqlx::query_file!("sql/user/prune_unverified_users.sql", config.expiry.verification)
             .fetch_all(pool).await,
             "Failed to prune unverified users");
 
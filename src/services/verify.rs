@@ -1,11 +1,12 @@
 use std::io::{Error, ErrorKind, Result};
 use sqlx::types::time::PrimitiveDateTime;
-use crate::util::DBPool;
+use crate::util::{DBPool, generate_token, hash_token};
 use crate::generic_service_err;
 use crate::services;
-use crate::services::User;
+use crate::services::{User, AuditContext};
+use crate::config::Config;
 
-/// Representation of the verify database table
+/// Representation of the verify database table, where the ID is the hash of the ID sent to the user
 pub struct Verify {
     pub id: String,
     pub email: String,
@@ -16,29 +17,29 @@ pub struct Verify {
 pub mod verify_service {
     use super::*;
 
-    /// Creates a verification record and returns the resulting record
+    /// Creates a verification record, replacing any previous one for the email address, and returns the resulting record with the unhashed ID
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
     /// * `email` - The email address of the user being verified
     pub async fn create_verification(pool: &DBPool, email: String) -> Result<Verify> {
-        prune_verifications(pool).await?;
+        generic_service_err!(
+            sqlx::query_file!("sql/verify/delete_verification_by_email.sql", email.clone())
+            .fetch_all(pool).await,
+            "Failed to delete previous verification record");
 
-        let exists = verification_exists_for_email(pool, email.clone()).await?;
+        let verify_id = generate_token();
 
-        if !exists {
-            let mut res = generic_service_err!(
-                sqlx::query_file_as!(Verify, "sql/verify/create_verification.sql", email.clone())
-                .fetch_all(pool).await,
-                "Failed to create new verification record");
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(Verify, "sql/verify/create_verification.sql", hash_token(&verify_id), email.clone())
+            .fetch_all(pool).await,
+            "Failed to create new verification record");
 
-            Ok(res.remove(0))
-        } else {
-            let verification = get_verification_for_email(pool, email.clone()).await?;
+        let mut verification = res.remove(0);
+        verification.id = verify_id;
 
-            Ok(verification)
-        }
+        Ok(verification)
     }
 
     /// Returns whether or not a verification record exists
@@ -46,95 +47,34 @@ pub mod verify_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `config` - The configuration
     /// * `verify_id` - The ID of the verification record
-    pub async fn verification_exists(pool: &DBPool, verify_id: String) -> Result<bool> {
-        prune_verifications(pool).await?;
-
+    pub async fn verification_exists(pool: &DBPool, config: &Config, verify_id: String) -> Result<bool> {
         let res = generic_service_err!(
-            sqlx::query_file_as!(Verify, "sql/verify/get_verification.sql", verify_id)
+            sqlx::query_file_as!(Verify, "sql/verify/get_verification.sql", hash_token(&verify_id), config.expiry.verification)
             .fetch_all(pool).await,
             "Failed to check if verification record exists");
 
         Ok(res.len() == 1)
     }
 
-    /// Returns whether or not a verification record exists given an email address
-    /// 
-    /// # Arguments
-    /// 
-    /// * `pool` - The database pool
-    /// * `email` - The email address associated with the verification record
-    pub async fn verification_exists_for_email(pool: &DBPool, email: String) -> Result<bool> {
-        prune_verifications(pool).await?;
-
-        let res = generic_service_err!(
-            sqlx::query_file_as!(Verify, "sql/verify/get_verification_by_email.sql", email)
-            .fetch_all(pool).await,
-            "Failed to check if verification record exists for given email");
-
-        Ok(res.len() == 1)
-    }
-
-    /// Returns a verification record
-    /// 
-    /// # Arguments
-    /// 
-    /// * `pool` - The database pool
-    /// * `verify_id` - The ID of the verification record
-    pub async fn get_verification(pool: &DBPool, verify_id: String) -> Result<Verify> {
-        prune_verifications(pool).await?;
-
-        let mut res = generic_service_err!(
-            sqlx::query_file_as!(Verify, "sql/verify/get_verification.sql", verify_id)
-            .fetch_all(pool).await,
-            "Failed to fetch verification record");
-
-        if res.len() == 1 {
-            Ok(res.remove(0))
-        } else {
-            Err(Error::new(ErrorKind::Other, "Verification record does not exist"))
-        }
-    }
-
-    /// Returns a verification record given an email address
-    /// 
-    /// # Arguments
-    /// 
-    /// * `pool` - The database pool
-    /// * `email` - The email address associated with the verification record
-    pub async fn get_verification_for_email(pool: &DBPool, email: String) -> Result<Verify> {
-        prune_verifications(pool).await?;
-
-        let mut res = generic_service_err!(
-            sqlx::query_file_as!(Verify, "sql/verify/get_verification_by_email.sql", email)
-            .fetch_all(pool).await,
-            "Failed to fetch verification record for given email");
-
-        if res.len() == 1 {
-            Ok(res.remove(0))
-        } else {
-            Err(Error::new(ErrorKind::Other, "Verification record does not exist for given email"))
-        }
-    }
-
     /// Returns the user who created the verification record
     /// 
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `config` - The configuration
     /// * `verify_id` - The ID of the verification record
-    pub async fn get_user_by_verification(pool: &DBPool, verify_id: String) -> Result<User> {
-        prune_verifications(pool).await?;
-
+    pub async fn get_user_by_verification(pool: &DBPool, config: &Config, verify_id: String) -> Result<User> {
         let mut res = generic_service_err!(
-            sqlx::query_file_as!(User, "sql/verify/get_user_by_verify_id.sql", verify_id)
+            sqlx::query_file_as!(User, "sql/verify/get_user_by_verify_id.sql", hash_token(&verify_id), config.expiry.verification)
             .fetch_all(pool).await,
             "Failed to fetch user by verify ID");
 
         if res.len() == 1 {
             Ok(res.remove(0))
         } else {
-            Err(Error::new(ErrorKind::Other, "User does not exist for given verify ID"))
+            Err(Error::other("user_not_found_for_verify_id"))
         }
     }
 
@@ -145,10 +85,8 @@ pub mod verify_service {
     /// * `pool` - The database pool
     /// * `verify_id` - The ID of the verification record
     pub async fn delete_verification(pool: &DBPool, verify_id: String) -> Result<()> {
-        prune_verifications(pool).await?;
-
         generic_service_err!(
-            sqlx::query_file!("sql/verify/delete_verification.sql", verify_id)
+            sqlx::query_file!("sql/verify/delete_verification.sql", hash_token(&verify_id))
             .fetch_all(pool).await,
             "Failed to delete verification record");
 
@@ -160,20 +98,20 @@ pub mod verify_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `context` - Where the user is verifying from
     /// * `verify_id` - The ID of the verification record
-    pub async fn verify_user(pool: &DBPool, verify_id: String) -> Result<()> {
-        prune_verifications(pool).await?;
-
-        let valid = verification_exists(pool, verify_id.clone()).await?;
+    pub async fn verify_user(pool: &DBPool, config: &Config, context: &AuditContext, verify_id: String) -> Result<()> {
+        let valid = verification_exists(pool, config, verify_id.clone()).await?;
 
         if valid {
-            let user = get_user_by_verification(pool, verify_id.clone()).await?;
+            let user = get_user_by_verification(pool, config, verify_id.clone()).await?;
             delete_verification(pool, verify_id.clone()).await?;
-            services::user_service::set_verified(pool, user.id, true).await?;
+            services::user_service::set_verified(pool, &context.with_actor(user.id), user.id, true).await?;
 
             Ok(())
         } else {
-            Err(Error::new(ErrorKind::Other, "Invalid verify ID"))
+            Err(Error::other("invalid_verify_id"))
         }
     }
 
@@ -182,11 +120,12 @@ pub mod verify_service {
     /// # Arguments
     /// 
     /// * `pool` - The database pool
-    pub async fn prune_verifications(pool: &DBPool) -> Result<()> {
-        services::user_service::prune_unverified_users(pool).await?;
+    /// * `config` - The configuration
+    pub async fn prune_verifications(pool: &DBPool, config: &Config) -> Result<()> {
+        services::user_service::prune_unverified_users(pool, config).await?;
 
         generic_service_err!(
-            sqlx::query_file!("sql/verify/prune_verifications.sql")
+            sqlx::query_file!("sql/verify/prune_verifications.sql", config.expiry.verification)
             .fetch_all(pool).await,
             "Failed to prune verification records");
 
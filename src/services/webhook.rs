@@ -0,0 +1,471 @@
+use std::io::{Error, ErrorKind, Result};
+use actix_web::web;
+use actix_web::error::BlockingError;
+use sqlx::types::time::PrimitiveDateTime;
+use serde_json::{json, Value as JsonValue};
+use std::time::Duration;
+use crate::util::{DBPool, generate_token};
+use crate::config::Config;
+use crate::{generic_service_err, generic_err};
+use crate::services;
+use crate::services::{AuditContext, Auditable};
+use crate::services::audit_event_service::create_audit_event;
+use crate::webhooks::{WebhookRequest, parse_webhook_url, check_webhook_host, sign_payload, send_webhook};
+
+/// The events a webhook can subscribe to
+pub const WEBHOOK_EVENTS: [&str; 4] = ["vote_cast", "vote_removed", "option_changed", "poll_closed"];
+
+/// The event sent when a poll's creator asks for a test delivery, which webhooks receive regardless of their subscriptions
+const WEBHOOK_TEST_EVENT: &str = "test";
+
+/// The maximum number of webhooks per poll
+const NUM_POLL_WEBHOOKS: usize = 5;
+
+/// The number of seconds before the first retry of a failed delivery, doubling for each later retry
+const WEBHOOK_RETRY_BASE_DELAY: i64 = 30;
+
+/// The longest number of seconds between retries of a failed delivery
+const WEBHOOK_RETRY_MAX_DELAY: i64 = 6 * 60 * 60;
+
+/// The number of seconds a claimed delivery is left alone before another worker may try it, in case the claiming worker dies
+const WEBHOOK_CLAIM_LEASE: i64 = 5 * 60;
+
+/// The number of due deliveries a worker claims at a time
+const WEBHOOK_BATCH_SIZE: i64 = 20;
+
+/// Representation of the webhook database table
+pub struct Webhook {
+    pub id: i32,
+    pub poll_id: i32,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub create_time: PrimitiveDateTime,
+}
+
+/// Representation of the webhook delivery database table, which is the log of every event sent to a webhook
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub create_time: PrimitiveDateTime,
+    pub next_attempt_time: PrimitiveDateTime,
+    pub last_attempt_time: Option<PrimitiveDateTime>,
+    pub delivered_time: Option<PrimitiveDateTime>,
+}
+
+impl Auditable for Webhook {
+    fn audit_snapshot(&self) -> JsonValue {
+        json!({
+            "id": self.id,
+            "poll_id": self.poll_id,
+            "url": self.url,
+            "events": self.events,
+            "create_time": self.create_time.assume_utc().unix_timestamp(),
+        })
+    }
+}
+
+/// Returns the number of seconds to wait before retrying a delivery that has failed a number of times
+/// 
+/// # Arguments
+/// 
+/// * `attempts` - The number of failed attempts so far, including the latest
+fn webhook_retry_delay(attempts: i32) -> i64 {
+    let exponent = (attempts - 1).clamp(0, 20) as u32;
+
+    (WEBHOOK_RETRY_BASE_DELAY * 2i64.pow(exponent)).min(WEBHOOK_RETRY_MAX_DELAY)
+}
+
+/// The webhook service
+pub mod webhook_service {
+    use super::*;
+
+    /// Registers a webhook on a poll and returns the resulting record, including the secret its deliveries are signed with
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `context` - Who is registering the webhook, and from where
+    /// * `poll_id` - The ID of the poll
+    /// * `url` - The `http` or `https` URL to deliver events to, whose host must resolve only to public addresses
+    /// * `events` - The events to deliver
+    pub async fn create_webhook(pool: &DBPool, config: &Config, context: &AuditContext, poll_id: i32, url: String, events: Vec<String>) -> Result<Webhook> {
+        let num_webhooks = get_poll_webhooks(pool, poll_id).await?.len();
+        let parsed_url = match parse_webhook_url(&url) {
+            Some(parsed_url) if url.len() <= 2047 => parsed_url,
+            _ => return generic_err!("invalid_webhook_url"),
+        };
+
+        // Resolving the host blocks, so do it on the thread pool rather than the worker thread
+        let allow_private = config.webhooks.allow_private_networks;
+        let host_allowed = web::block(move || check_webhook_host(&parsed_url, allow_private)).await.is_ok();
+
+        if !host_allowed {
+            generic_err!("webhook_url_not_public")
+        } else if events.is_empty() || events.iter().any(|event| !WEBHOOK_EVENTS.contains(&&event[..])) {
+            generic_err!("invalid_webhook_events")
+        } else if num_webhooks >= NUM_POLL_WEBHOOKS {
+            generic_err!("webhook_limit")
+        } else {
+            let mut res = generic_service_err!(
+                sqlx::query_file_as!(Webhook, "sql/webhook/create_webhook.sql", poll_id, url, generate_token(), &events[..])
+                .fetch_all(pool).await,
+                "Failed to create new webhook");
+            let webhook = res.remove(0);
+
+            create_audit_event(pool, context, "create_webhook", "webhook", webhook.id, Some(poll_id), None, Some(&webhook)).await?;
+
+            Ok(webhook)
+        }
+    }
+
+    /// Returns a webhook
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `webhook_id` - The ID of the webhook
+    pub async fn get_webhook(pool: &DBPool, webhook_id: i32) -> Result<Webhook> {
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(Webhook, "sql/webhook/get_webhook.sql", webhook_id)
+            .fetch_all(pool).await,
+            "Failed to fetch webhook");
+
+        if res.len() == 1 {
+            Ok(res.remove(0))
+        } else {
+            generic_err!("webhook_not_found")
+        }
+    }
+
+    /// Returns a webhook on one of a user's polls, treating webhooks on other users' polls as if they do not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `user_id` - The ID of the user
+    /// * `webhook_id` - The ID of the webhook
+    pub async fn get_user_webhook(pool: &DBPool, user_id: i32, webhook_id: i32) -> Result<Webhook> {
+        let webhook = get_webhook(pool, webhook_id).await?;
+        let poll = services::poll_service::get_poll(pool, webhook.poll_id).await?;
+
+        if poll.user_id == user_id {
+            Ok(webhook)
+        } else {
+            generic_err!("webhook_not_found")
+        }
+    }
+
+    /// Returns all webhooks registered on a poll
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `poll_id` - The ID of the poll
+    pub async fn get_poll_webhooks(pool: &DBPool, poll_id: i32) -> Result<Vec<Webhook>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(Webhook, "sql/webhook/get_poll_webhooks.sql", poll_id)
+            .fetch_all(pool).await,
+            "Failed to fetch poll webhooks");
+
+        Ok(res)
+    }
+
+    /// Deletes a webhook along with its delivery log
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `context` - Who is deleting the webhook, and from where
+    /// * `webhook_id` - The ID of the webhook
+    pub async fn delete_webhook(pool: &DBPool, context: &AuditContext, webhook_id: i32) -> Result<()> {
+        let before = get_webhook(pool, webhook_id).await?;
+
+        generic_service_err!(
+            sqlx::query_file!("sql/webhook/delete_webhook.sql", webhook_id)
+            .fetch_all(pool).await,
+            "Failed to delete webhook");
+
+        create_audit_event(pool, context, "delete_webhook", "webhook", webhook_id, Some(before.poll_id), Some(&before), None).await?;
+
+        Ok(())
+    }
+
+    /// Queues an event for delivery to a webhook and returns the resulting record
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `webhook` - The webhook
+    /// * `event` - The name of the event
+    /// * `data` - An object describing what happened
+    async fn queue_delivery(pool: &DBPool, webhook: &Webhook, event: &str, data: &JsonValue) -> Result<WebhookDelivery> {
+        let payload = json!({
+            "event": event,
+            "poll_id": webhook.poll_id,
+            "time": time::OffsetDateTime::now_utc().unix_timestamp(),
+            "data": data,
+        });
+
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(WebhookDelivery, "sql/webhook/create_webhook_delivery.sql", webhook.id, event, payload.to_string())
+            .fetch_all(pool).await,
+            "Failed to queue webhook delivery");
+
+        Ok(res.remove(0))
+    }
+
+    /// Queues an event on a poll for delivery to every webhook on the poll subscribed to it
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `poll_id` - The ID of the poll the event happened on
+    /// * `event` - The name of the event, one of `WEBHOOK_EVENTS`
+    /// * `data` - An object describing what happened
+    pub async fn dispatch_event(pool: &DBPool, poll_id: i32, event: &str, data: JsonValue) -> Result<()> {
+        let webhooks = generic_service_err!(
+            sqlx::query_file_as!(Webhook, "sql/webhook/get_event_webhooks.sql", poll_id, event)
+            .fetch_all(pool).await,
+            "Failed to fetch webhooks for event");
+
+        for webhook in webhooks {
+            queue_delivery(pool, &webhook, event, &data).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Attempts a delivery, scheduling a retry with exponential backoff if it fails, or giving up on it once it has failed
+    /// too many times, and returns the updated record
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `webhook` - The webhook the delivery is for
+    /// * `delivery` - The delivery
+    async fn attempt_delivery(pool: &DBPool, config: &Config, webhook: &Webhook, delivery: WebhookDelivery) -> Result<WebhookDelivery> {
+        let timestamp = time::OffsetDateTime::now_utc().unix_timestamp();
+        let request = WebhookRequest {
+            url: webhook.url.clone(),
+            event: delivery.event.clone(),
+            delivery_id: delivery.id,
+            timestamp,
+            signature: sign_payload(&webhook.secret, timestamp, &delivery.payload),
+            body: delivery.payload.clone(),
+        };
+
+        // Sending blocks, so deliver on the thread pool rather than the worker thread
+        let timeout = Duration::from_secs(config.webhooks.timeout);
+        let allow_private = config.webhooks.allow_private_networks;
+        let res = web::block(move || send_webhook(&request, timeout, allow_private)).await;
+
+        let (response_status, error) = match res {
+            Ok(status) if (200..300).contains(&status) => {
+                let mut res = generic_service_err!(
+                    sqlx::query_file_as!(WebhookDelivery, "sql/webhook/set_webhook_delivered.sql", delivery.id, status as i32)
+                    .fetch_all(pool).await,
+                    "Failed to mark webhook delivery as delivered");
+
+                return Ok(res.remove(0));
+            },
+            Ok(status) => (Some(status as i32), format!("Endpoint responded with status {}", status)),
+            Err(BlockingError::Error(e)) => (None, e.to_string()),
+            Err(BlockingError::Canceled) => (None, "Webhook delivery was canceled".to_string()),
+        };
+
+        let attempts = delivery.attempts + 1;
+        let status = if attempts >= config.webhooks.max_attempts { "failed" } else { "pending" };
+
+        let mut res = generic_service_err!(
+            sqlx::query_file_as!(WebhookDelivery, "sql/webhook/set_webhook_attempt_failed.sql",
+                delivery.id, status, response_status, error, webhook_retry_delay(attempts))
+            .fetch_all(pool).await,
+            "Failed to record failed webhook delivery attempt");
+
+        Ok(res.remove(0))
+    }
+
+    /// Attempts every queued webhook delivery that is due
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    pub async fn deliver_due_webhooks(pool: &DBPool, config: &Config) -> Result<()> {
+        let deliveries = generic_service_err!(
+            sqlx::query_file_as!(WebhookDelivery, "sql/webhook/claim_due_webhook_deliveries.sql", WEBHOOK_BATCH_SIZE, WEBHOOK_CLAIM_LEASE)
+            .fetch_all(pool).await,
+            "Failed to claim due webhook deliveries");
+
+        for delivery in deliveries {
+            // The webhook may have been deleted since the delivery was claimed, taking the delivery with it
+            if let Ok(webhook) = get_webhook(pool, delivery.webhook_id).await {
+                attempt_delivery(pool, config, &webhook, delivery).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends a test event to a webhook straight away, returning the logged delivery so its outcome can be checked, and retrying it later like any other delivery if it fails
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `config` - The configuration
+    /// * `webhook` - The webhook
+    pub async fn send_test_event(pool: &DBPool, config: &Config, webhook: &Webhook) -> Result<WebhookDelivery> {
+        let delivery = queue_delivery(pool, webhook, WEBHOOK_TEST_EVENT, &json!({})).await?;
+
+        attempt_delivery(pool, config, webhook, delivery).await
+    }
+
+    /// Returns a page of a webhook's delivery log, newest first
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    /// * `webhook_id` - The ID of the webhook
+    /// * `limit` - The maximum number of deliveries to return
+    /// * `offset` - The number of deliveries to skip
+    pub async fn get_webhook_deliveries(pool: &DBPool, webhook_id: i32, limit: i64, offset: i64) -> Result<Vec<WebhookDelivery>> {
+        let res = generic_service_err!(
+            sqlx::query_file_as!(WebhookDelivery, "sql/webhook/get_webhook_deliveries.sql", webhook_id, limit, offset)
+            .fetch_all(pool).await,
+            "Failed to fetch webhook deliveries");
+
+        Ok(res)
+    }
+
+    /// Prunes all finished webhook deliveries created more than 30 days ago
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pool` - The database pool
+    pub async fn prune_webhook_deliveries(pool: &DBPool) -> Result<()> {
+        generic_service_err!(
+            sqlx::query_file!("sql/webhook/prune_webhook_deliveries.sql")
+            .fetch_all(pool).await,
+            "Failed to prune webhook deliveries");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::webhook_service::*;
+    use crate::dbinit;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Starts an endpoint that answers each request with the given status codes in turn, returning its URL
+    fn serve(statuses: Vec<u16>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some((key, value)) if key.eq_ignore_ascii_case("content-length") => length = value.parse().unwrap(),
+                        Some(_) => (),
+                        None if line.trim_end().is_empty() => break,
+                        None => (),
+                    }
+                }
+
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                write!(stream, "HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            }
+        });
+
+        url
+    }
+
+    /// Returns the number of seconds after a delivery's last attempt that its next attempt is due
+    fn retry_delay(delivery: &WebhookDelivery) -> i64 {
+        (delivery.next_attempt_time - delivery.last_attempt_time.unwrap()).whole_seconds()
+    }
+
+    /// Makes a failed delivery due straight away, then delivers every due webhook and returns the delivery's new state
+    async fn retry_now(pool: &DBPool, config: &Config, delivery: &WebhookDelivery) -> WebhookDelivery {
+        sqlx::query("UPDATE webhook_delivery SET next_attempt_time = NOW() WHERE id = $1")
+            .bind(delivery.id)
+            .execute(pool).await.unwrap();
+        deliver_due_webhooks(pool, config).await.unwrap();
+
+        get_webhook_deliveries(pool, delivery.webhook_id, 1, 0).await.unwrap().remove(0)
+    }
+
+    #[actix_rt::test]
+    async fn retries_with_backoff_then_gives_up() {
+        let (pool, schema) = dbinit::test_pool("webhook").await;
+        let mut config = Config::default();
+        config.webhooks.timeout = 5;
+        config.webhooks.max_attempts = 3;
+
+        let (user_id,): (i32,) = sqlx::query_as("INSERT INTO app_user (username, email, password) VALUES ('webhook_test', 'webhook_test@example.com', '') RETURNING id")
+            .fetch_one(&pool).await.unwrap();
+        let (poll_id,): (i32,) = sqlx::query_as("INSERT INTO poll (user_id, title, description) VALUES ($1, 'Webhook test', '') RETURNING id")
+            .bind(user_id)
+            .fetch_one(&pool).await.unwrap();
+        let context = AuditContext::system();
+        let events = vec!["vote_cast".to_string()];
+
+        let url = serve(vec![500, 503, 500, 200]);
+
+        let err = create_webhook(&pool, &config, &context, poll_id, url.clone(), events.clone()).await.err().unwrap();
+        assert_eq!(err.to_string(), "webhook_url_not_public");
+
+        config.webhooks.allow_private_networks = true;
+        let webhook = create_webhook(&pool, &config, &context, poll_id, url, events).await.unwrap();
+
+        let delivery = send_test_event(&pool, &config, &webhook).await.unwrap();
+        assert_eq!((&delivery.status[..], delivery.attempts, delivery.response_status), ("pending", 1, Some(500)));
+        assert_eq!(delivery.last_error.as_deref(), Some("Endpoint responded with status 500"));
+        assert_eq!(retry_delay(&delivery), WEBHOOK_RETRY_BASE_DELAY);
+
+        let delivery = retry_now(&pool, &config, &delivery).await;
+        assert_eq!((&delivery.status[..], delivery.attempts, delivery.response_status), ("pending", 2, Some(503)));
+        assert_eq!(retry_delay(&delivery), WEBHOOK_RETRY_BASE_DELAY * 2);
+
+        let delivery = retry_now(&pool, &config, &delivery).await;
+        assert_eq!((&delivery.status[..], delivery.attempts, delivery.response_status), ("failed", 3, Some(500)));
+
+        // A failed delivery is never claimed again, even once it is due
+        let delivery = retry_now(&pool, &config, &delivery).await;
+        assert_eq!((&delivery.status[..], delivery.attempts), ("failed", 3));
+
+        let delivery = send_test_event(&pool, &config, &webhook).await.unwrap();
+        assert_eq!((&delivery.status[..], delivery.attempts, delivery.response_status), ("delivered", 1, Some(200)));
+        assert!(delivery.delivered_time.is_some());
+
+        dbinit::drop_test_schema(&pool, &schema).await;
+    }
+
+    #[test]
+    fn doubles_retry_delay_up_to_the_maximum() {
+        assert_eq!(webhook_retry_delay(0), WEBHOOK_RETRY_BASE_DELAY);
+        assert_eq!(webhook_retry_delay(1), WEBHOOK_RETRY_BASE_DELAY);
+        assert_eq!(webhook_retry_delay(4), WEBHOOK_RETRY_BASE_DELAY * 8);
+        assert_eq!(webhook_retry_delay(i32::MAX), WEBHOOK_RETRY_MAX_DELAY);
+    }
+}
@@ -0,0 +1,276 @@
+use std::io::{Error, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+use serde_json::{json, Value as JsonValue};
+use tera::{Tera, Context};
+use crate::emailer::EmailMessage;
+use crate::i18n;
+
+/// The email templates built into the binary, as pairs of template name and source
+const EMBEDDED_TEMPLATES: &[(&str, &str)] = &[
+    ("base.html", include_str!("../emails/base.html")),
+    ("base.txt", include_str!("../emails/base.txt")),
+    ("macros.html", include_str!("../emails/macros.html")),
+    ("verify.html", include_str!("../emails/verify.html")),
+    ("verify.txt", include_str!("../emails/verify.txt")),
+    ("verify.es.html", include_str!("../emails/verify.es.html")),
+    ("verify.es.txt", include_str!("../emails/verify.es.txt")),
+    ("password_reset.html", include_str!("../emails/password_reset.html")),
+    ("password_reset.txt", include_str!("../emails/password_reset.txt")),
+    ("password_reset.es.html", include_str!("../emails/password_reset.es.html")),
+    ("password_reset.es.txt", include_str!("../emails/password_reset.es.txt")),
+    ("email_change.html", include_str!("../emails/email_change.html")),
+    ("email_change.txt", include_str!("../emails/email_change.txt")),
+    ("email_change_notice.html", include_str!("../emails/email_change_notice.html")),
+    ("email_change_notice.txt", include_str!("../emails/email_change_notice.txt")),
+    ("lockout.html", include_str!("../emails/lockout.html")),
+    ("lockout.txt", include_str!("../emails/lockout.txt")),
+    ("notification.html", include_str!("../emails/notification.html")),
+    ("notification.txt", include_str!("../emails/notification.txt")),
+    ("digest.html", include_str!("../emails/digest.html")),
+    ("digest.txt", include_str!("../emails/digest.txt")),
+];
+
+/// The frontend URL the emails are rendered with when checking they render
+const SAMPLE_URL: &str = "https://example.com";
+
+/// The compiled email templates, set once at startup
+static TEMPLATES: OnceLock<Tera> = OnceLock::new();
+
+/// An email that can be rendered, each made up of an HTML and a text template, optionally with variants for other locales named like `verify.es.html`
+#[derive(Clone, Copy)]
+pub enum EmailTemplate {
+    Verify,
+    PasswordReset,
+    EmailChange,
+    EmailChangeNotice,
+    Lockout,
+    Notification,
+    Digest,
+}
+
+impl EmailTemplate {
+    /// Every email, checked at startup
+    pub const ALL: &'static [EmailTemplate] = &[
+        EmailTemplate::Verify,
+        EmailTemplate::PasswordReset,
+        EmailTemplate::EmailChange,
+        EmailTemplate::EmailChangeNotice,
+        EmailTemplate::Lockout,
+        EmailTemplate::Notification,
+        EmailTemplate::Digest,
+    ];
+
+    /// Returns the name of the email's templates, without the file extension
+    pub fn name(self) -> &'static str {
+        match self {
+            EmailTemplate::Verify => "verify",
+            EmailTemplate::PasswordReset => "password_reset",
+            EmailTemplate::EmailChange => "email_change",
+            EmailTemplate::EmailChangeNotice => "email_change_notice",
+            EmailTemplate::Lockout => "lockout",
+            EmailTemplate::Notification => "notification",
+            EmailTemplate::Digest => "digest",
+        }
+    }
+
+    /// Returns the ID of the email's subject line in the message catalogue
+    fn subject_id(self) -> &'static str {
+        match self {
+            EmailTemplate::Verify => "email_subject_verify",
+            EmailTemplate::PasswordReset => "email_subject_password_reset",
+            EmailTemplate::EmailChange => "email_subject_email_change",
+            EmailTemplate::EmailChangeNotice => "email_subject_email_change_notice",
+            EmailTemplate::Lockout => "email_subject_lockout",
+            EmailTemplate::Notification => "email_subject_notification",
+            EmailTemplate::Digest => "email_subject_digest",
+        }
+    }
+
+    /// Returns whether all of the email's text comes from the message catalogue, so one template serves every locale
+    fn translated_by_catalogue(self) -> bool {
+        matches!(self, EmailTemplate::Notification | EmailTemplate::Digest)
+    }
+
+    /// Returns values for every placeholder the email uses, to check that it renders
+    fn sample_context(self) -> JsonValue {
+        match self {
+            EmailTemplate::Verify => json!({ "url": SAMPLE_URL, "verify_id": "sample" }),
+            EmailTemplate::PasswordReset => json!({ "url": SAMPLE_URL, "reset_id": "sample" }),
+            EmailTemplate::EmailChange => json!({ "url": SAMPLE_URL, "change_id": "sample" }),
+            EmailTemplate::EmailChangeNotice => json!({ "url": SAMPLE_URL, "new_email": "sample@example.com" }),
+            EmailTemplate::Lockout => json!({ "url": SAMPLE_URL, "minutes": 15 }),
+            EmailTemplate::Notification => json!({ "url": SAMPLE_URL, "message": "sample", "poll_id": 1 }),
+            EmailTemplate::Digest => json!({ "url": SAMPLE_URL, "notifications": [{ "message": "sample", "poll_id": 1 }] }),
+        }
+    }
+}
+
+/// Converts a templating error into an IO error, including its causes, which hold the useful details
+/// 
+/// # Arguments
+/// 
+/// * `message` - What was being done when the error occurred
+/// * `err` - The templating error
+fn template_err(message: &str, err: tera::Error) -> Error {
+    let mut details = err.to_string();
+    let mut source = std::error::Error::source(&err);
+
+    while let Some(cause) = source {
+        details = format!("{}: {}", details, cause);
+        source = cause.source();
+    }
+
+    Error::other(format!("{}: {}", message, details))
+}
+
+/// Template function translating a message, called like `{{ t(id="email_signoff", locale=locale) }}`
+/// 
+/// # Arguments
+/// 
+/// * `args` - The `id` of the message and the `locale` to translate it into
+fn translate_function(args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    match (args.get("id").and_then(|id| id.as_str()), args.get("locale").and_then(|locale| locale.as_str())) {
+        (Some(id), Some(locale)) => Ok(tera::Value::String(i18n::translate(locale, id))),
+        _ => Err(tera::Error::msg("t requires an id and a locale")),
+    }
+}
+
+/// Returns the locale an email will be rendered in, which is the default locale if the email has no variant for the requested one
+/// and its text does not come from the message catalogue
+/// 
+/// # Arguments
+/// 
+/// * `tera` - The compiled templates
+/// * `template` - The email
+/// * `locale` - The requested locale
+fn template_locale(tera: &Tera, template: EmailTemplate, locale: &str) -> &'static str {
+    match i18n::supported_locale(locale) {
+        Some(locale) if template.translated_by_catalogue() => locale,
+        Some(locale) if tera.get_template_names().any(|name| name == format!("{}.{}.html", template.name(), locale)) => locale,
+        _ => i18n::DEFAULT_LOCALE,
+    }
+}
+
+/// Renders an email with compiled templates
+/// 
+/// # Arguments
+/// 
+/// * `tera` - The compiled templates
+/// * `template` - The email to render
+/// * `locale` - The locale to render the email in
+/// * `to` - The address the email is for
+/// * `context` - An object holding the values of the email's placeholders
+fn render_with(tera: &Tera, template: EmailTemplate, locale: &str, to: String, context: &JsonValue) -> Result<EmailMessage> {
+    let locale = template_locale(tera, template, locale);
+
+    let mut context = match Context::from_value(context.clone()) {
+        Ok(val) => Ok(val),
+        Err(e) => Err(template_err("Invalid email context", e))
+    }?;
+    context.insert("locale", locale);
+
+    let variant = format!("{}.{}", template.name(), locale);
+    let name = if tera.get_template_names().any(|name| name == format!("{}.html", variant)) {
+        variant
+    } else {
+        template.name().to_string()
+    };
+
+    let render = |extension: &str| match tera.render(&format!("{}.{}", name, extension), &context) {
+        Ok(val) => Ok(val),
+        Err(e) => Err(template_err("Failed to render email", e))
+    };
+
+    Ok(EmailMessage {
+        to,
+        subject: i18n::translate(locale, template.subject_id()),
+        html: render("html")?,
+        text: render("txt")?,
+    })
+}
+
+/// Reads the `.html` and `.txt` templates in a directory, as pairs of template name and source
+/// 
+/// # Arguments
+/// 
+/// * `dir` - The directory to read templates from
+fn read_template_dir(dir: &Path) -> Result<Vec<(String, String)>> {
+    let mut templates = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_template = path.extension().is_some_and(|extension| extension == "html" || extension == "txt");
+
+        if let (true, Some(name)) = (is_template, path.file_name().and_then(|name| name.to_str())) {
+            templates.push((name.to_string(), std::fs::read_to_string(&path)?));
+        }
+    }
+
+    Ok(templates)
+}
+
+/// Compiles the email templates and checks that every email renders in every locale, failing if any template is missing or broken
+/// 
+/// The templates are embedded in the binary, but any in the template directory replace the embedded template of the
+/// same name. Values inserted into `.html` templates are escaped.
+/// 
+/// # Arguments
+/// 
+/// * `template_dir` - The directory of templates replacing embedded ones, if any
+pub fn load_templates(template_dir: Option<&str>) -> Result<()> {
+    let mut templates: Vec<(String, String)> = EMBEDDED_TEMPLATES.iter()
+        .map(|(name, source)| (name.to_string(), source.to_string()))
+        .collect();
+
+    if let Some(dir) = template_dir {
+        for (name, source) in read_template_dir(Path::new(dir))? {
+            templates.retain(|(embedded_name, _)| *embedded_name != name);
+            templates.push((name, source));
+        }
+    }
+
+    let mut tera = Tera::default();
+    tera.register_function("t", translate_function);
+    match tera.add_raw_templates(templates) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(template_err("Failed to compile email templates", e))
+    }?;
+
+    for template in EmailTemplate::ALL {
+        for locale in i18n::supported_locales() {
+            render_with(&tera, *template, locale, String::new(), &template.sample_context())?;
+        }
+    }
+
+    match TEMPLATES.set(tera) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::other("Email templates have already been loaded")),
+    }
+}
+
+/// Renders an email in a locale, falling back to the default locale if the email has no variant for it
+/// 
+/// # Arguments
+/// 
+/// * `template` - The email to render
+/// * `locale` - The locale to render the email in
+/// * `to` - The address the email is for
+/// * `context` - An object holding the values of the email's placeholders
+/// 
+/// # Placeholder example
+/// 
+/// In the HTML and text templates, the following could be used to insert placeholder values:
+/// 
+/// `Hello, {{ firstname }} {{ lastname }}. You have received the message: {{ message }}.`
+/// 
+/// The `context` parameter could then contain values for `firstname`, `lastname`, and `message`. The `locale` placeholder is always set.
+pub fn render_email(template: EmailTemplate, locale: &str, to: String, context: &JsonValue) -> Result<EmailMessage> {
+    let tera = match TEMPLATES.get() {
+        Some(tera) => Ok(tera),
+        None => Err(Error::other("Email templates have not been loaded")),
+    }?;
+
+    render_with(tera, template, locale, to, context)
+}
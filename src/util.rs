@@ -1,17 +1,43 @@
 use serde::{Serialize, Deserialize};
-use actix_web::{HttpRequest, HttpResponse, HttpMessage, Result};
-use crate::services;
-use crate::services::User;
+use actix_web::{HttpRequest, HttpResponse, web};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header;
+use time::Duration;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Sha256, Digest};
+use std::sync::Arc;
+use crate::config::Config;
+use crate::emailer::EmailTransport;
+use crate::i18n;
+use crate::services::AuditContext;
 
-/// The URL for the frontend
-pub const FRONTEND_URL: &str = "https://greenpoll.herokuapp.com";
+/// The number of random bytes in a generated token
+const TOKEN_BYTES: usize = 32;
+
+/// The number of records returned by a paged route when the client does not ask for a number
+const DEFAULT_PAGE_SIZE: i64 = 50;
+
+/// The largest number of records a paged route returns at once
+const MAX_PAGE_SIZE: i64 = 100;
 
 /// Shortcut for the sqlx postgres pool type
 pub type DBPool = sqlx::Pool<sqlx::Postgres>;
 
-/// Container for the database pool within the actix app
+/// Container for the database pool, configuration and email transport within the actix app, shared immutably between every worker
 pub struct AppData {
     pub pool: sqlx::Pool<sqlx::Postgres>,
+    pub config: Arc<Config>,
+    pub email_transport: Arc<dyn EmailTransport>,
+}
+
+/// The session a request was authenticated with, recorded so the session cookie can be renewed on the response
+#[derive(Clone)]
+pub struct ActiveSession {
+    pub session_id: String,
+    /// The administrator the session was created for, if it is impersonating its user
+    pub impersonator_id: Option<i32>,
 }
 
 /// Success JSON message
@@ -32,7 +58,7 @@ pub struct ExistsJSON {
     pub exists: bool,
 }
 
-/// A macro for matching errors at the routing layer and returning them in JSON in a generic format
+/// A macro for matching errors at the routing layer and returning them in JSON in a generic format, translated into the request's locale
 /// 
 /// # Arguments
 /// 
@@ -43,7 +69,7 @@ macro_rules! generic_http_err {
         match $x {
             Ok(val) => Ok(val),
             Err(e) => Err(HttpResponse::Ok().json(ErrorJSON {
-                error: format!("{}", e)
+                error: $crate::i18n::localize(&format!("{}", e))
             })),
         }?
     };
@@ -84,34 +110,149 @@ pub fn success_json() -> HttpResponse {
     })
 }
 
-/// Returns an error JSON HTTP response
+/// Returns an error JSON HTTP response, translating the message into the request's locale
 /// 
 /// # Arguments
 /// 
-/// * `err` - The error message
+/// * `err` - The ID of the error message
 pub fn error_json(err: &str) -> HttpResponse {
     HttpResponse::Ok().json(ErrorJSON {
-        error: String::from(err)
+        error: i18n::localize(err)
     })
 }
 
-/// Returns the user that is logged in
+/// Returns the limit and offset to page through records with, keeping the limit a client asks for between 1 and `MAX_PAGE_SIZE` and the offset from going negative
+/// 
+/// # Arguments
+/// 
+/// * `limit` - The number of records the client asked for, if any
+/// * `offset` - The number of records the client asked to skip, if any
+pub fn page_bounds(limit: Option<i64>, offset: Option<i64>) -> (i64, i64) {
+    (limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE), offset.unwrap_or(0).max(0))
+}
+
+/// Generates a random token from the operating system's secure random number generator, encoded as hex
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+
+    hex::encode(bytes)
+}
+
+/// Returns the SHA-256 hash of a token, encoded as hex, which is what gets stored in place of the token itself
+/// 
+/// # Arguments
+/// 
+/// * `token` - The token to hash
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Returns a session cookie that lasts until the session's idle timeout
+/// 
+/// # Arguments
+/// 
+/// * `config` - The configuration
+/// * `session_id` - The ID of the session
+pub fn session_cookie(config: &Config, session_id: String) -> Cookie<'static> {
+    Cookie::build("session_id", session_id)
+        .path("/")
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::None)
+        .max_age(Duration::seconds(config.sessions.idle_timeout))
+        .finish()
+}
+
+/// Returns a cookie holding an administrator's own session while they impersonate a user, so it can be restored when they stop
+/// 
+/// # Arguments
+/// 
+/// * `config` - The configuration
+/// * `session_id` - The ID of the administrator's session
+pub fn impersonator_session_cookie(config: &Config, session_id: String) -> Cookie<'static> {
+    Cookie::build("impersonator_session_id", session_id)
+        .path("/")
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::None)
+        .max_age(Duration::seconds(config.sessions.idle_timeout))
+        .finish()
+}
+
+/// Extends the session cookie on a response to a request that was authenticated by a session, unless the handler already set the cookie itself
+/// 
+/// # Arguments
+/// 
+/// * `res` - The response to the request
+pub fn renew_session_cookie<B>(res: &mut ServiceResponse<B>) {
+    let app_data = res.request().app_data::<web::Data<AppData>>().cloned();
+    let active_session = res.request().extensions().get::<ActiveSession>().cloned();
+    let cookie_set = res.response().cookies().any(|cookie| cookie.name() == "session_id");
+
+    if let (Some(app_data), Some(active_session), false) = (app_data, active_session, cookie_set) {
+        let _ = res.response_mut().add_cookie(&session_cookie(&app_data.config, active_session.session_id));
+    }
+}
+
+/// Returns the IP address the request was made from
+/// 
+/// The address is that of the connection, unless the connection comes from a configured trusted proxy, in which case
+/// `X-Forwarded-For` is read from right to left and the first address not belonging to a trusted proxy is taken.
+/// Addresses further left were written by the client and could be anything, so they are never believed.
+/// 
+/// # Arguments
+/// 
+/// * `req` - The HTTP request object
+pub fn request_ip(req: &HttpRequest) -> Option<String> {
+    let mut ip = req.peer_addr()?.ip();
+
+    if let Some(app_data) = req.app_data::<web::Data<AppData>>() {
+        let forwarded: Vec<&str> = req.headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','))
+            .collect();
+
+        for forwarded_ip in forwarded.into_iter().rev() {
+            if !app_data.config.server.is_trusted_proxy(ip) {
+                break;
+            }
+
+            match forwarded_ip.trim().parse() {
+                Ok(forwarded_ip) => ip = forwarded_ip,
+                Err(_) => break,
+            }
+        }
+    }
+
+    Some(ip.to_string())
+}
+
+/// Returns the user agent the request was made with
 /// 
 /// # Arguments
 /// 
-/// * `pool` - The database pool
 /// * `req` - The HTTP request object
-pub async fn get_user_by_session(pool: &DBPool, req: HttpRequest) -> Result<User> {
-    let session_id = match req.cookie("session_id") {
-        Some(val) => Ok(val),
-        None => Err(HttpResponse::Ok().json(ErrorJSON {
-            error: String::from("Not logged in")
-        }))
-    }?;
-
-    let user = generic_http_err!(
-        services::session_service::get_user_by_session_id(pool, String::from(session_id.value()))
-        .await);
-
-    Ok(user)
+pub fn request_user_agent(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .map(|user_agent| user_agent.chars().take(255).collect())
+}
+
+/// Returns the audit context for a request, recording the administrator behind it if its session is impersonating the user
+/// 
+/// # Arguments
+/// 
+/// * `req` - The HTTP request object
+/// * `actor_id` - The ID of the user making the request, if logged in
+pub fn audit_context(req: &HttpRequest, actor_id: Option<i32>) -> AuditContext {
+    let impersonator_id = req.extensions().get::<ActiveSession>().and_then(|active_session| active_session.impersonator_id);
+
+    AuditContext {
+        actor_id,
+        impersonator_id: actor_id.and(impersonator_id),
+        ip_address: request_ip(req),
+    }
 }
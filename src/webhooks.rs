@@ -0,0 +1,373 @@
+use hmac::{Hmac, Mac, NewMac};
+use native_tls::TlsConnector;
+use sha2::Sha256;
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// The user agent webhooks are sent with
+const WEBHOOK_USER_AGENT: &str = "GreenPoll-Webhooks";
+
+/// The most redirects followed when sending a webhook
+const WEBHOOK_MAX_REDIRECTS: usize = 5;
+
+/// A signed webhook request ready to be sent
+pub struct WebhookRequest {
+    pub url: String,
+    pub event: String,
+    pub delivery_id: i32,
+    pub timestamp: i64,
+    pub signature: String,
+    pub body: String,
+}
+
+/// Parses a webhook URL, which must be an absolute `http` or `https` URL with a host
+/// 
+/// # Arguments
+/// 
+/// * `url` - The URL
+pub fn parse_webhook_url(url: &str) -> Option<Url> {
+    match Url::parse(url) {
+        Ok(url) if (url.scheme() == "http" || url.scheme() == "https") && url.host_str().is_some() => Some(url),
+        _ => None,
+    }
+}
+
+/// Signs a webhook payload, returning the value of the `X-GreenPoll-Signature` header
+/// 
+/// The signature is the hex-encoded HMAC-SHA256, keyed with the webhook's secret, of the timestamp sent in the
+/// `X-GreenPoll-Timestamp` header, a full stop, and the request body, so receivers can reject replayed requests.
+/// 
+/// # Arguments
+/// 
+/// * `secret` - The webhook's secret
+/// * `timestamp` - The time the request is sent, in seconds since the Unix epoch
+/// * `body` - The request body
+pub fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Returns whether an address is reachable from the public internet, rather than being loopback, private, link-local,
+/// unique-local or otherwise reserved for a local network, so a webhook may be sent to it
+/// 
+/// # Arguments
+/// 
+/// * `ip` - The address
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            let shared = octets[0] == 100 && (octets[1] & 0xc0) == 64;
+
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()
+                || ip.is_multicast() || ip.is_documentation() || octets[0] == 0 || shared)
+        },
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                let unique_local = (ip.segments()[0] & 0xfe00) == 0xfc00;
+                let link_local = (ip.segments()[0] & 0xffc0) == 0xfe80;
+
+                !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local)
+            },
+        },
+    }
+}
+
+/// Resolves a host and port, returning its addresses only if every one of them is public
+/// 
+/// Requests are made to the returned addresses rather than resolving the host again, so a host cannot pass the check
+/// and then resolve to a private address when connected to.
+/// 
+/// # Arguments
+/// 
+/// * `netloc` - The host and port, like `example.com:443` or `[2001:db8::1]:80`
+/// * `allow_private` - Whether to allow addresses on private networks, for development
+pub fn resolve_public_addrs(netloc: &str, allow_private: bool) -> Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = netloc.to_socket_addrs()?.collect();
+
+    if addrs.is_empty() {
+        Err(Error::new(ErrorKind::NotFound, format!("Failed to resolve {}", netloc)))
+    } else if !allow_private && addrs.iter().any(|addr| !is_public_ip(addr.ip())) {
+        Err(Error::new(ErrorKind::PermissionDenied, format!("{} resolves to an address that is not public", netloc)))
+    } else {
+        Ok(addrs)
+    }
+}
+
+/// Checks that a webhook URL's host resolves only to public addresses, returning an error if not
+/// 
+/// # Arguments
+/// 
+/// * `url` - The URL
+/// * `allow_private` - Whether to allow addresses on private networks, for development
+pub fn check_webhook_host(url: &Url, allow_private: bool) -> Result<()> {
+    let host = url.host_str().unwrap_or("");
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    resolve_public_addrs(&format!("{}:{}", host, port), allow_private).map(|_| ())
+}
+
+/// Posts a webhook request and returns the status code of the response, blocking until the endpoint responds or times out
+/// 
+/// Every address connected to, including those of redirects, is checked to be public first. Redirects are followed by
+/// posting the request again to the new location, so the payload and signature are never dropped along the way.
+/// 
+/// # Arguments
+/// 
+/// * `request` - The webhook request
+/// * `timeout` - How long to wait for the endpoint to connect and respond
+/// * `allow_private` - Whether to allow sending to addresses on private networks, for development
+pub fn send_webhook(request: &WebhookRequest, timeout: Duration, allow_private: bool) -> Result<u16> {
+    post_webhook(request, timeout, move |netloc: &str| resolve_public_addrs(netloc, allow_private))
+}
+
+/// Posts a webhook request, connecting only to the addresses a resolver returns, and returns the status code of the response
+/// 
+/// # Arguments
+/// 
+/// * `request` - The webhook request
+/// * `timeout` - How long to wait for the endpoint to connect and respond
+/// * `resolver` - Resolves the host and port of each request, and refuses those that may not be connected to
+fn post_webhook(request: &WebhookRequest, timeout: Duration, resolver: impl ureq::Resolver + 'static) -> Result<u16> {
+    let tls_connector = match TlsConnector::new() {
+        Ok(val) => Ok(val),
+        Err(e) => Err(Error::other(format!("Failed to create TLS connector: {}", e)))
+    }?;
+    let agent = ureq::AgentBuilder::new()
+        .timeout(timeout)
+        .redirects(0)
+        .user_agent(WEBHOOK_USER_AGENT)
+        .tls_connector(Arc::new(tls_connector))
+        .resolver(resolver)
+        .build();
+
+    let mut url = match parse_webhook_url(&request.url) {
+        Some(url) => Ok(url),
+        None => Err(Error::new(ErrorKind::InvalidInput, "Invalid webhook URL")),
+    }?;
+
+    for _ in 0..=WEBHOOK_MAX_REDIRECTS {
+        let res = agent.post(url.as_str())
+            .set("Content-Type", "application/json")
+            .set("X-GreenPoll-Event", &request.event)
+            .set("X-GreenPoll-Delivery", &request.delivery_id.to_string())
+            .set("X-GreenPoll-Timestamp", &request.timestamp.to_string())
+            .set("X-GreenPoll-Signature", &request.signature)
+            .send_string(&request.body);
+
+        let response = match res {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, _)) => return Ok(status),
+            Err(e) => return Err(Error::other(e.to_string())),
+        };
+
+        let location = match response.header("Location") {
+            Some(location) if (300..400).contains(&response.status()) => location,
+            _ => return Ok(response.status()),
+        };
+
+        url = match url.join(location).ok().and_then(|location| parse_webhook_url(location.as_str())) {
+            Some(location) => Ok(location),
+            None => Err(Error::new(ErrorKind::InvalidData, format!("Invalid redirect to {:?}", location))),
+        }?;
+    }
+
+    Err(Error::other(format!("Too many redirects, the last to {}", url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::thread;
+
+    /// A request received by a test endpoint
+    struct ReceivedRequest {
+        request_line: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    impl ReceivedRequest {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| &value[..])
+        }
+    }
+
+    /// Starts an endpoint that answers one request with each of the given raw responses in turn, returning its port and
+    /// the requests it receives
+    fn serve(address: &str, responses: Vec<&'static str>) -> (u16, Receiver<ReceivedRequest>) {
+        let listener = TcpListener::bind(address).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = channel();
+
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some((key, value)) => headers.push((key.to_string(), value.to_string())),
+                        None => break,
+                    }
+                }
+
+                let length = headers.iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+                    .map_or(0, |(_, value)| value.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                stream.write_all(response.as_bytes()).unwrap();
+                sender.send(ReceivedRequest {
+                    request_line: request_line.trim_end().to_string(),
+                    headers,
+                    body: String::from_utf8(body).unwrap(),
+                }).unwrap();
+            }
+        });
+
+        (port, receiver)
+    }
+
+    fn test_request(url: String) -> WebhookRequest {
+        let body = r#"{"event":"test","poll_id":1,"time":1600000000,"data":{}}"#.to_string();
+
+        WebhookRequest {
+            url,
+            event: "test".to_string(),
+            delivery_id: 7,
+            timestamp: 1600000000,
+            signature: sign_payload("secret", 1600000000, &body),
+            body,
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn sends_signed_request() {
+        let (port, requests) = serve("127.0.0.1:0", vec!["HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n"]);
+        let request = test_request(format!("http://127.0.0.1:{}/hook?key=value", port));
+
+        assert_eq!(send_webhook(&request, TIMEOUT, true).unwrap(), 204);
+
+        let received = requests.recv().unwrap();
+        assert_eq!(received.request_line, "POST /hook?key=value HTTP/1.1");
+        assert_eq!(received.header("User-Agent"), Some(WEBHOOK_USER_AGENT));
+        assert_eq!(received.header("Content-Type"), Some("application/json"));
+        assert_eq!(received.header("X-GreenPoll-Event"), Some("test"));
+        assert_eq!(received.header("X-GreenPoll-Delivery"), Some("7"));
+        assert_eq!(received.header("X-GreenPoll-Timestamp"), Some("1600000000"));
+        assert_eq!(received.header("X-GreenPoll-Signature"), Some(&sign_payload("secret", 1600000000, &received.body)[..]));
+        assert_eq!(received.body, request.body);
+    }
+
+    #[test]
+    fn reads_chunked_and_error_responses() {
+        let (port, _requests) = serve("127.0.0.1:0", vec![
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n4\r\nokay\r\n0\r\n\r\n",
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 5\r\nConnection: close\r\n\r\noops\n",
+        ]);
+        let request = test_request(format!("http://127.0.0.1:{}/", port));
+
+        assert_eq!(send_webhook(&request, TIMEOUT, true).unwrap(), 200);
+        assert_eq!(send_webhook(&request, TIMEOUT, true).unwrap(), 500);
+    }
+
+    #[test]
+    fn sends_to_ipv6_literals() {
+        let listener = match TcpListener::bind("[::1]:0") {
+            Ok(listener) => listener,
+            // IPv6 is not available everywhere tests are run
+            Err(_) => return,
+        };
+        drop(listener);
+
+        let (port, requests) = serve("[::1]:0", vec!["HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"]);
+        let request = test_request(format!("http://[::1]:{}/", port));
+
+        assert_eq!(send_webhook(&request, TIMEOUT, true).unwrap(), 200);
+        assert_eq!(requests.recv().unwrap().header("Host"), Some(&format!("[::1]:{}", port)[..]));
+    }
+
+    #[test]
+    fn follows_redirects_with_the_same_request() {
+        let (port, requests) = serve("127.0.0.1:0", vec![
+            "HTTP/1.1 302 Found\r\nLocation: /moved\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ]);
+        let request = test_request(format!("http://127.0.0.1:{}/", port));
+
+        assert_eq!(send_webhook(&request, TIMEOUT, true).unwrap(), 200);
+
+        let first = requests.recv().unwrap();
+        let second = requests.recv().unwrap();
+        assert_eq!(first.request_line, "POST / HTTP/1.1");
+        assert_eq!(second.request_line, "POST /moved HTTP/1.1");
+        assert_eq!(second.header("X-GreenPoll-Signature"), Some(&request.signature[..]));
+        assert_eq!(second.body, request.body);
+    }
+
+    #[test]
+    fn refuses_private_addresses() {
+        let (port, requests) = serve("127.0.0.1:0", vec!["HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"]);
+
+        for url in &[format!("http://127.0.0.1:{}/", port), format!("http://localhost:{}/", port), format!("http://[::1]:{}/", port)] {
+            assert!(send_webhook(&test_request(url.clone()), TIMEOUT, false).is_err(), "{} was not refused", url);
+            assert!(check_webhook_host(&parse_webhook_url(url).unwrap(), false).is_err(), "{} was not refused", url);
+        }
+
+        assert!(requests.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn refuses_redirects_to_private_addresses() {
+        let (port, requests) = serve("127.0.0.1:0", vec![
+            "HTTP/1.1 307 Temporary Redirect\r\nLocation: http://10.0.0.1/\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ]);
+        let endpoint = format!("127.0.0.1:{}", port);
+        let request = test_request(format!("http://{}/", endpoint));
+
+        // Only the test endpoint is let through, so the redirect is checked like any other address
+        let res = post_webhook(&request, TIMEOUT, move |netloc: &str| {
+            if netloc == endpoint {
+                Ok(netloc.to_socket_addrs()?.collect())
+            } else {
+                resolve_public_addrs(netloc, false)
+            }
+        });
+
+        assert!(res.unwrap_err().to_string().contains("10.0.0.1:80 resolves to an address that is not public"));
+        assert!(requests.recv().is_ok());
+    }
+
+    #[test]
+    fn classifies_addresses() {
+        for ip in &["93.184.216.34", "8.8.8.8", "2606:4700:4700::1111"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{} should be public", ip);
+        }
+
+        for ip in &[
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+            "255.255.255.255", "224.0.0.1", "::1", "::", "fc00::1", "fd12:3456::1", "fe80::1", "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{} should not be public", ip);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Starts the API and checks that it keeps answering every request as the number of concurrent clients grows, printing
+//! the throughput at each level.
+//!
+//! The API is run against the database named by `DATABASE_URL`, the same one the build checks queries against. To see
+//! the throughput, run:
+//!
+//! ```text
+//! cargo test --release --test load_test -- --nocapture
+//! ```
+
+use std::io::{BufRead, BufReader, Read, Result, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The route loaded, which checks out a database connection for every request
+const PATH: &str = "/readyz";
+
+/// The number of requests made at each level of concurrency
+const REQUESTS: usize = 500;
+
+/// The levels of concurrency tested
+const CONCURRENCY: &[usize] = &[1, 8, 32];
+
+/// The longest the API may take to start, including migrating the database
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The API running in a child process, which is killed when this is dropped
+struct Server {
+    process: Child,
+    port: u16,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Returns a port that nothing is listening on
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("Failed to find a free port")
+}
+
+/// Starts the API with a rate limit high enough not to get in the way, and waits until it answers requests
+fn start_server() -> Server {
+    let port = free_port();
+    let process = Command::new(env!("CARGO_BIN_EXE_greenpoll-api"))
+        .env("CONFIG_FILE", concat!(env!("CARGO_MANIFEST_DIR"), "/config.example.toml"))
+        .env("PORT", port.to_string())
+        .env("EMAIL_TRANSPORT", "memory")
+        .env("RATE_LIMIT_BURST", "1000000")
+        .env("RATE_LIMIT_PER_MINUTE", "100000000")
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start the API");
+    let mut server = Server { process, port };
+    let start = Instant::now();
+
+    while request(port, "/healthz").is_err() {
+        if let Ok(Some(status)) = server.process.try_wait() {
+            panic!("The API exited while starting: {}", status);
+        }
+
+        assert!(start.elapsed() < STARTUP_TIMEOUT, "The API did not start within {:?}", STARTUP_TIMEOUT);
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    server
+}
+
+/// Makes a single GET request and returns the status code of the response, after reading the whole response
+///
+/// # Arguments
+///
+/// * `port` - The port the API is listening on
+/// * `path` - The path to request
+fn request(port: u16, path: &str) -> Result<u16> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    write!(stream, "GET {} HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nConnection: close\r\n\r\n", path, port)?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    reader.read_to_end(&mut Vec::new())?;
+
+    Ok(status_line.split_whitespace().nth(1).and_then(|status| status.parse().ok()).unwrap_or(0))
+}
+
+/// Makes `REQUESTS` requests from a number of clients at once, returning the status code of each and how long they took in total
+///
+/// # Arguments
+///
+/// * `port` - The port the API is listening on
+/// * `concurrency` - The number of clients making requests at once
+fn run_level(port: u16, concurrency: usize) -> (Vec<Option<u16>>, Duration) {
+    let next_request = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+
+    let clients: Vec<_> = (0..concurrency).map(|_| {
+        let next_request = next_request.clone();
+
+        thread::spawn(move || {
+            let mut statuses = Vec::new();
+
+            while next_request.fetch_add(1, Ordering::SeqCst) < REQUESTS {
+                statuses.push(request(port, PATH).ok());
+            }
+
+            statuses
+        })
+    }).collect();
+
+    let statuses = clients.into_iter().flat_map(|client| client.join().unwrap()).collect();
+
+    (statuses, start.elapsed())
+}
+
+#[test]
+fn serves_concurrent_clients() {
+    let server = start_server();
+
+    println!("{:>11} {:>10} {:>8} {:>10}", "concurrency", "requests", "failed", "req/s");
+
+    for &concurrency in CONCURRENCY.iter() {
+        let (statuses, elapsed) = run_level(server.port, concurrency);
+        let failed = statuses.iter().filter(|status| **status != Some(200)).count();
+
+        println!("{:>11} {:>10} {:>8} {:>10.0}", concurrency, statuses.len(), failed, statuses.len() as f64 / elapsed.as_secs_f64());
+
+        assert_eq!(statuses.len(), REQUESTS);
+        assert_eq!(failed, 0, "{} of {} requests from {} concurrent clients failed", failed, REQUESTS, concurrency);
+    }
+}